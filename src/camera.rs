@@ -5,33 +5,45 @@
 //! from screen size.
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_41.html
 
-use crate::{xy_idx, Fort, Map, PlayerOrder, Position, Renderable, TileType};
+use crate::gui::layout::Layout;
+use crate::{Deposit, FogOfWar, Fort, Map, Player, Position, Renderable, TileType};
 use bracket_lib::prelude::*;
 use specs::prelude::*;
 
 const SHOW_BOUNDARIES: bool = true;
 
+/// Draws the part of the map around the cursor into the map view as the player whose turn it
+/// is knows it, enemy forts outside of what the player can see are drawn where they were
+/// last seen
 pub fn render_camera(ecs: &World, ctx: &mut BTerm) {
     let map = ecs.fetch::<Map>();
     let player_pos = ecs.fetch::<Point>();
     let positions = ecs.read_storage::<Position>();
     let renderables = ecs.read_storage::<Renderable>();
-    let (x_chars, y_chars) = ctx.get_char_size();
+    let players = ecs.read_storage::<Player>();
+    let forts = ecs.read_storage::<Fort>();
+    let fogs = ecs.read_storage::<FogOfWar>();
+    let entities = ecs.entities();
+    let player_entity = ecs.fetch::<Entity>();
+    let viewport = Layout::of(ctx).viewport;
 
-    let center_x = (x_chars / 2) as i32;
-    let center_y = (y_chars / 2) as i32;
+    let fog = match fogs.get(*player_entity) {
+        Some(fog) => fog,
+        None => return,
+    };
+    let active_order = players.get(*player_entity).map(|player| player.order);
 
-    let min_x = player_pos.x - center_x;
-    let max_x = min_x + x_chars as i32;
-    let min_y = player_pos.y - center_y;
-    let max_y = min_y + y_chars as i32;
+    let view = view_area(*player_pos, viewport);
+    let min_x = view.x1;
+    let min_y = view.y1;
 
-    for (y, ty) in (min_y..max_y).enumerate() {
-        for (x, tx) in (min_x..max_x).enumerate() {
+    for (y, ty) in (view.y1..view.y2).enumerate() {
+        for (x, tx) in (view.x1..view.x2).enumerate() {
+            let (x, y) = (viewport.x1 + x as i32, viewport.y1 + y as i32);
             if tx >= 0 && tx < map.width && ty >= 0 && ty < map.height {
-                let idx = xy_idx(tx, ty);
-                if map.revealed_tiles[idx] {
-                    let (glyph, fg, bg) = get_tile_glyph(idx, &*map);
+                let idx = map.xy_idx(tx, ty);
+                if fog.revealed_tiles[idx] {
+                    let (glyph, fg, bg) = get_tile_glyph(idx, &map, Some(fog));
                     ctx.set(x, y, fg, bg, glyph);
                 }
             } else if SHOW_BOUNDARIES {
@@ -40,77 +52,123 @@ pub fn render_camera(ecs: &World, ctx: &mut BTerm) {
         }
     }
 
-    let mut data = (&positions, &renderables).join().collect::<Vec<_>>();
-    data.sort_by(|&a, &b| b.1.render_order.cmp(&a.1.render_order));
-    for (pos, render) in data.iter() {
-        let idx = xy_idx(pos.x, pos.y);
-        if map.visible_tiles[idx] {
-            let entity_screen_x = pos.x - min_x;
-            let entity_screen_y = pos.y - min_y;
-
-            if entity_screen_x >= 0
-                && entity_screen_x < map.width
-                && entity_screen_y >= 0
-                && entity_screen_y < map.height
-            {
-                ctx.set(
-                    entity_screen_x,
-                    entity_screen_y,
-                    render.fg,
-                    render.bg,
-                    render.glyph,
-                );
-            }
+    let mut draw_entity = |pos: &Position, render: &Renderable| {
+        let entity_screen_x = viewport.x1 + pos.x - min_x;
+        let entity_screen_y = viewport.y1 + pos.y - min_y;
+
+        if viewport.point_in_rect(Point::new(entity_screen_x, entity_screen_y)) {
+            ctx.set(
+                entity_screen_x,
+                entity_screen_y,
+                render.fg,
+                render.bg,
+                render.glyph,
+            );
         }
-        if map.revealed_tiles[idx] {
-            // Used for rendering forts in revealed tiles
-            let entities = ecs.entities();
-            let forts = ecs.read_storage::<Fort>();
-            for (_fort, _entity) in (&forts, &entities).join() {
-                let entity_screen_x = pos.x - min_x;
-                let entity_screen_y = pos.y - min_y;
-
-                if entity_screen_x >= 0
-                    && entity_screen_x < map.width
-                    && entity_screen_y >= 0
-                    && entity_screen_y < map.height
-                {
-                    ctx.set(
-                        entity_screen_x,
-                        entity_screen_y,
-                        render.fg,
-                        render.bg,
-                        render.glyph,
-                    );
-                }
+    };
+
+    // Enemy forts are drawn from what the player remembers of them, which is kept up
+    // to date by the visibility system while they're in view
+    for snapshot in fog.seen_forts.iter() {
+        draw_entity(&snapshot.pos, &snapshot.render);
+    }
+
+    // Other players' cursors are never drawn, the player's own forts are always drawn, and
+    // everything else is only drawn while one of the player's units can see it
+    let mut data = (&entities, &positions, &renderables)
+        .join()
+        .filter(|(entity, pos, _)| {
+            if players.get(*entity).is_some() {
+                return *entity == *player_entity;
             }
-        }
+            match forts.get(*entity) {
+                Some(fort) => Some(fort.owner) == active_order,
+                None => fog.visible_tiles[map.xy_idx(pos.x, pos.y)],
+            }
+        })
+        .map(|(_, pos, render)| (pos, render))
+        .collect::<Vec<_>>();
+    data.sort_by_key(|&(_, render)| std::cmp::Reverse(render.render_order));
+    for (pos, render) in data.iter() {
+        draw_entity(pos, render);
+    }
+}
+
+/// Gets the tiles shown in the given map view when it's centered on the given spot
+pub fn view_area(center: Point, viewport: Rect) -> Rect {
+    Rect::with_size(
+        center.x - viewport.width() / 2,
+        center.y - viewport.height() / 2,
+        viewport.width(),
+        viewport.height(),
+    )
+}
+
+/// Gets the map tile drawn at the given spot on the screen, spots outside of the map view
+/// or past the edges of the map don't have a tile
+pub fn screen_to_tile(ecs: &World, ctx: &BTerm, screen: Point) -> Option<Position> {
+    let viewport = Layout::of(ctx).viewport;
+    if !viewport.point_in_rect(screen) {
+        return None;
+    }
+
+    let view = view_area(*ecs.fetch::<Point>(), viewport);
+    let tile = Point::new(
+        view.x1 + screen.x - viewport.x1,
+        view.y1 + screen.y - viewport.y1,
+    );
+    if ecs.fetch::<Map>().in_bounds(tile) {
+        Some(Position { x: tile.x, y: tile.y })
+    } else {
+        None
     }
 }
 
-fn get_tile_glyph(idx: usize, map: &Map) -> (FontCharType, RGB, RGB) {
-    let glyph;
+/// Gets the glyph and colours a tile gets drawn with. Tiles the player can't currently see
+/// are greyed out, and without a fog of war the whole map is drawn as if it was in sight.
+pub fn get_tile_glyph(
+    idx: usize,
+    map: &Map,
+    fog: Option<&FogOfWar>,
+) -> (FontCharType, RGB, RGB) {
+    let mut glyph;
     let mut fg;
 
-    let bg = match map.claimed_tiles[idx] {
-        PlayerOrder::NoPlayer => RGB::named(BLACK),
-        PlayerOrder::PlayerOne => RGB::named(PINK),
-        PlayerOrder::PlayerTwo => RGB::named(RED),
-    };
+    let bg = map.claimed_tiles[idx].claim_color();
 
     match map.tiles[idx] {
         TileType::Mountain => {
             fg = RGB::named(GREY);
             glyph = to_cp437('A');
         }
+        TileType::Hills => {
+            fg = RGB::named(PERU);
+            glyph = to_cp437('n');
+        }
         TileType::Forest => {
             fg = RGB::named(DARKGREEN);
             glyph = to_cp437('t');
         }
+        TileType::Jungle => {
+            fg = RGB::named(LIMEGREEN);
+            glyph = to_cp437('T');
+        }
         TileType::Grasslands => {
             fg = RGB::named(GREEN);
             glyph = to_cp437('w');
         }
+        TileType::Desert => {
+            fg = RGB::named(SANDYBROWN);
+            glyph = to_cp437('.');
+        }
+        TileType::Tundra => {
+            fg = RGB::named(LIGHTSLATEGRAY);
+            glyph = to_cp437(',');
+        }
+        TileType::Snow => {
+            fg = RGB::named(SNOW);
+            glyph = to_cp437(':');
+        }
         TileType::Coast => {
             fg = RGB::named(YELLOW);
             glyph = to_cp437('s');
@@ -124,7 +182,35 @@ fn get_tile_glyph(idx: usize, map: &Map) -> (FontCharType, RGB, RGB) {
             glyph = to_cp437('#');
         }
     }
-    if !map.visible_tiles[idx] {
+
+    // Rivers are drawn over the terrain they run through
+    if map.rivers[idx] {
+        fg = RGB::named(DODGERBLUE);
+        glyph = to_cp437('≈');
+    }
+
+    // Deposits are drawn over the terrain they're found on
+    match map.deposits[idx] {
+        Some(Deposit::Iron) => {
+            fg = RGB::named(LIGHTSTEELBLUE);
+            glyph = to_cp437('*');
+        }
+        Some(Deposit::Horses) => {
+            fg = RGB::named(TAN);
+            glyph = to_cp437('h');
+        }
+        Some(Deposit::Fish) => {
+            fg = RGB::named(CYAN);
+            glyph = to_cp437('f');
+        }
+        Some(Deposit::Stone) => {
+            fg = RGB::named(LIGHTGRAY);
+            glyph = to_cp437('o');
+        }
+        None => {}
+    }
+
+    if fog.map(|fog| !fog.visible_tiles[idx]).unwrap_or(false) {
         fg = fg.to_greyscale()
     }
 
@@ -0,0 +1,395 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Handles units attacking each other when a moving unit bumps into a unit
+//! owned by another player. The idea of attacking by moving into an enemy
+//! comes from section 2.6: Dealing Damage of the Rust roguelike tutorial
+//! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_7.html
+
+use crate::{
+    FailedMoveReason, Fort, Map, Moving, Name, Player, PlayerOrder, Position, Unit,
+};
+use bracket_lib::prelude::*;
+use specs::prelude::*;
+
+/// What happened when one unit attacked another
+pub struct CombatResult {
+    pub attacker: String,
+    pub attacker_owner: PlayerOrder,
+    pub defender: String,
+    pub defender_owner: PlayerOrder,
+    pub damage: u8,
+    pub defender_killed: bool,
+    /// Where the defender was standing
+    pub position: Position,
+}
+
+/// What a moving unit would attack by stepping onto a tile
+pub enum Target {
+    Unit(Entity),
+    Fort(Entity),
+}
+
+/// What happened when a unit attacked a fort
+pub struct SiegeResult {
+    pub attacker: String,
+    pub attacker_owner: PlayerOrder,
+    pub fort: String,
+    pub fort_owner: PlayerOrder,
+    pub damage: u8,
+    pub captured: bool,
+    /// Where the fort is
+    pub position: Position,
+}
+
+/// Looks for a unit or fort owned by someone else on the tile the moving unit is trying to
+/// step on, returns the moving unit along with what it would attack. Units standing in a
+/// fort have to be beaten before the fort itself can be attacked.
+pub fn find_target(ecs: &World, delta_x: i32, delta_y: i32) -> Option<(Entity, Target)> {
+    let entities = ecs.entities();
+    let units = ecs.read_storage::<Unit>();
+    let forts = ecs.read_storage::<Fort>();
+    let positions = ecs.read_storage::<Position>();
+    let moving = ecs.read_storage::<Moving>();
+    let map = ecs.fetch::<Map>();
+
+    let (attacker, unit, pos, _moving) = (&entities, &units, &positions, &moving).join().next()?;
+    if !map.in_bounds(Point::new(pos.x + delta_x, pos.y + delta_y)) {
+        return None;
+    }
+    let destination_idx = map.xy_idx(pos.x + delta_x, pos.y + delta_y);
+    let content = &map.tile_content[destination_idx];
+
+    let enemy_unit = content.iter().find(|entity| {
+        units
+            .get(**entity)
+            .map(|target| target.owner != unit.owner)
+            .unwrap_or(false)
+    });
+    if let Some(defender) = enemy_unit {
+        return Some((attacker, Target::Unit(*defender)));
+    }
+
+    content
+        .iter()
+        .find(|entity| {
+            forts
+                .get(**entity)
+                .map(|fort| fort.owner != unit.owner)
+                .unwrap_or(false)
+        })
+        .map(|fort| (attacker, Target::Fort(*fort)))
+}
+
+/// Has the attacker hit the defender, with the damage being rolled from the attacker's strength
+/// and cut down by the defense bonus of the tile the defender is standing on. A defender that
+/// runs out of health is removed from the world.
+pub fn attack(
+    ecs: &mut World,
+    attacker: Entity,
+    defender: Entity,
+) -> Result<CombatResult, FailedMoveReason> {
+    let result;
+    {
+        let mut units = ecs.write_storage::<Unit>();
+        let positions = ecs.read_storage::<Position>();
+        let names = ecs.read_storage::<Name>();
+        let map = ecs.fetch::<Map>();
+        let mut rng = ecs.fetch_mut::<RandomNumberGenerator>();
+
+        let (strength, attacker_owner) = match units.get_mut(attacker) {
+            None => return Err(FailedMoveReason::UnableToGrabEntity),
+            Some(unit) if unit.stamina == 0 => return Err(FailedMoveReason::UnitOutOfMoves),
+            Some(unit) => {
+                unit.stamina -= 1;
+                (unit.strength as i32, unit.owner)
+            }
+        };
+        let defender_pos = *positions
+            .get(defender)
+            .ok_or(FailedMoveReason::UnableToGrabEntity)?;
+        let defense_bonus = map.tiles[map.xy_idx(defender_pos.x, defender_pos.y)].defense_bonus();
+
+        // Rolls somewhere between half and all of the attacker's strength
+        let roll = strength / 2 + rng.roll_dice(1, strength - strength / 2);
+        let damage = (roll * 100 / (100 + defense_bonus)).max(1) as u8;
+
+        let target = units
+            .get_mut(defender)
+            .ok_or(FailedMoveReason::UnableToGrabEntity)?;
+        target.health = target.health.saturating_sub(damage);
+
+        let name_of = |entity: Entity| {
+            names
+                .get(entity)
+                .map(|name| name.name.clone())
+                .unwrap_or_default()
+        };
+        result = CombatResult {
+            attacker: name_of(attacker),
+            attacker_owner,
+            defender: name_of(defender),
+            defender_owner: target.owner,
+            damage,
+            defender_killed: target.health == 0,
+            position: defender_pos,
+        };
+    }
+
+    if result.defender_killed {
+        remove_unit(ecs, defender, result.defender_owner);
+    }
+
+    Ok(result)
+}
+
+/// Has the attacker hit an enemy fort, with the fort's defense soaking up the damage. Once the
+/// fort's defense hits zero the attacker's owner captures the fort along with the tiles around it.
+pub fn attack_fort(
+    ecs: &mut World,
+    attacker: Entity,
+    target: Entity,
+) -> Result<SiegeResult, FailedMoveReason> {
+    let result;
+    let fort_pos;
+    {
+        let mut units = ecs.write_storage::<Unit>();
+        let mut forts = ecs.write_storage::<Fort>();
+        let positions = ecs.read_storage::<Position>();
+        let names = ecs.read_storage::<Name>();
+        let mut rng = ecs.fetch_mut::<RandomNumberGenerator>();
+
+        let (strength, attacker_owner) = match units.get_mut(attacker) {
+            None => return Err(FailedMoveReason::UnableToGrabEntity),
+            Some(unit) if unit.stamina == 0 => return Err(FailedMoveReason::UnitOutOfMoves),
+            Some(unit) => {
+                unit.stamina -= 1;
+                (unit.strength as i32, unit.owner)
+            }
+        };
+        fort_pos = *positions
+            .get(target)
+            .ok_or(FailedMoveReason::UnableToGrabEntity)?;
+        let fort = forts
+            .get_mut(target)
+            .ok_or(FailedMoveReason::UnableToGrabEntity)?;
+
+        // Rolls somewhere between half and all of the attacker's strength
+        let damage = (strength / 2 + rng.roll_dice(1, strength - strength / 2)).max(1) as u8;
+        fort.defense = fort.defense.saturating_sub(damage);
+
+        let name_of = |entity: Entity| {
+            names
+                .get(entity)
+                .map(|name| name.name.clone())
+                .unwrap_or_default()
+        };
+        result = SiegeResult {
+            attacker: name_of(attacker),
+            attacker_owner,
+            fort: name_of(target),
+            fort_owner: fort.owner,
+            damage,
+            captured: fort.defense == 0,
+            position: fort_pos,
+        };
+    }
+
+    if result.captured {
+        capture_fort(
+            ecs,
+            target,
+            fort_pos,
+            result.fort_owner,
+            result.attacker_owner,
+        );
+    }
+
+    Ok(result)
+}
+
+/// Hands a fort and the 3x3 area of tiles around it over to a new owner, and moves the fort
+/// over between the two players' fort counts. Tiles holding another fort are left alone, so
+/// a fort next to the captured one keeps standing on its owner's claim.
+fn capture_fort(
+    ecs: &mut World,
+    target: Entity,
+    pos: Position,
+    old_owner: PlayerOrder,
+    new_owner: PlayerOrder,
+) {
+    let entities = ecs.entities();
+    let mut forts = ecs.write_storage::<Fort>();
+    let positions = ecs.read_storage::<Position>();
+    let mut players = ecs.write_storage::<Player>();
+    let mut map = ecs.fetch_mut::<Map>();
+
+    let other_forts: Vec<(i32, i32)> = (&entities, &forts, &positions)
+        .join()
+        .filter(|(entity, _fort, _pos)| *entity != target)
+        .map(|(_entity, _fort, pos)| (pos.x, pos.y))
+        .collect();
+
+    // Whatever the old owner was building is lost along with the fort
+    if let Some(fort) = forts.get_mut(target) {
+        fort.owner = new_owner;
+        fort.queue.clear();
+        fort.progress = 0;
+    }
+
+    for x in pos.x - 1..=pos.x + 1 {
+        for y in pos.y - 1..=pos.y + 1 {
+            if !map.in_bounds(Point::new(x, y)) || other_forts.contains(&(x, y)) {
+                continue;
+            }
+            let idx = map.xy_idx(x, y);
+            map.claimed_tiles[idx] = new_owner;
+        }
+    }
+
+    for player in (&mut players).join() {
+        if player.order == old_owner {
+            player.fort_count = player.fort_count.saturating_sub(1);
+        } else if player.order == new_owner {
+            player.fort_count += 1;
+        }
+    }
+}
+
+/// Removes a unit from the world and takes it off of its owner's unit count
+fn remove_unit(ecs: &mut World, unit: Entity, owner: PlayerOrder) {
+    {
+        let mut players = ecs.write_storage::<Player>();
+
+        for player in (&mut players).join() {
+            if player.order == owner {
+                player.unit_count = player.unit_count.saturating_sub(1);
+            }
+        }
+    }
+    ecs.delete_entity(unit)
+        .expect("Unable to remove defeated unit");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{apply, Command, Outcome};
+    use crate::invariants::check_invariants;
+    use crate::{State, TileType};
+
+    use PlayerOrder::{PlayerOne, PlayerTwo};
+
+    /// Puts a unit for each player next to each other with the defender on the given terrain,
+    /// and selects the attacker
+    fn face_off(defender_tile: TileType) -> (State, Entity, Entity) {
+        let mut gs = State::test_arena(10, 10);
+        {
+            let mut map = gs.ecs.fetch_mut::<Map>();
+            let idx = map.xy_idx(5, 5);
+            map.tiles[idx] = defender_tile;
+        }
+        let attacker = gs.test_unit(4, 5, PlayerOne);
+        let defender = gs.test_unit(5, 5, PlayerTwo);
+        gs.test_select(attacker);
+        (gs, attacker, defender)
+    }
+
+    fn unit(gs: &State, entity: Entity) -> Unit {
+        gs.ecs.read_storage::<Unit>().get(entity).unwrap().clone()
+    }
+
+    fn claim_at(gs: &State, x: i32, y: i32) -> PlayerOrder {
+        let map = gs.ecs.fetch::<Map>();
+        map.claimed_tiles[map.xy_idx(x, y)]
+    }
+
+    #[test]
+    fn attacks_are_cut_down_by_the_defenders_terrain() {
+        for (tile, bonus) in [(TileType::Grasslands, 0), (TileType::Hills, 50)] {
+            assert_eq!(tile.defense_bonus(), bonus);
+            let (mut gs, attacker, defender) = face_off(tile);
+            let strength = unit(&gs, attacker).strength as i32;
+            let health = unit(&gs, defender).health;
+
+            assert_eq!(apply(&mut gs, Command::MoveUnit(1, 0)), Outcome::Applied);
+
+            // The roll goes from just over half of the attacker's strength up to all of it
+            let damage = (health - unit(&gs, defender).health) as i32;
+            let lowest = (strength / 2 + 1) * 100 / (100 + bonus);
+            let highest = strength * 100 / (100 + bonus);
+            assert!(
+                (lowest..=highest).contains(&damage),
+                "{} damage on {}",
+                damage,
+                tile.name()
+            );
+
+            // Attacking costs stamina but leaves the attacker where it was
+            assert_eq!(unit(&gs, attacker).stamina, 5);
+            let positions = gs.ecs.read_storage::<Position>();
+            assert_eq!(positions.get(attacker).unwrap().x, 4);
+        }
+    }
+
+    #[test]
+    fn killed_units_are_removed_from_the_map() {
+        let (mut gs, _attacker, defender) = face_off(TileType::Grasslands);
+        gs.ecs
+            .write_storage::<Unit>()
+            .get_mut(defender)
+            .unwrap()
+            .health = 1;
+
+        assert_eq!(apply(&mut gs, Command::MoveUnit(1, 0)), Outcome::Applied);
+
+        assert!(!gs.ecs.is_alive(defender));
+        {
+            let map = gs.ecs.fetch::<Map>();
+            assert!(map.tile_content[map.xy_idx(5, 5)].is_empty());
+        }
+        let players = gs.ecs.read_storage::<Player>();
+        let loser = players.join().find(|p| p.order == PlayerTwo).unwrap();
+        assert_eq!(loser.unit_count, 0);
+        drop(players);
+        assert_eq!(check_invariants(&gs.ecs), Ok(()));
+    }
+
+    #[test]
+    fn friendly_and_out_of_reach_units_cant_be_attacked() {
+        let mut gs = State::test_arena(10, 10);
+        let attacker = gs.test_unit(4, 5, PlayerOne);
+        let friend = gs.test_unit(5, 5, PlayerOne);
+        let enemy = gs.test_unit(4, 7, PlayerTwo);
+        gs.test_select(attacker);
+
+        assert_eq!(apply(&mut gs, Command::MoveUnit(1, 0)), Outcome::Failed);
+        assert_eq!(apply(&mut gs, Command::MoveUnit(0, 2)), Outcome::Failed);
+        assert_eq!(unit(&gs, friend).health, 20);
+        assert_eq!(unit(&gs, enemy).health, 20);
+        assert_eq!(unit(&gs, attacker).stamina, 6);
+    }
+
+    #[test]
+    fn capturing_a_fort_leaves_the_forts_next_to_it_alone() {
+        let mut gs = State::test_arena(10, 10);
+        let target = gs.test_fort(5, 5, PlayerTwo);
+        let neighbour = gs.test_fort(6, 5, PlayerTwo);
+        let attacker = gs.test_unit(4, 5, PlayerOne);
+        gs.ecs
+            .write_storage::<Fort>()
+            .get_mut(target)
+            .unwrap()
+            .defense = 1;
+        gs.test_select(attacker);
+
+        assert_eq!(apply(&mut gs, Command::MoveUnit(1, 0)), Outcome::Applied);
+
+        let forts = gs.ecs.read_storage::<Fort>();
+        assert_eq!(forts.get(target).unwrap().owner, PlayerOne);
+        assert_eq!(forts.get(neighbour).unwrap().owner, PlayerTwo);
+        assert_eq!(claim_at(&gs, 5, 5), PlayerOne);
+        assert_eq!(claim_at(&gs, 4, 4), PlayerOne);
+        assert_eq!(claim_at(&gs, 6, 5), PlayerTwo);
+        assert_eq!(check_invariants(&gs.ecs), Ok(()));
+    }
+}
@@ -0,0 +1,371 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Every action a player can take goes through a Command, which lets the
+//! game's rules run without a window. Keyboard and mouse input are only
+//! translated into commands here, and apply hands each command to the part of
+//! the game that handles the state the game is currently in.
+
+use crate::gui::log::{log_command, LOG_ROWS};
+use crate::gui::minimap::{overview_command, MinimapCache};
+use crate::gui::window::selection_command;
+use crate::gui::{option_at, owned_forts, owned_units, SelectionType};
+use crate::mouse::{Click, MouseButton};
+use crate::{
+    cursor_command, fort_command, unit_command, MessageType, ProductionItem, RunState, State,
+};
+use bracket_lib::prelude::VirtualKeyCode;
+use serde::{Deserialize, Serialize};
+
+/// An action taken by the player whose turn it is
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum Command {
+    /// Moves the cursor by the given amount, or the marker on the overview by that many cells
+    MoveCursor(i32, i32),
+    /// Moves the selected unit by the given amount, attacking anything owned by another
+    /// player that's in the way
+    MoveUnit(i32, i32),
+    ClaimTile,
+    BuildFort,
+    /// Sends the selected unit towards the cursor
+    GoToCursor,
+    /// Sends the selected unit towards the given tile
+    GoTo(i32, i32),
+    /// Selects the player's unit or fort on the given tile, or moves the cursor there if
+    /// there isn't one
+    SelectAt(i32, i32),
+    /// Adds an item onto the end of the selected fort's production queue
+    QueueProduction(ProductionItem),
+    /// Moves the highlighted entry in the selected fort's queue one spot closer to the front
+    RaiseProduction,
+    /// Moves the highlighted entry in the selected fort's queue one spot further back
+    LowerProduction,
+    /// Takes the highlighted entry out of the selected fort's queue
+    CancelProduction,
+    /// Opens the list of units the player owns
+    ShowUnits,
+    /// Opens the list of forts the player owns
+    ShowForts,
+    /// Opens the full message log
+    ShowLog,
+    /// Opens the overview of the whole map
+    ShowOverview,
+    /// Hides messages of the given type in the message log, or shows them again
+    ToggleFilter(MessageType),
+    /// Moves the highlight in the message log by the given number of messages
+    ScrollLog(i32),
+    /// Picks the given entry, starting from 1, out of the open unit/fort list
+    Select(u32),
+    /// Adds a digit onto the selection typed into the open unit/fort list
+    TypeDigit(u32),
+    /// Removes the last digit typed into the open unit/fort list
+    DeleteDigit,
+    /// Moves the highlight up in the open unit/fort list or the selected fort's queue
+    MenuUp,
+    /// Moves the highlight down in the open unit/fort list or the selected fort's queue
+    MenuDown,
+    /// Picks the entry typed into or highlighted in the open unit/fort list, or jumps the
+    /// cursor to the message highlighted in the message log or the spot marked on the overview
+    Confirm,
+    /// Closes the open unit/fort list, the message log, or the overview without picking anything
+    Cancel,
+    /// Hands control of the selected unit or fort back to the cursor
+    Deselect,
+    EndTurn,
+    SaveGame,
+    LoadGame,
+    Quit,
+}
+
+/// What happened when a command was applied
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Outcome {
+    /// The command was carried out
+    Applied,
+    /// The command was tried but failed, the reason why is written to the message log
+    Failed,
+    /// The command doesn't do anything in the state the game is currently in
+    Ignored,
+    /// The player asked to close the game
+    Quit,
+}
+
+impl Outcome {
+    /// Turns whether an action worked into an outcome
+    pub fn from_success(success: bool) -> Outcome {
+        if success {
+            Outcome::Applied
+        } else {
+            Outcome::Failed
+        }
+    }
+}
+
+/// Carries out a command for the player whose turn it is, no rendering context is needed
+/// so whole games can be played by feeding in commands
+pub fn apply(gs: &mut State, command: Command) -> Outcome {
+    let outcome = match gs.runstate {
+        RunState::MoveCursor => cursor_command(gs, command),
+        RunState::MoveUnit => unit_command(gs, command),
+        RunState::SelectedFort => fort_command(gs, command),
+        RunState::ShowUnits => selection_command(gs, command, SelectionType::Unit),
+        RunState::ShowForts => selection_command(gs, command, SelectionType::Fort),
+        RunState::ShowLog => log_command(gs, command),
+        RunState::Overview => overview_command(gs, command),
+    };
+
+    // Keeping the blocked tiles and what each player can see up to date with the command
+    gs.run_systems();
+    gs.ecs.fetch_mut::<MinimapCache>().clear();
+
+    outcome
+}
+
+/// Translates a key press into the command it stands for in the state the game is in
+pub fn key_to_command(runstate: RunState, key: VirtualKeyCode) -> Option<Command> {
+    match runstate {
+        RunState::MoveCursor => match key {
+            VirtualKeyCode::A => Some(Command::MoveCursor(-1, 0)),
+            VirtualKeyCode::D => Some(Command::MoveCursor(1, 0)),
+            VirtualKeyCode::W => Some(Command::MoveCursor(0, -1)),
+            VirtualKeyCode::S => Some(Command::MoveCursor(0, 1)),
+            VirtualKeyCode::N => Some(Command::EndTurn),
+            VirtualKeyCode::I => Some(Command::ShowUnits),
+            VirtualKeyCode::F => Some(Command::ShowForts),
+            VirtualKeyCode::L => Some(Command::ShowLog),
+            VirtualKeyCode::M => Some(Command::ShowOverview),
+            VirtualKeyCode::F5 => Some(Command::SaveGame),
+            VirtualKeyCode::F9 => Some(Command::LoadGame),
+            VirtualKeyCode::Escape => Some(Command::Quit),
+            _ => None,
+        },
+        RunState::MoveUnit => match key {
+            VirtualKeyCode::A => Some(Command::MoveUnit(-1, 0)),
+            VirtualKeyCode::D => Some(Command::MoveUnit(1, 0)),
+            VirtualKeyCode::W => Some(Command::MoveUnit(0, -1)),
+            VirtualKeyCode::S => Some(Command::MoveUnit(0, 1)),
+            VirtualKeyCode::G => Some(Command::ClaimTile),
+            VirtualKeyCode::B => Some(Command::BuildFort),
+            VirtualKeyCode::T => Some(Command::GoToCursor),
+            VirtualKeyCode::I => Some(Command::Deselect),
+            _ => None,
+        },
+        RunState::SelectedFort => match key {
+            VirtualKeyCode::B => Some(Command::QueueProduction(ProductionItem::Unit)),
+            VirtualKeyCode::S => Some(Command::QueueProduction(ProductionItem::Swordsman)),
+            VirtualKeyCode::Up => Some(Command::MenuUp),
+            VirtualKeyCode::Down => Some(Command::MenuDown),
+            VirtualKeyCode::LBracket => Some(Command::RaiseProduction),
+            VirtualKeyCode::RBracket => Some(Command::LowerProduction),
+            VirtualKeyCode::X | VirtualKeyCode::Delete => Some(Command::CancelProduction),
+            VirtualKeyCode::I | VirtualKeyCode::Escape => Some(Command::Deselect),
+            _ => None,
+        },
+        RunState::ShowUnits | RunState::ShowForts => match key {
+            VirtualKeyCode::Escape => Some(Command::Cancel),
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Some(Command::Confirm),
+            VirtualKeyCode::Back => Some(Command::DeleteDigit),
+            VirtualKeyCode::Up => Some(Command::MenuUp),
+            VirtualKeyCode::Down => Some(Command::MenuDown),
+            VirtualKeyCode::Key0 | VirtualKeyCode::Numpad0 => Some(Command::TypeDigit(0)),
+            VirtualKeyCode::Key1 | VirtualKeyCode::Numpad1 => Some(Command::TypeDigit(1)),
+            VirtualKeyCode::Key2 | VirtualKeyCode::Numpad2 => Some(Command::TypeDigit(2)),
+            VirtualKeyCode::Key3 | VirtualKeyCode::Numpad3 => Some(Command::TypeDigit(3)),
+            VirtualKeyCode::Key4 | VirtualKeyCode::Numpad4 => Some(Command::TypeDigit(4)),
+            VirtualKeyCode::Key5 | VirtualKeyCode::Numpad5 => Some(Command::TypeDigit(5)),
+            VirtualKeyCode::Key6 | VirtualKeyCode::Numpad6 => Some(Command::TypeDigit(6)),
+            VirtualKeyCode::Key7 | VirtualKeyCode::Numpad7 => Some(Command::TypeDigit(7)),
+            VirtualKeyCode::Key8 | VirtualKeyCode::Numpad8 => Some(Command::TypeDigit(8)),
+            VirtualKeyCode::Key9 | VirtualKeyCode::Numpad9 => Some(Command::TypeDigit(9)),
+            _ => None,
+        },
+        RunState::ShowLog => match key {
+            VirtualKeyCode::Escape | VirtualKeyCode::L => Some(Command::Cancel),
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Some(Command::Confirm),
+            VirtualKeyCode::Up => Some(Command::ScrollLog(-1)),
+            VirtualKeyCode::Down => Some(Command::ScrollLog(1)),
+            VirtualKeyCode::PageUp => Some(Command::ScrollLog(-(LOG_ROWS as i32))),
+            VirtualKeyCode::PageDown => Some(Command::ScrollLog(LOG_ROWS as i32)),
+            VirtualKeyCode::Key1 => Some(Command::ToggleFilter(MessageType::Build)),
+            VirtualKeyCode::Key2 => Some(Command::ToggleFilter(MessageType::Claim)),
+            VirtualKeyCode::Key3 => Some(Command::ToggleFilter(MessageType::Move)),
+            VirtualKeyCode::Key4 => Some(Command::ToggleFilter(MessageType::Combat)),
+            VirtualKeyCode::Key5 => Some(Command::ToggleFilter(MessageType::Error)),
+            VirtualKeyCode::Key6 => Some(Command::ToggleFilter(MessageType::Other)),
+            _ => None,
+        },
+        RunState::Overview => match key {
+            VirtualKeyCode::A => Some(Command::MoveCursor(-1, 0)),
+            VirtualKeyCode::D => Some(Command::MoveCursor(1, 0)),
+            VirtualKeyCode::W => Some(Command::MoveCursor(0, -1)),
+            VirtualKeyCode::S => Some(Command::MoveCursor(0, 1)),
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Some(Command::Confirm),
+            VirtualKeyCode::Escape | VirtualKeyCode::M => Some(Command::Cancel),
+            _ => None,
+        },
+    }
+}
+
+/// Translates a click into the command it stands for in the state the game is in. Clicking
+/// the map picks out whatever's on the clicked tile, right clicking sends the selected unit
+/// to it, and clicking an entry in the unit/fort list picks it.
+pub fn click_to_command(gs: &State, click: Click) -> Option<Command> {
+    match (gs.runstate, click.button) {
+        (RunState::MoveCursor, MouseButton::Left) => {
+            click.tile.map(|tile| Command::SelectAt(tile.x, tile.y))
+        }
+        (RunState::MoveUnit, MouseButton::Right) => {
+            click.tile.map(|tile| Command::GoTo(tile.x, tile.y))
+        }
+        (RunState::ShowUnits, MouseButton::Left) => {
+            let count = owned_units(&gs.ecs).len() as u32;
+            option_at(gs, count, click.screen).map(|index| Command::Select(index + 1))
+        }
+        (RunState::ShowForts, MouseButton::Left) => {
+            let count = owned_forts(&gs.ecs).len() as u32;
+            option_at(gs, count, click.screen).map(|index| Command::Select(index + 1))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gui::select_player;
+    use crate::invariants::check_invariants;
+    use crate::{Map, Moving, Player, PlayerOrder, Position};
+    use bracket_lib::prelude::Point;
+    use specs::prelude::*;
+
+    /// Applies the command and checks the world is still in a valid state afterwards
+    fn apply_checked(gs: &mut State, command: Command) -> Outcome {
+        let outcome = apply(gs, command);
+        if let Err(violations) = check_invariants(&gs.ecs) {
+            let violations: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+            panic!("{:?} broke invariants: {}", command, violations.join("; "));
+        }
+        outcome
+    }
+
+    fn cursor(gs: &State) -> Point {
+        *gs.ecs.fetch::<Point>()
+    }
+
+    fn unit_count(gs: &State, order: PlayerOrder) -> u16 {
+        let players = gs.ecs.read_storage::<Player>();
+        (&players)
+            .join()
+            .find(|player| player.order == order)
+            .map(|player| player.unit_count)
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn cursor_moves_and_comes_back() {
+        let mut gs = State::test_game(3);
+        let start = cursor(&gs);
+
+        assert_eq!(
+            apply_checked(&mut gs, Command::MoveCursor(1, 0)),
+            Outcome::Applied
+        );
+        assert_eq!(cursor(&gs), Point::new(start.x + 1, start.y));
+        assert_eq!(
+            apply_checked(&mut gs, Command::MoveCursor(0, 1)),
+            Outcome::Applied
+        );
+        assert_eq!(
+            apply_checked(&mut gs, Command::MoveCursor(-1, 0)),
+            Outcome::Applied
+        );
+        assert_eq!(
+            apply_checked(&mut gs, Command::MoveCursor(0, -1)),
+            Outcome::Applied
+        );
+        assert_eq!(cursor(&gs), start);
+
+        // Commands that don't belong to cursor mode are left alone
+        assert_eq!(apply_checked(&mut gs, Command::ClaimTile), Outcome::Ignored);
+        assert_eq!(gs.runstate, RunState::MoveCursor);
+    }
+
+    #[test]
+    fn units_claim_unclaimed_tiles() {
+        let mut gs = State::test_game(3);
+        let order = select_player(&gs.ecs).unwrap();
+        let units = owned_units(&gs.ecs).len() as u32;
+
+        let mut claimed = None;
+        for choice in 1..=units {
+            assert_eq!(apply_checked(&mut gs, Command::ShowUnits), Outcome::Applied);
+            assert_eq!(
+                apply_checked(&mut gs, Command::Select(choice)),
+                Outcome::Applied
+            );
+            assert_eq!(gs.runstate, RunState::MoveUnit);
+
+            let pos = {
+                let positions = gs.ecs.read_storage::<Position>();
+                let moving = gs.ecs.read_storage::<Moving>();
+                let (pos, _) = (&positions, &moving).join().next().unwrap();
+                *pos
+            };
+            let idx = gs.ecs.fetch::<Map>().xy_idx(pos.x, pos.y);
+            if gs.ecs.fetch::<Map>().claimed_tiles[idx] == PlayerOrder::NoPlayer {
+                assert_eq!(apply_checked(&mut gs, Command::ClaimTile), Outcome::Applied);
+                assert_eq!(gs.ecs.fetch::<Map>().claimed_tiles[idx], order);
+                // A tile can't be claimed twice
+                assert_eq!(apply_checked(&mut gs, Command::ClaimTile), Outcome::Failed);
+                claimed = Some(pos);
+                break;
+            }
+            assert_eq!(apply_checked(&mut gs, Command::Deselect), Outcome::Applied);
+        }
+        assert!(
+            claimed.is_some(),
+            "None of the starting units were on unclaimed tiles"
+        );
+    }
+
+    #[test]
+    fn forts_build_queued_units_over_turns() {
+        let mut gs = State::test_game(3);
+        let order = select_player(&gs.ecs).unwrap();
+        let starting_units = unit_count(&gs, order);
+
+        assert_eq!(apply_checked(&mut gs, Command::ShowForts), Outcome::Applied);
+        assert_eq!(apply_checked(&mut gs, Command::Select(1)), Outcome::Applied);
+        assert_eq!(gs.runstate, RunState::SelectedFort);
+        assert_eq!(
+            apply_checked(&mut gs, Command::QueueProduction(ProductionItem::Unit)),
+            Outcome::Applied
+        );
+        assert_eq!(apply_checked(&mut gs, Command::Deselect), Outcome::Applied);
+        assert_eq!(gs.runstate, RunState::MoveCursor);
+
+        // Every player ends their turn until the fort finishes the unit
+        for _ in 0..20 {
+            assert_eq!(apply_checked(&mut gs, Command::EndTurn), Outcome::Applied);
+            if unit_count(&gs, order) > starting_units {
+                break;
+            }
+        }
+        assert_eq!(unit_count(&gs, order), starting_units + 1);
+        assert_eq!(owned_units(&gs.ecs).len(), (starting_units + 1) as usize);
+    }
+
+    #[test]
+    fn ending_turns_passes_control_around_the_table() {
+        let mut gs = State::test_game(3);
+        let first = select_player(&gs.ecs);
+        assert_eq!(gs.turns, 0);
+
+        assert_eq!(apply_checked(&mut gs, Command::EndTurn), Outcome::Applied);
+        assert_ne!(select_player(&gs.ecs), first);
+        assert_eq!(gs.turns, 0);
+
+        assert_eq!(apply_checked(&mut gs, Command::EndTurn), Outcome::Applied);
+        assert_eq!(select_player(&gs.ecs), first);
+        assert_eq!(gs.turns, 1);
+    }
+}
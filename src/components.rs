@@ -4,12 +4,15 @@
 //! this project.
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_0.html
 
+use crate::economy::Yields;
+use crate::{Deposit, GameLog, Map};
 use bracket_lib::prelude::*;
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 use specs_derive::*;
 
 /// Stores the tiles currently visible, and stores how many tiles out an entity can see
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Viewshed {
     pub visible_tiles: Vec<Point>,
     pub range: i32,
@@ -17,14 +20,14 @@ pub struct Viewshed {
 }
 
 /// Stores an x/y position for an entity
-#[derive(Component, Debug, Clone, Copy)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
 }
 
 /// For recording how to render an entity and its color and background along with render order
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Renderable {
     pub glyph: FontCharType,
     pub fg: RGB,
@@ -32,32 +35,108 @@ pub struct Renderable {
     pub render_order: i32,
 }
 // Came from roguelike tutorial chapter 7
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct BlocksTile {}
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct Name {
     pub name: String,
 }
 
 /// Used to keep track of which player owns/claims what
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum PlayerOrder {
     NoPlayer,
     PlayerOne,
     PlayerTwo,
+    PlayerThree,
+    PlayerFour,
 }
 
-/// Used for the cursor, keeps track of the player order, and how many units and forts that player has
-#[derive(Component, Debug)]
+impl PlayerOrder {
+    /// Every player that can take part in a game, in the order they take their turns
+    pub const TURN_ORDER: [PlayerOrder; 4] = [
+        PlayerOrder::PlayerOne,
+        PlayerOrder::PlayerTwo,
+        PlayerOrder::PlayerThree,
+        PlayerOrder::PlayerFour,
+    ];
+
+    /// Where a player sits in the turn order, NoPlayer comes before everyone
+    pub fn index(&self) -> usize {
+        match self {
+            PlayerOrder::NoPlayer => 0,
+            PlayerOrder::PlayerOne => 1,
+            PlayerOrder::PlayerTwo => 2,
+            PlayerOrder::PlayerThree => 3,
+            PlayerOrder::PlayerFour => 4,
+        }
+    }
+
+    /// The name used for a player in the sidebar and the message log
+    pub fn name(&self) -> String {
+        match self {
+            PlayerOrder::NoPlayer => "No Player".to_string(),
+            PlayerOrder::PlayerOne => "Player1".to_string(),
+            PlayerOrder::PlayerTwo => "Player2".to_string(),
+            PlayerOrder::PlayerThree => "Player3".to_string(),
+            PlayerOrder::PlayerFour => "Player4".to_string(),
+        }
+    }
+
+    /// Background color used for tiles claimed by a player
+    pub fn claim_color(&self) -> RGB {
+        match self {
+            PlayerOrder::NoPlayer => RGB::named(BLACK),
+            PlayerOrder::PlayerOne => RGB::named(PINK),
+            PlayerOrder::PlayerTwo => RGB::named(RED),
+            PlayerOrder::PlayerThree => RGB::named(DARKORANGE),
+            PlayerOrder::PlayerFour => RGB::named(PURPLE),
+        }
+    }
+
+    /// Foreground color used for a player's units so they can be told apart on the map
+    pub fn unit_color(&self) -> RGB {
+        match self {
+            PlayerOrder::NoPlayer => RGB::named(WHITE),
+            PlayerOrder::PlayerOne => RGB::named(CYAN),
+            PlayerOrder::PlayerTwo => RGB::named(GOLD),
+            PlayerOrder::PlayerThree => RGB::named(LIME),
+            PlayerOrder::PlayerFour => RGB::named(MAGENTA),
+        }
+    }
+}
+
+/// Used for the cursor, keeps track of the player order, how many units and forts that player
+/// has, and the resources they've collected
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 pub struct Player {
     pub order: PlayerOrder,
     pub unit_count: u16,
     pub fort_count: u16,
+    pub stockpile: Yields,
+}
+
+/// The last thing a player saw of an enemy fort, drawn in place of the fort once it
+/// goes back into the fog of war
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FortSnapshot {
+    pub pos: Position,
+    pub owner: PlayerOrder,
+    pub render: Renderable,
+}
+
+/// Stores the tiles a player has explored and can currently see, along with the last
+/// place they saw each enemy fort
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct FogOfWar {
+    pub revealed_tiles: Vec<bool>,
+    pub visible_tiles: Vec<bool>,
+    pub seen_forts: Vec<FortSnapshot>,
 }
 
 /// Stores the health and strength of a unit and keeps track of who owns that unit
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Unit {
     pub owner: PlayerOrder,
     pub health: u8,
@@ -65,17 +144,70 @@ pub struct Unit {
 	pub stamina: u8,
 }
 
+/// A multi-turn order for a unit to walk somewhere, stores the map indexes of the tiles the
+/// unit still has to step on with the next step first
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct GoTo {
+    pub path: Vec<usize>,
+}
+
 /// Used for marking a unit as being movable
 #[derive(Component)]
 pub struct Moving;
 
-/// Stores the forts defense and stores who owns that fort
-#[derive(Component)]
+/// Something a fort can be told to build
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum ProductionItem {
+    Unit,
+    Swordsman,
+}
+
+impl ProductionItem {
+    /// How many production points the fort has to put in before the item is finished
+    pub fn cost(&self) -> u16 {
+        match self {
+            ProductionItem::Unit => 10,
+            ProductionItem::Swordsman => 15,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            ProductionItem::Unit => "Unit".to_string(),
+            ProductionItem::Swordsman => "Swordsman".to_string(),
+        }
+    }
+
+    /// The deposit a player needs to have claimed before they're able to build the item
+    pub fn required_deposit(&self) -> Option<Deposit> {
+        match self {
+            ProductionItem::Unit => None,
+            ProductionItem::Swordsman => Some(Deposit::Iron),
+        }
+    }
+}
+
+/// Stores the forts defense, who owns that fort, and what the fort is building along with
+/// how many production points have gone into the first item in the queue
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Fort {
     pub owner: PlayerOrder,
     pub defense: u8,
+    pub queue: Vec<ProductionItem>,
+    pub progress: u16,
 }
 
 /// Used for marking which fort is currently selected by the player
 #[derive(Component)]
 pub struct Selected;
+
+/// Marks which entities get written out when the game is saved
+pub struct SerializeMe;
+
+/// Temporary entity used for carrying the map and the message log through a save file,
+/// since resources can't be serialized the same way components are
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct SerializationHelper {
+    pub map: Map,
+    pub log: GameLog,
+}
@@ -0,0 +1,193 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Handles the resources players collect each turn from the tiles they've
+//! claimed around their forts.
+
+use crate::{Deposit, Fort, Map, Player, PlayerOrder, Position};
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use std::collections::HashSet;
+use std::ops::{Add, AddAssign};
+
+/// How many tiles away from a fort its claimed tiles still get worked
+pub const FORT_WORK_RADIUS: i32 = 2;
+
+/// An amount of each of the resources a player can collect
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug, Default)]
+pub struct Yields {
+    pub food: u32,
+    pub production: u32,
+    pub gold: u32,
+}
+
+impl Add for Yields {
+    type Output = Yields;
+
+    fn add(self, other: Yields) -> Yields {
+        Yields {
+            food: self.food + other.food,
+            production: self.production + other.production,
+            gold: self.gold + other.gold,
+        }
+    }
+}
+
+impl AddAssign for Yields {
+    fn add_assign(&mut self, other: Yields) {
+        *self = *self + other;
+    }
+}
+
+/// Splits the tiles the player has claimed within the work radius of their forts between those
+/// forts, a tile in range of more than one fort is only worked by the first of them
+pub fn tiles_by_fort(ecs: &World, owner: PlayerOrder) -> Vec<(Entity, Vec<usize>)> {
+    let entities = ecs.entities();
+    let forts = ecs.read_storage::<Fort>();
+    let positions = ecs.read_storage::<Position>();
+    let map = ecs.fetch::<Map>();
+    let mut taken = HashSet::new();
+    let mut worked = Vec::new();
+
+    for (entity, fort, pos) in (&entities, &forts, &positions).join() {
+        if fort.owner != owner {
+            continue;
+        }
+
+        let mut tiles = Vec::new();
+        for y in pos.y - FORT_WORK_RADIUS..=pos.y + FORT_WORK_RADIUS {
+            for x in pos.x - FORT_WORK_RADIUS..=pos.x + FORT_WORK_RADIUS {
+                if x < 0 || x >= map.width || y < 0 || y >= map.height {
+                    continue;
+                }
+                let idx = map.xy_idx(x, y);
+                if map.claimed_tiles[idx] == owner && taken.insert(idx) {
+                    tiles.push(idx);
+                }
+            }
+        }
+        worked.push((entity, tiles));
+    }
+
+    worked
+}
+
+/// Gets every tile the player has claimed within the work radius of one of their forts
+pub fn worked_tiles(ecs: &World, owner: PlayerOrder) -> Vec<usize> {
+    let mut tiles: Vec<usize> = tiles_by_fort(ecs, owner)
+        .into_iter()
+        .flat_map(|(_fort, tiles)| tiles)
+        .collect();
+    tiles.sort_unstable();
+    tiles
+}
+
+/// Works out how many production points each of the player's forts gets from the tiles it works
+pub fn fort_production(ecs: &World, owner: PlayerOrder) -> Vec<(Entity, u32)> {
+    let map = ecs.fetch::<Map>();
+
+    tiles_by_fort(ecs, owner)
+        .into_iter()
+        .map(|(fort, tiles)| {
+            let production = tiles
+                .into_iter()
+                .map(|idx| map.tile_yields(idx).production)
+                .sum();
+            (fort, production)
+        })
+        .collect()
+}
+
+/// Works out how much the player will collect at the start of the next turn
+pub fn income(ecs: &World, owner: PlayerOrder) -> Yields {
+    let map = ecs.fetch::<Map>();
+
+    worked_tiles(ecs, owner)
+        .into_iter()
+        .fold(Yields::default(), |total, idx| total + map.tile_yields(idx))
+}
+
+/// Checks if the player has claimed a tile holding the given deposit anywhere on the map
+pub fn has_deposit(ecs: &World, owner: PlayerOrder, deposit: Deposit) -> bool {
+    let map = ecs.fetch::<Map>();
+
+    map.deposits
+        .iter()
+        .zip(map.claimed_tiles.iter())
+        .any(|(found, claim)| *found == Some(deposit) && *claim == owner)
+}
+
+/// Adds each player's food and gold onto their stockpile, ran at the start of every turn.
+/// Production isn't stockpiled since the forts working the tiles put it into their queues.
+pub fn collect_yields(ecs: &mut World) {
+    let mut incomes = Vec::new();
+    {
+        let players = ecs.read_storage::<Player>();
+        for player in players.join() {
+            incomes.push((player.order, income(ecs, player.order)));
+        }
+    }
+
+    let mut players = ecs.write_storage::<Player>();
+    for player in (&mut players).join() {
+        if let Some((_, income)) = incomes.iter().find(|(order, _)| *order == player.order) {
+            player.stockpile += Yields {
+                production: 0,
+                ..*income
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{apply, Command};
+    use crate::{ProductionItem, State, TileType, FORT_BASE_PRODUCTION};
+
+    #[test]
+    fn production_is_only_counted_once() {
+        let mut gs = State::test_arena(12, 12);
+        let fort = gs.test_fort(5, 5, PlayerOrder::PlayerOne);
+        {
+            let mut map = gs.ecs.fetch_mut::<Map>();
+            for (x, y, tile) in [
+                (4, 5, TileType::Hills),
+                (6, 5, TileType::Forest),
+                (5, 7, TileType::Hills),
+            ] {
+                let idx = map.xy_idx(x, y);
+                map.tiles[idx] = tile;
+                map.claimed_tiles[idx] = PlayerOrder::PlayerOne;
+            }
+        }
+        let tile_production: u32 = worked_tiles(&gs.ecs, PlayerOrder::PlayerOne)
+            .into_iter()
+            .map(|idx| gs.ecs.fetch::<Map>().tile_yields(idx).production)
+            .sum();
+        assert_eq!(tile_production, 6);
+
+        gs.ecs
+            .write_storage::<Fort>()
+            .get_mut(fort)
+            .unwrap()
+            .queue
+            .push(ProductionItem::Swordsman);
+        apply(&mut gs, Command::EndTurn);
+        apply(&mut gs, Command::EndTurn);
+        assert_eq!(gs.turns, 1);
+
+        // Everything the tiles made went into the fort's queue and none of it into the stockpile
+        let progress = u32::from(gs.ecs.read_storage::<Fort>().get(fort).unwrap().progress);
+        let players = gs.ecs.read_storage::<Player>();
+        let player = players
+            .join()
+            .find(|player| player.order == PlayerOrder::PlayerOne)
+            .unwrap();
+        assert_eq!(player.stockpile.production, 0);
+        assert_eq!(progress, FORT_BASE_PRODUCTION + tile_production);
+        assert_eq!(
+            player.stockpile.food,
+            income(&gs.ecs, PlayerOrder::PlayerOne).food
+        );
+    }
+}
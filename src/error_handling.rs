@@ -1,6 +1,7 @@
-use crate::{ExpectedFuzzState, FailedMoveReason, GameLog, MessageType, RunState};
-use bracket_lib::prelude::*;
-use rand::{thread_rng, Rng};
+use crate::combat::{CombatResult, SiegeResult};
+use crate::gui::select_player;
+use crate::unit::PathProgress;
+use crate::{FailedMoveReason, GameLog, LogEvent, MessageType, Name, PlayerOrder, Position, Unit};
 use specs::prelude::*;
 
 pub fn handle_move_result(
@@ -8,105 +9,147 @@ pub fn handle_move_result(
     res: Result<(i32, i32), FailedMoveReason>,
     verbose: bool,
 ) {
+    let player = select_player(ecs).unwrap_or(PlayerOrder::NoPlayer);
     let mut log = ecs.fetch_mut::<GameLog>();
+    let error = |message: &str| LogEvent::new(MessageType::Error, player, message.to_string());
     match res {
         Ok((x, y)) => {
             if verbose {
-                log.entries.push(format!("Moved entity to ({}, {})", x, y));
-                log.message_type.push(MessageType::Move);
+                log.push(
+                    LogEvent::new(
+                        MessageType::Move,
+                        player,
+                        format!("Moved entity to ({}, {})", x, y),
+                    )
+                    .at(Position { x, y }),
+                );
             }
         }
         Err(e) => match e {
             FailedMoveReason::TileBlocked => {
-                log.entries
-                    .push("Tile entity tried to move on is blocked".to_string());
-                log.message_type.push(MessageType::Error);
+                log.push(error("Tile entity tried to move on is blocked"));
             }
             FailedMoveReason::UnableToGrabEntity => {
-                log.entries.push("Failed to grab entity".to_string());
-                log.message_type.push(MessageType::Error);
+                log.push(error("Failed to grab entity"));
             }
             FailedMoveReason::UnitOutOfMoves => {
-                log.entries.push("Unit out of stamina, can't make more moves".to_string());
-                log.message_type.push(MessageType::Error);
-            }	
+                log.push(error("Unit out of stamina, can't make more moves"));
+            }
+            FailedMoveReason::NotEnoughStamina => {
+                log.push(error("Unit doesn't have enough stamina to cross that terrain"));
+            }
         },
     }
 }
 
-/// Generate a key for a test, returns back the expected state after the command is run
-pub fn generate_key(initial_state: RunState, ctx: &mut BTerm) -> ExpectedFuzzState {
-    let mut rng = thread_rng();
-    let key: i32 = rng.gen_range(0..8);
-    let mut gen_key: Option<VirtualKeyCode> = None; 
-
-    let mut expected_state = ExpectedFuzzState {
-        first: initial_state,
-        second: None,
-        third: None,
-    };
-
-    // Yes, this is very dumb. I couldn't really do anything else for generating input since
-    // I can't add derive traits on external enums. I generated the match statement using
-    // a small rust prorgram that wrote this out to a file so I didn't have to write it manually
-    if initial_state == RunState::ShowUnits || initial_state == RunState::ShowForts {
-        gen_key = match key {
-            0 => Some(VirtualKeyCode::Return),
-            1 => Some(VirtualKeyCode::Escape),
-            2 => Some(VirtualKeyCode::Up),
-            _ => Some(VirtualKeyCode::Down),
-
+/// Writes the outcome of an attack out to the message log
+pub fn handle_combat_result(ecs: &mut World, res: Result<CombatResult, FailedMoveReason>) {
+    let player = select_player(ecs).unwrap_or(PlayerOrder::NoPlayer);
+    let mut log = ecs.fetch_mut::<GameLog>();
+    match res {
+        Ok(result) => {
+            let mut message = format!(
+                "{}'s {} hit {}'s {} for {} damage",
+                result.attacker_owner.name(),
+                result.attacker,
+                result.defender_owner.name(),
+                result.defender,
+                result.damage
+            );
+            if result.defender_killed {
+                message.push_str(", destroying it");
+            }
+            log.push(
+                LogEvent::new(MessageType::Combat, result.attacker_owner, message)
+                    .at(result.position),
+            );
+        }
+        Err(e) => {
+            let message = match e {
+                FailedMoveReason::UnitOutOfMoves => "Unit out of stamina, can't attack",
+                _ => "Failed to attack unit",
+            };
+            log.push(LogEvent::new(MessageType::Error, player, message.to_string()));
         }
-    } else {
-        gen_key = match key {
-            0 => Some(VirtualKeyCode::W),
-            1 => Some(VirtualKeyCode::A),
-            2 => Some(VirtualKeyCode::S),
-            3 => Some(VirtualKeyCode::D),
-            4 => Some(VirtualKeyCode::I),
-            5 => Some(VirtualKeyCode::F),
-            6 => Some(VirtualKeyCode::G),
-            _ => Some(VirtualKeyCode::B),
-        };
-    }
-    
-    if gen_key.is_some() {
-        ctx.key = gen_key;
-        println!("key: {:?}", gen_key.unwrap()); 
     }
+}
 
-    // These first two cases are for the fort/unit menus, it returns two different types
-    // to signal those two are the acceptable states for the game to be in
-    match initial_state {
-        RunState::ShowUnits => {
-            expected_state.first = RunState::ShowUnits;
-            expected_state.second = Some(RunState::MoveUnit);
-            expected_state.third = Some(RunState::MoveCursor);
-        }
-        RunState::ShowForts => {
-            expected_state.first = RunState::ShowForts;
-            expected_state.second = Some(RunState::SelectedFort);
-            expected_state.third = Some(RunState::MoveCursor);
+/// Writes the outcome of an attack on a fort out to the message log
+pub fn handle_siege_result(ecs: &mut World, res: Result<SiegeResult, FailedMoveReason>) {
+    let player = select_player(ecs).unwrap_or(PlayerOrder::NoPlayer);
+    let mut log = ecs.fetch_mut::<GameLog>();
+    match res {
+        Ok(result) => {
+            let message = if result.captured {
+                format!(
+                    "{}'s {} captured {} from {}",
+                    result.attacker_owner.name(),
+                    result.attacker,
+                    result.fort,
+                    result.fort_owner.name()
+                )
+            } else {
+                format!(
+                    "{}'s {} hit {}'s {} for {} damage",
+                    result.attacker_owner.name(),
+                    result.attacker,
+                    result.fort_owner.name(),
+                    result.fort,
+                    result.damage
+                )
+            };
+            log.push(
+                LogEvent::new(MessageType::Combat, result.attacker_owner, message)
+                    .at(result.position),
+            );
         }
-        RunState::MoveUnit => {
-            if key == 4 { // I
-                expected_state.first = RunState::MoveCursor;
-            }
+        Err(e) => {
+            let message = match e {
+                FailedMoveReason::UnitOutOfMoves => "Unit out of stamina, can't attack",
+                _ => "Failed to attack fort",
+            };
+            log.push(LogEvent::new(MessageType::Error, player, message.to_string()));
         }
-        RunState::SelectedFort => {
-            if (key == 7) || (key == 4) { // B and I
-                expected_state.first = RunState::MoveCursor;
-            }
+    }
+}
+
+/// Writes out how far a unit following a go to order got, units that are still on their
+/// way don't get a message
+pub fn handle_path_result(ecs: &mut World, unit: Entity, res: PathProgress) {
+    let name = ecs
+        .read_storage::<Name>()
+        .get(unit)
+        .map(|name| name.name.clone())
+        .unwrap_or_default();
+    let owner = ecs
+        .read_storage::<Unit>()
+        .get(unit)
+        .map(|unit| unit.owner)
+        .unwrap_or(PlayerOrder::NoPlayer);
+    let position = ecs.read_storage::<Position>().get(unit).copied();
+    let mut log = ecs.fetch_mut::<GameLog>();
+    match res {
+        PathProgress::Arrived(x, y) => {
+            log.push(
+                LogEvent::new(
+                    MessageType::Move,
+                    owner,
+                    format!("{} has arrived at ({}, {})", name, x, y),
+                )
+                .at(Position { x, y })
+                .about(unit),
+            );
         }
-        RunState::MoveCursor => {
-            if key == 4 { // I
-                expected_state.first = RunState::ShowUnits;
-            } else if key == 5 { // F
-                expected_state.first = RunState::ShowForts;
-            }
+        PathProgress::Blocked => {
+            let mut event = LogEvent::new(
+                MessageType::Error,
+                owner,
+                format!("{}'s path is blocked", name),
+            )
+            .about(unit);
+            event.position = position;
+            log.push(event);
         }
-		_ => { expected_state.first = initial_state; }
+        PathProgress::Underway => {}
     }
-
-    expected_state
 }
@@ -0,0 +1,226 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Writes the whole map out as an image with one pixel per tile, so the world a
+//! seed generates can be looked over without having to play through it.
+
+use crate::camera::get_tile_glyph;
+use crate::{Fort, Map, PlayerOrder, Position, Renderable, Unit};
+use bracket_lib::prelude::{Algorithm2D, Point, RGB};
+use specs::prelude::*;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Where the image gets written to when no path is given
+pub const DEFAULT_EXPORT_FILE: &str = "./map.png";
+
+/// What gets drawn on top of the terrain
+#[derive(PartialEq, Copy, Clone, Debug, Default)]
+pub struct ExportOptions {
+    pub claims: bool,
+    pub entities: bool,
+}
+
+/// The image formats the map can be written as, picked from the file's extension
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+}
+
+/// Used for returning why the image couldn't be written
+#[derive(Debug)]
+pub enum ExportError {
+    Io(std::io::Error),
+    Png(png::EncodingError),
+    UnknownFormat(String),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::Io(e) => write!(f, "unable to write the image: {}", e),
+            ExportError::Png(e) => write!(f, "unable to encode the png: {}", e),
+            ExportError::UnknownFormat(path) => write!(
+                f,
+                "can't tell what kind of image {} should be, use .png or .ppm",
+                path
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+impl From<png::EncodingError> for ExportError {
+    fn from(e: png::EncodingError) -> Self {
+        ExportError::Png(e)
+    }
+}
+
+/// Turns a colour into the three bytes of a pixel
+fn to_bytes(color: RGB) -> [u8; 3] {
+    [
+        (color.r.clamp(0.0, 1.0) * 255.0) as u8,
+        (color.g.clamp(0.0, 1.0) * 255.0) as u8,
+        (color.b.clamp(0.0, 1.0) * 255.0) as u8,
+    ]
+}
+
+/// Gets the colour of every tile on the map in rows from the top left, using the same
+/// colours the tiles are drawn with in game. Claimed tiles get their owner's colour mixed
+/// in, and units and forts are drawn in their own colours over the tile they're on.
+pub fn render_pixels(ecs: &World, options: ExportOptions) -> Vec<u8> {
+    let map = ecs.fetch::<Map>();
+    let mut pixels = Vec::with_capacity(map.tiles.len() * 3);
+
+    for idx in 0..map.tiles.len() {
+        let (_glyph, mut fg, bg) = get_tile_glyph(idx, &map, None);
+        if options.claims && map.claimed_tiles[idx] != PlayerOrder::NoPlayer {
+            fg = fg.lerp(bg, 0.6);
+        }
+        pixels.extend_from_slice(&to_bytes(fg));
+    }
+
+    if options.entities {
+        let positions = ecs.read_storage::<Position>();
+        let renderables = ecs.read_storage::<Renderable>();
+        let units = ecs.read_storage::<Unit>();
+        let forts = ecs.read_storage::<Fort>();
+
+        // Forts are drawn after units so they stay visible with a unit standing in them
+        let unit_pixels = (&positions, &renderables, &units)
+            .join()
+            .map(|(pos, render, _unit)| (*pos, render.fg));
+        let fort_pixels = (&positions, &renderables, &forts)
+            .join()
+            .map(|(pos, render, _fort)| (*pos, render.fg));
+        for (pos, color) in unit_pixels.chain(fort_pixels) {
+            if map.in_bounds(Point::new(pos.x, pos.y)) {
+                let idx = map.xy_idx(pos.x, pos.y) * 3;
+                pixels[idx..idx + 3].copy_from_slice(&to_bytes(color));
+            }
+        }
+    }
+
+    pixels
+}
+
+/// Writes the map as an image to the given path, with the format coming from the extension
+pub fn export_map(ecs: &World, path: &str, options: ExportOptions) -> Result<(), ExportError> {
+    let format =
+        ImageFormat::from_path(path).ok_or_else(|| ExportError::UnknownFormat(path.to_string()))?;
+    let (width, height) = {
+        let map = ecs.fetch::<Map>();
+        (map.width as u32, map.height as u32)
+    };
+    let pixels = render_pixels(ecs, options);
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    match format {
+        ImageFormat::Png => {
+            let mut encoder = png::Encoder::new(writer, width, height);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header()?.write_image_data(&pixels)?;
+        }
+        ImageFormat::Ppm => {
+            // The binary version of the format: a short text header followed by the pixels
+            write!(writer, "P6\n{} {}\n255\n", width, height)?;
+            writer.write_all(&pixels)?;
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+    use std::fs;
+
+    fn temp_image(name: &str) -> String {
+        let file = format!("civlike_{}_{}", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file)
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn ppm_has_one_pixel_per_tile_in_the_tile_colours() {
+        let gs = State::test_game(2);
+        let path = temp_image("export.ppm");
+        export_map(&gs.ecs, &path, ExportOptions::default()).unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        let header = b"P6\n80 60\n255\n";
+        assert_eq!(&data[..header.len()], header);
+        let pixels = &data[header.len()..];
+        assert_eq!(pixels.len(), 80 * 60 * 3);
+
+        let map = gs.ecs.fetch::<Map>();
+        for idx in 0..map.tiles.len() {
+            let (_glyph, fg, _bg) = get_tile_glyph(idx, &map, None);
+            assert_eq!(pixels[idx * 3..idx * 3 + 3], to_bytes(fg), "tile {}", idx);
+        }
+    }
+
+    #[test]
+    fn png_keeps_the_map_size_and_draws_units_over_the_terrain() {
+        let gs = State::test_game(2);
+        let path = temp_image("export.png");
+        let options = ExportOptions {
+            claims: false,
+            entities: true,
+        };
+        export_map(&gs.ecs, &path, options).unwrap();
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        fs::remove_file(&path).ok();
+
+        assert_eq!((reader.info().width, reader.info().height), (80, 60));
+        let map = gs.ecs.fetch::<Map>();
+        let positions = gs.ecs.read_storage::<Position>();
+        let renderables = gs.ecs.read_storage::<Renderable>();
+        let units = gs.ecs.read_storage::<Unit>();
+        let mut drawn = 0;
+        for (pos, render, _unit) in (&positions, &renderables, &units).join() {
+            let idx = map.xy_idx(pos.x, pos.y) * 3;
+            assert_eq!(pixels[idx..idx + 3], to_bytes(render.fg));
+            drawn += 1;
+        }
+        assert!(drawn > 0);
+    }
+
+    #[test]
+    fn unknown_extensions_are_refused() {
+        let gs = State::test_game(2);
+        let result = export_map(&gs.ecs, "map.bmp", ExportOptions::default());
+        assert!(matches!(result, Err(ExportError::UnknownFormat(_))));
+    }
+}
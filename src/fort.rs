@@ -1,17 +1,154 @@
 //! Made by: Thomas Gardner, 2022
 //!
 //! The code for this comes from my work with src/player.rs and src/unit.rs,
-//! but adjusted for working with forts; contains controls for creating units
-//! through forts, and a function to add the unit to the game.
+//! but adjusted for working with forts; contains controls for managing the
+//! production queue of a fort, and the functions for building what's in the
+//! queue over the course of multiple turns.
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_0.html
 
+use crate::command::{Command, Outcome};
+use crate::gui::select_player;
 use crate::spawner::*;
 use crate::{
-    teleport_player, xy_idx, Fort, GameLog, Map, MessageType, Player, PlayerOrder, Position,
-    RunState, Selected, State, World,
+    economy, teleport_player, Deposit, Fort, GameLog, LogEvent, Map, MessageType, Name, Player,
+    PlayerOrder, Position, ProductionItem, RunState, Selected, State, World,
 };
-use bracket_lib::prelude::*;
 use specs::prelude::*;
+use std::cmp::min;
+
+/// The most defense a fort can have, which is also what new forts start with
+pub const FORT_MAX_DEFENSE: u8 = 10;
+/// How much defense a damaged fort gets back at the start of each turn
+pub const FORT_DEFENSE_REGEN: u8 = 2;
+
+/// How many production points a fort puts into its queue each turn on top of what its worked
+/// tiles give, so a fort with no productive tiles around it still finishes things eventually
+pub const FORT_BASE_PRODUCTION: u32 = 1;
+/// The most items a fort can have waiting in its queue at once
+pub const FORT_QUEUE_LIMIT: usize = 8;
+
+/// Gets the fort the player currently has selected
+fn selected_fort(ecs: &World) -> Option<Entity> {
+    let entities = ecs.entities();
+    let forts = ecs.read_storage::<Fort>();
+    let selects = ecs.read_storage::<Selected>();
+
+    (&entities, &forts, &selects)
+        .join()
+        .next()
+        .map(|(entity, _fort, _selected)| entity)
+}
+
+/// Gets how many items are waiting in the selected fort's queue
+fn selected_queue_len(ecs: &World) -> usize {
+    let forts = ecs.read_storage::<Fort>();
+
+    selected_fort(ecs)
+        .and_then(|entity| forts.get(entity))
+        .map(|fort| fort.queue.len())
+        .unwrap_or(0)
+}
+
+/// Used for returning why an item couldn't be added to a fort's queue
+pub enum FailedQueueReason {
+    NoFortSelected,
+    QueueFull,
+    MissingDeposit(Deposit),
+}
+
+/// Adds an item onto the end of the selected fort's queue if the fort belongs to the player,
+/// there's room left in the queue, and the player has claimed whatever the item needs
+fn queue_production(ecs: &mut World, item: ProductionItem) -> Result<(), FailedQueueReason> {
+    let player_order = select_player(ecs).ok_or(FailedQueueReason::NoFortSelected)?;
+    let selected = selected_fort(ecs).ok_or(FailedQueueReason::NoFortSelected)?;
+
+    if let Some(deposit) = item.required_deposit() {
+        if !economy::has_deposit(ecs, player_order, deposit) {
+            return Err(FailedQueueReason::MissingDeposit(deposit));
+        }
+    }
+
+    let mut forts = ecs.write_storage::<Fort>();
+    match forts.get_mut(selected) {
+        Some(fort) if fort.owner != player_order => Err(FailedQueueReason::NoFortSelected),
+        Some(fort) if fort.queue.len() >= FORT_QUEUE_LIMIT => Err(FailedQueueReason::QueueFull),
+        Some(fort) => {
+            fort.queue.push(item);
+            Ok(())
+        }
+        None => Err(FailedQueueReason::NoFortSelected),
+    }
+}
+
+/// Swaps the entry at the given spot in the selected fort's queue with the one before or after
+/// it, returns where the entry ended up. Production points aren't lost when the queue is changed,
+/// they go into whatever ends up at the front of the queue.
+fn move_queue_entry(ecs: &mut World, index: usize, earlier: bool) -> Option<usize> {
+    let selected = selected_fort(ecs)?;
+    let mut forts = ecs.write_storage::<Fort>();
+    let fort = forts.get_mut(selected)?;
+
+    let destination = if earlier {
+        index.checked_sub(1)?
+    } else {
+        index + 1
+    };
+    if destination >= fort.queue.len() || index >= fort.queue.len() {
+        return None;
+    }
+
+    fort.queue.swap(index, destination);
+    Some(destination)
+}
+
+/// Takes the entry at the given spot out of the selected fort's queue
+fn cancel_queue_entry(ecs: &mut World, index: usize) -> Option<ProductionItem> {
+    let selected = selected_fort(ecs)?;
+    let mut forts = ecs.write_storage::<Fort>();
+    let fort = forts.get_mut(selected)?;
+
+    if index < fort.queue.len() {
+        Some(fort.queue.remove(index))
+    } else {
+        None
+    }
+}
+
+/// Finds where a finished unit can be placed, which is the fort's own tile if nothing is standing
+/// on it, otherwise any free tile next to the fort that units can walk on
+fn find_spawn_tile(map: &Map, pos: Position) -> Option<(i32, i32)> {
+    let mut candidates = vec![(pos.x, pos.y)];
+    for y in pos.y - 1..=pos.y + 1 {
+        for x in pos.x - 1..=pos.x + 1 {
+            if (x, y) != (pos.x, pos.y) {
+                candidates.push((x, y));
+            }
+        }
+    }
+
+    candidates.into_iter().find(|&(x, y)| {
+        if x < 0 || x >= map.width || y < 0 || y >= map.height {
+            return false;
+        }
+        let idx = map.xy_idx(x, y);
+        map.tiles[idx].movement_cost().is_some() && !map.blocked[idx]
+    })
+}
+
+/// Works out how many production points a fort puts into its queue each turn
+pub fn production_per_turn(ecs: &World, fort: Entity) -> u32 {
+    let owner = match ecs.read_storage::<Fort>().get(fort) {
+        Some(fort) => fort.owner,
+        None => return 0,
+    };
+
+    let worked = economy::fort_production(ecs, owner)
+        .into_iter()
+        .find(|(entity, _)| *entity == fort)
+        .map(|(_, production)| production)
+        .unwrap_or(0);
+    FORT_BASE_PRODUCTION + worked
+}
 
 /*
     Using scoping in this function to prevent errors from the borrow checker since I'm moving
@@ -21,51 +158,101 @@ use specs::prelude::*;
     Section: User Interface; Notifying of Deaths
     Link: https://bfnightly.bracketproductions.com/rustbook/chapter_8.html
 */
-/// Builds a unit at the current fort is a unit isn't already present
-fn build_unit(ecs: &mut World) -> Option<(i32, i32)> {
-    let mut player_order: Option<PlayerOrder> = None;
-    let mut new_unit_pos: Option<(i32, i32)> = None;
+/// Puts every fort's production points into the front of its queue, and places a unit for each
+/// item that gets finished. A finished item waits at the front of the queue if there's no room
+/// around the fort to place it.
+pub fn advance_production(ecs: &mut World) {
+    let mut finished: Vec<(PlayerOrder, ProductionItem, (i32, i32))> = Vec::new();
+    let mut no_room: Vec<(Entity, String, Position, PlayerOrder)> = Vec::new();
+
+    let production: Vec<(Entity, u32)> = PlayerOrder::TURN_ORDER
+        .iter()
+        .flat_map(|owner| economy::fort_production(ecs, *owner))
+        .collect();
 
     {
-        let players = ecs.read_storage::<Player>();
         let entities = ecs.entities();
+        let mut forts = ecs.write_storage::<Fort>();
         let positions = ecs.read_storage::<Position>();
-        let forts = ecs.read_storage::<Fort>();
-        let selects = ecs.read_storage::<Selected>();
-        let map = ecs.fetch::<Map>();
+        let names = ecs.read_storage::<Name>();
+        let mut map = ecs.fetch_mut::<Map>();
 
-        for (player, _entity) in (&players, &entities).join() {
-            player_order = Some(player.order);
-        }
+        for (entity, fort, pos, name) in (&entities, &mut forts, &positions, &names).join() {
+            let item = match fort.queue.first() {
+                Some(item) => *item,
+                None => continue,
+            };
 
-        if let Some(ref owner) = player_order {
-            for (fort, pos, _selected) in (&forts, &positions, &selects).join() {
-                if !map.blocked[xy_idx(pos.x, pos.y)] && *owner == fort.owner {
-                    new_unit_pos = Some((pos.x, pos.y));
+            let worked = production
+                .iter()
+                .find(|(fort, _)| *fort == entity)
+                .map_or(0, |(_, points)| *points);
+            let points = FORT_BASE_PRODUCTION + worked;
+            fort.progress = min(u32::from(fort.progress) + points, u32::from(item.cost())) as u16;
+            if fort.progress < item.cost() {
+                continue;
+            }
+
+            match find_spawn_tile(&map, *pos) {
+                Some((x, y)) => {
+                    // Marking the tile as blocked so units finished by other forts this turn
+                    // can't be placed on the same tile
+                    let idx = map.xy_idx(x, y);
+                    map.blocked[idx] = true;
+                    fort.queue.remove(0);
+                    fort.progress = 0;
+                    finished.push((fort.owner, item, (x, y)));
                 }
+                None => no_room.push((entity, name.name.clone(), *pos, fort.owner)),
             }
         }
     }
 
-    if let Some(pos) = new_unit_pos {
-        if let Some(player) = player_order {
-            let mut unit_counter = 0;
-            {
-                let mut players = ecs.write_storage::<Player>();
-                let entities = ecs.entities();
+    for (owner, item, pos) in finished {
+        let mut unit_counter = 0;
+        {
+            let mut players = ecs.write_storage::<Player>();
 
-                for (player, _entity) in (&mut players, &entities).join() {
+            for player in (&mut players).join() {
+                if player.order == owner {
                     player.unit_count += 1;
                     unit_counter = player.unit_count;
                 }
             }
-            let name = format!("Unit{}", unit_counter);
-            let unit = unit(ecs, pos, name, 8, player);
-            ecs.insert(unit);
         }
+
+        let name = format!("{}{}", item.name(), unit_counter);
+        let built = match item {
+            ProductionItem::Unit => unit(ecs, pos, name, 8, owner),
+            ProductionItem::Swordsman => swordsman(ecs, pos, name, 8, owner),
+        };
+
+        let message = format!(
+            "{} has built a {} at ({}, {})",
+            owner.name(),
+            item.name().to_lowercase(),
+            pos.0,
+            pos.1
+        );
+        ecs.fetch_mut::<GameLog>().push(
+            LogEvent::new(MessageType::Build, owner, message)
+                .at(Position { x: pos.0, y: pos.1 })
+                .about(built),
+        );
     }
 
-    new_unit_pos
+    let mut log = ecs.fetch_mut::<GameLog>();
+    for (entity, name, pos, owner) in no_room {
+        log.push(
+            LogEvent::new(
+                MessageType::Error,
+                owner,
+                format!("{} has no room to place what it built", name),
+            )
+            .at(pos)
+            .about(entity),
+        );
+    }
 }
 
 /// Used for removing the moving marker from a unit struct so it won't move the next time a unit gets moved
@@ -89,64 +276,201 @@ fn unmark_selected_fort(ecs: &mut World) -> Option<Position> {
     curr_pos
 }
 
-/// Lets the player build a unit or exit back to cursor mode
-pub fn fort_input(gs: &mut State, ctx: &mut BTerm) -> RunState {
-    let mut order = String::new();
-    {
-        let entities = gs.ecs.entities();
-        let players = gs.ecs.read_storage::<Player>();
-
-        for (player, _entity) in (&players, &entities).join() {
-            match player.order {
-                PlayerOrder::NoPlayer => order = "No Player".to_string(),
-                PlayerOrder::PlayerOne => order = "Player1".to_string(),
-                PlayerOrder::PlayerTwo => order = "Player2".to_string(),
-            }
-        }
-    }
+/// Carries out a command given while a fort is selected, letting the player add to, reorder,
+/// and cancel entries in the fort's production queue, or go back to cursor mode
+pub fn fort_command(gs: &mut State, command: Command) -> Outcome {
+    let order = select_player(&gs.ecs).unwrap_or(PlayerOrder::NoPlayer);
 
-    match ctx.key {
-        None => {}
-        Some(key) => match key {
-            VirtualKeyCode::B => {
-                let new_unit_location = build_unit(&mut gs.ecs);
-
-                {
-                    let mut log = gs.ecs.fetch_mut::<GameLog>();
-                    match new_unit_location {
-                        Some((x, y)) => {
-                            log.entries
-                                .push(format!("{} has built a unit at ({}, {})", order, x, y));
-                            log.message_type.push(MessageType::Build);
-                        }
-                        None => {
-                            log.entries.push("Unable to build a unit".to_string());
-                            log.message_type.push(MessageType::Error);
-                        }
-                    }
-                }
+    match command {
+        Command::QueueProduction(item) => {
+            let res = queue_production(&mut gs.ecs, item);
+            let queued = res.is_ok();
 
-                match unmark_selected_fort(&mut gs.ecs) {
-                    None => {
-                        panic!("Failed to unmark selected fort")
-                    }
-                    Some(pos) => {
-                        teleport_player(pos, &mut gs.ecs);
-                    }
+            let (kind, message) = match res {
+                Ok(()) => (
+                    MessageType::Build,
+                    format!("{} has queued a {}", order.name(), item.name().to_lowercase()),
+                ),
+                Err(FailedQueueReason::MissingDeposit(deposit)) => (
+                    MessageType::Error,
+                    format!(
+                        "A {} needs {} to be claimed first",
+                        item.name().to_lowercase(),
+                        deposit.name().to_lowercase()
+                    ),
+                ),
+                Err(FailedQueueReason::QueueFull) => {
+                    (MessageType::Error, "The fort's queue is full".to_string())
                 }
-                return RunState::MoveCursor;
-            }
-            VirtualKeyCode::I => match unmark_selected_fort(&mut gs.ecs) {
-                None => {
-                    panic!("Failed to unmark selected fort")
+                Err(FailedQueueReason::NoFortSelected) => {
+                    (MessageType::Error, "Unable to add to the queue".to_string())
                 }
-                Some(pos) => {
-                    teleport_player(pos, &mut gs.ecs);
-                    return RunState::MoveCursor;
+            };
+            let mut event = LogEvent::new(kind, order, message);
+            if let Some(fort) = selected_fort(&gs.ecs) {
+                event = event.about(fort);
+                event.position = gs.ecs.read_storage::<Position>().get(fort).copied();
+            }
+            gs.ecs.fetch_mut::<GameLog>().push(event);
+            Outcome::from_success(queued)
+        }
+        Command::MenuUp => {
+            if gs.last_option > 0 {
+                gs.last_option -= 1;
+            }
+            Outcome::Applied
+        }
+        Command::MenuDown => {
+            if (gs.last_option as usize + 1) < selected_queue_len(&gs.ecs) {
+                gs.last_option += 1;
+            }
+            Outcome::Applied
+        }
+        Command::RaiseProduction | Command::LowerProduction => {
+            let earlier = command == Command::RaiseProduction;
+            match move_queue_entry(&mut gs.ecs, gs.last_option as usize, earlier) {
+                Some(index) => {
+                    // The highlight follows the entry that was moved
+                    gs.last_option = index as u32;
+                    Outcome::Applied
                 }
-            },
-            _ => {}
+                None => Outcome::Failed,
+            }
+        }
+        Command::CancelProduction => {
+            let cancelled = cancel_queue_entry(&mut gs.ecs, gs.last_option as usize);
+            let len = selected_queue_len(&gs.ecs) as u32;
+            if gs.last_option >= len {
+                gs.last_option = len.saturating_sub(1);
+            }
+            Outcome::from_success(cancelled.is_some())
+        }
+        Command::Deselect => match unmark_selected_fort(&mut gs.ecs) {
+            None => {
+                let message = "There's no selected fort to deselect".to_string();
+                let event = LogEvent::new(MessageType::Error, order, message);
+                gs.ecs.fetch_mut::<GameLog>().push(event);
+                gs.runstate = RunState::MoveCursor;
+                Outcome::Failed
+            }
+            Some(pos) => {
+                teleport_player(pos, &mut gs.ecs);
+                gs.runstate = RunState::MoveCursor;
+                Outcome::Applied
+            }
         },
+        _ => Outcome::Ignored,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::apply;
+    use crate::economy::FORT_WORK_RADIUS;
+    use crate::TileType;
+
+    #[test]
+    fn swordsmen_need_iron_to_be_claimed_first() {
+        let mut gs = State::test_arena(10, 10);
+        let fort = gs.test_fort(5, 5, PlayerOrder::PlayerOne);
+        let unit = gs.test_unit(7, 5, PlayerOrder::PlayerOne);
+        {
+            let mut map = gs.ecs.fetch_mut::<Map>();
+            let idx = map.xy_idx(7, 5);
+            map.deposits[idx] = Some(Deposit::Iron);
+        }
+        let select_fort = |gs: &mut State| {
+            gs.ecs
+                .write_storage::<Selected>()
+                .insert(fort, Selected)
+                .unwrap();
+            gs.runstate = RunState::SelectedFort;
+        };
+        let queue_len = |gs: &State| gs.ecs.read_storage::<Fort>().get(fort).unwrap().queue.len();
+        let has_iron =
+            |gs: &State| economy::has_deposit(&gs.ecs, PlayerOrder::PlayerOne, Deposit::Iron);
+        let swordsman = Command::QueueProduction(ProductionItem::Swordsman);
+
+        select_fort(&mut gs);
+        assert!(!has_iron(&gs));
+        assert_eq!(apply(&mut gs, swordsman), Outcome::Failed);
+        assert_eq!(queue_len(&gs), 0);
+        assert_eq!(apply(&mut gs, Command::Deselect), Outcome::Applied);
+
+        gs.test_select(unit);
+        assert_eq!(apply(&mut gs, Command::ClaimTile), Outcome::Applied);
+        assert_eq!(apply(&mut gs, Command::Deselect), Outcome::Applied);
+
+        select_fort(&mut gs);
+        assert!(has_iron(&gs));
+        assert_eq!(apply(&mut gs, swordsman), Outcome::Applied);
+        assert_eq!(queue_len(&gs), 1);
+    }
+
+    #[test]
+    fn deselect_without_a_selected_fort_fails_instead_of_panicking() {
+        let mut gs = State::test_game(3);
+        gs.runstate = RunState::SelectedFort;
+        let events = gs.ecs.fetch::<GameLog>().events.len();
+
+        assert_eq!(apply(&mut gs, Command::Deselect), Outcome::Failed);
+        assert_eq!(gs.runstate, RunState::MoveCursor);
+        let log = gs.ecs.fetch::<GameLog>();
+        assert_eq!(log.events.len(), events + 1);
+        assert_eq!(log.events.back().unwrap().kind, MessageType::Error);
+    }
+
+    #[test]
+    fn forts_build_with_the_production_of_their_worked_tiles() {
+        let mut gs = State::test_game(3);
+        let order = select_player(&gs.ecs).unwrap();
+        let (fort, pos) = {
+            let entities = gs.ecs.entities();
+            let forts = gs.ecs.read_storage::<Fort>();
+            let positions = gs.ecs.read_storage::<Position>();
+            (&entities, &forts, &positions)
+                .join()
+                .find(|(_, fort, _)| fort.owner == order)
+                .map(|(entity, _, pos)| (entity, *pos))
+                .unwrap()
+        };
+        let before = production_per_turn(&gs.ecs, fort);
+        assert!(before >= FORT_BASE_PRODUCTION);
+
+        // Claiming a bare hill next to the fort adds its production to the fort's
+        {
+            let mut map = gs.ecs.fetch_mut::<Map>();
+            let x = if pos.x + FORT_WORK_RADIUS < map.width {
+                pos.x + FORT_WORK_RADIUS
+            } else {
+                pos.x - FORT_WORK_RADIUS
+            };
+            let idx = map.xy_idx(x, pos.y);
+            assert_eq!(map.claimed_tiles[idx], PlayerOrder::NoPlayer);
+            map.tiles[idx] = TileType::Hills;
+            map.deposits[idx] = None;
+            map.claimed_tiles[idx] = order;
+        }
+        let after = production_per_turn(&gs.ecs, fort);
+        assert_eq!(after, before + TileType::Hills.yields().production);
+
+        gs.ecs
+            .write_storage::<Fort>()
+            .get_mut(fort)
+            .unwrap()
+            .queue
+            .push(ProductionItem::Swordsman);
+        apply(&mut gs, Command::EndTurn);
+        apply(&mut gs, Command::EndTurn);
+
+        let forts = gs.ecs.read_storage::<Fort>();
+        let fort = forts.get(fort).unwrap();
+        let cost = u32::from(ProductionItem::Swordsman.cost());
+        if after < cost {
+            assert_eq!(u32::from(fort.progress), after);
+        } else {
+            assert!(fort.queue.is_empty());
+        }
     }
-    RunState::SelectedFort
 }
@@ -0,0 +1,487 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Plays random key presses and clicks against a headless game to find bugs. Every
+//! input comes from a generator seeded with the world seed, so the same seed always
+//! makes the same inputs. The world's invariants are checked after every key, and the commands
+//! that were applied get written to a replay file as soon as something goes wrong
+//! so the failure can be stepped through again.
+
+use crate::command::{apply, key_to_command, Command};
+use crate::generator_config::GeneratorConfig;
+use crate::invariants::check_invariants;
+use crate::spawner::PlacementError;
+use crate::{Map, RunState, State};
+use bracket_lib::prelude::{Point, RandomNumberGenerator, VirtualKeyCode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Where the commands leading up to a failure get written to
+pub const FUZZ_REPLAY_FILE: &str = "./fuzz_replay.json";
+
+/// How many keys get pressed when no step count is given
+pub const DEFAULT_FUZZ_STEPS: u32 = 10000;
+
+/// One in this many inputs is a click instead of a key press, in the states that take clicks
+const CLICK_CHANCE: i32 = 8;
+
+/// How many tiles away from the cursor a click can land
+const CLICK_RANGE: i32 = 10;
+
+/// Keys pressed while moving the cursor around, leaving out the keys for saving,
+/// loading, and closing the game
+const CURSOR_KEYS: [VirtualKeyCode; 9] = [
+    VirtualKeyCode::W,
+    VirtualKeyCode::A,
+    VirtualKeyCode::S,
+    VirtualKeyCode::D,
+    VirtualKeyCode::I,
+    VirtualKeyCode::F,
+    VirtualKeyCode::N,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+];
+
+/// Keys pressed while a unit is selected
+const UNIT_KEYS: [VirtualKeyCode; 8] = [
+    VirtualKeyCode::W,
+    VirtualKeyCode::A,
+    VirtualKeyCode::S,
+    VirtualKeyCode::D,
+    VirtualKeyCode::I,
+    VirtualKeyCode::G,
+    VirtualKeyCode::B,
+    VirtualKeyCode::T,
+];
+
+/// Keys pressed while a fort is selected
+const FORT_KEYS: [VirtualKeyCode; 8] = [
+    VirtualKeyCode::B,
+    VirtualKeyCode::S,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::LBracket,
+    VirtualKeyCode::RBracket,
+    VirtualKeyCode::X,
+    VirtualKeyCode::I,
+];
+
+/// Keys pressed while the unit or fort list is open
+const MENU_KEYS: [VirtualKeyCode; 9] = [
+    VirtualKeyCode::Return,
+    VirtualKeyCode::Escape,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::Back,
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key9,
+];
+
+/// Keys pressed while the overview is open
+const OVERVIEW_KEYS: [VirtualKeyCode; 6] = [
+    VirtualKeyCode::W,
+    VirtualKeyCode::A,
+    VirtualKeyCode::S,
+    VirtualKeyCode::D,
+    VirtualKeyCode::Return,
+    VirtualKeyCode::Escape,
+];
+
+/// Keys pressed while the message log is open
+const LOG_KEYS: [VirtualKeyCode; 8] = [
+    VirtualKeyCode::Return,
+    VirtualKeyCode::Escape,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::PageUp,
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key5,
+];
+
+/// The states the game is allowed to be in after a command, most commands only allow one
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub struct ExpectedFuzzState {
+    first: RunState,
+    second: Option<RunState>,
+    third: Option<RunState>,
+}
+
+impl ExpectedFuzzState {
+    fn allows(&self, runstate: RunState) -> bool {
+        runstate == self.first || Some(runstate) == self.second || Some(runstate) == self.third
+    }
+}
+
+/// A single key press or click made by the fuzzer along with the command it was turned into,
+/// and the states the game was allowed to end up in afterwards. Replay files written before
+/// the states were recorded don't have them, so those steps aren't checked against them.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FuzzStep {
+    pub key: String,
+    pub command: Option<Command>,
+    #[serde(default)]
+    pub expected: Option<ExpectedFuzzState>,
+}
+
+/// Everything needed to play a fuzz run over again: the game it ran against and every
+/// command up to and including the one that failed
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub players: usize,
+    pub range: i32,
+    #[serde(default)]
+    pub config: GeneratorConfig,
+    pub failure: String,
+    pub steps: Vec<FuzzStep>,
+}
+
+/// Used for returning why a fuzz run or a replay stopped
+#[derive(Debug)]
+pub enum FuzzError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    Placement(PlacementError),
+    Failed { step: usize, reason: String },
+}
+
+impl fmt::Display for FuzzError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FuzzError::Io(e) => write!(f, "unable to access replay file: {}", e),
+            FuzzError::Format(e) => write!(f, "replay file is malformed: {}", e),
+            FuzzError::Placement(e) => write!(f, "unable to start the game: {}", e),
+            FuzzError::Failed { step, reason } => write!(f, "step {} failed: {}", step, reason),
+        }
+    }
+}
+
+impl std::error::Error for FuzzError {}
+
+impl From<std::io::Error> for FuzzError {
+    fn from(e: std::io::Error) -> Self {
+        FuzzError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for FuzzError {
+    fn from(e: serde_json::Error) -> Self {
+        FuzzError::Format(e)
+    }
+}
+
+impl From<PlacementError> for FuzzError {
+    fn from(e: PlacementError) -> Self {
+        FuzzError::Placement(e)
+    }
+}
+
+/// Picks a random key that makes sense for the state the game is in
+pub fn generate_key(runstate: RunState, rng: &mut RandomNumberGenerator) -> VirtualKeyCode {
+    let keys: &[VirtualKeyCode] = match runstate {
+        RunState::MoveCursor => &CURSOR_KEYS,
+        RunState::MoveUnit => &UNIT_KEYS,
+        RunState::SelectedFort => &FORT_KEYS,
+        RunState::ShowUnits | RunState::ShowForts => &MENU_KEYS,
+        RunState::ShowLog => &LOG_KEYS,
+        RunState::Overview => &OVERVIEW_KEYS,
+    };
+
+    *rng.random_slice_entry(keys)
+        .expect("Fuzzer has no keys to press")
+}
+
+/// Sometimes picks a click on the map near the cursor for the states that take clicks, clicks
+/// skip the screen and go straight to the command they'd be turned into
+pub fn generate_click(gs: &State, rng: &mut RandomNumberGenerator) -> Option<Command> {
+    if gs.runstate != RunState::MoveCursor && gs.runstate != RunState::MoveUnit {
+        return None;
+    }
+    if rng.range(0, CLICK_CHANCE) != 0 {
+        return None;
+    }
+
+    let cursor = *gs.ecs.fetch::<Point>();
+    let (width, height) = {
+        let map = gs.ecs.fetch::<Map>();
+        (map.width, map.height)
+    };
+    let x = (cursor.x + rng.range(-CLICK_RANGE, CLICK_RANGE + 1)).clamp(0, width - 1);
+    let y = (cursor.y + rng.range(-CLICK_RANGE, CLICK_RANGE + 1)).clamp(0, height - 1);
+
+    match gs.runstate {
+        RunState::MoveUnit => Some(Command::GoTo(x, y)),
+        _ => Some(Command::SelectAt(x, y)),
+    }
+}
+
+/// Works out which states the game should be in after the command is applied
+fn expected_state(initial_state: RunState, command: Option<Command>) -> ExpectedFuzzState {
+    let mut expected_state = ExpectedFuzzState {
+        first: initial_state,
+        second: None,
+        third: None,
+    };
+
+    // These first two cases are for the fort/unit menus, it returns two different types
+    // to signal those two are the acceptable states for the game to be in
+    match (initial_state, command) {
+        (RunState::ShowUnits, _) => {
+            expected_state.second = Some(RunState::MoveUnit);
+            expected_state.third = Some(RunState::MoveCursor);
+        }
+        (RunState::ShowForts, _) => {
+            expected_state.second = Some(RunState::SelectedFort);
+            expected_state.third = Some(RunState::MoveCursor);
+        }
+        (RunState::MoveUnit, Some(Command::Deselect))
+        | (RunState::MoveUnit, Some(Command::GoToCursor))
+        | (RunState::SelectedFort, Some(Command::Deselect)) => {
+            expected_state.first = RunState::MoveCursor;
+        }
+        // Clicking a tile selects the unit or fort on it, or just moves the cursor
+        (RunState::MoveCursor, Some(Command::SelectAt(_, _))) => {
+            expected_state.second = Some(RunState::MoveUnit);
+            expected_state.third = Some(RunState::SelectedFort);
+        }
+        // Units can't be sent somewhere unexplored, which leaves the unit selected
+        (RunState::MoveUnit, Some(Command::GoTo(_, _))) => {
+            expected_state.second = Some(RunState::MoveCursor);
+        }
+        (RunState::MoveCursor, Some(Command::ShowUnits)) => {
+            expected_state.first = RunState::ShowUnits;
+        }
+        (RunState::MoveCursor, Some(Command::ShowForts)) => {
+            expected_state.first = RunState::ShowForts;
+        }
+        (RunState::MoveCursor, Some(Command::ShowLog)) => {
+            expected_state.first = RunState::ShowLog;
+        }
+        // Jumping to a message only closes the log if it happened somewhere already explored
+        (RunState::ShowLog, Some(Command::Confirm)) => {
+            expected_state.second = Some(RunState::MoveCursor);
+        }
+        (RunState::ShowLog, Some(Command::Cancel)) => {
+            expected_state.first = RunState::MoveCursor;
+        }
+        (RunState::MoveCursor, Some(Command::ShowOverview)) => {
+            expected_state.first = RunState::Overview;
+        }
+        // Jumping only closes the overview if something's been revealed around the marker
+        (RunState::Overview, Some(Command::Confirm)) => {
+            expected_state.second = Some(RunState::MoveCursor);
+        }
+        (RunState::Overview, Some(Command::Cancel)) => {
+            expected_state.first = RunState::MoveCursor;
+        }
+        _ => {}
+    }
+
+    expected_state
+}
+
+/// Picks the next key press or click for the state the game is in
+fn next_step(gs: &State, rng: &mut RandomNumberGenerator) -> FuzzStep {
+    let (key, command) = match generate_click(gs, rng) {
+        Some(command) => ("Click".to_string(), Some(command)),
+        None => {
+            let key = generate_key(gs.runstate, rng);
+            (format!("{:?}", key), key_to_command(gs.runstate, key))
+        }
+    };
+
+    FuzzStep {
+        key,
+        command,
+        expected: Some(expected_state(gs.runstate, command)),
+    }
+}
+
+/// Applies a single command and checks the game ended up in a state it's allowed to be in
+/// without breaking any of the world's invariants, panics are caught and handed back as
+/// errors so the run can be saved
+fn run_step(gs: &mut State, command: Option<Command>) -> Result<(), String> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let initial_state = gs.runstate;
+        let expected = expected_state(initial_state, command);
+
+        if let Some(command) = command {
+            apply(gs, command);
+        }
+
+        if !expected.allows(gs.runstate) {
+            return Err(format!(
+                "runstates don't match! Went from {:?} to {:?} after {:?}",
+                initial_state, gs.runstate, command
+            ));
+        }
+
+        check_invariants(&gs.ecs).map_err(|violations| {
+            let violations = violations
+                .iter()
+                .map(|violation| violation.to_string())
+                .collect::<Vec<_>>();
+            format!(
+                "invariants broken after {:?}: {}",
+                command,
+                violations.join("; ")
+            )
+        })
+    }));
+
+    match result {
+        Ok(res) => res,
+        Err(payload) => Err(match payload.downcast_ref::<&str>() {
+            Some(message) => format!("panicked: {}", message),
+            None => match payload.downcast_ref::<String>() {
+                Some(message) => format!("panicked: {}", message),
+                None => "panicked".to_string(),
+            },
+        }),
+    }
+}
+
+/// Presses the given number of random keys against a new game generated from the seed, and
+/// writes a replay file if any of them leave the game in a bad state or cause a panic
+pub fn run_fuzz(
+    seed: u64,
+    players: usize,
+    range: i32,
+    steps: u32,
+    config: &GeneratorConfig,
+) -> Result<(), FuzzError> {
+    let mut gs = State::new();
+    gs.new_game(seed, players, range, config)?;
+
+    // The fuzzer rolls its keys from its own generator so it doesn't change any of the
+    // rolls the game itself makes
+    let mut rng = RandomNumberGenerator::seeded(seed);
+    let mut recorded: Vec<FuzzStep> = Vec::new();
+
+    for step in 0..steps as usize {
+        let fuzz_step = next_step(&gs, &mut rng);
+        let command = fuzz_step.command;
+        recorded.push(fuzz_step);
+
+        if let Err(reason) = run_step(&mut gs, command) {
+            let replay = Replay {
+                seed,
+                players,
+                range,
+                config: *config,
+                failure: reason.clone(),
+                steps: recorded,
+            };
+            write_replay(FUZZ_REPLAY_FILE, &replay)?;
+            println!("Wrote replay of the failure to {}", FUZZ_REPLAY_FILE);
+
+            return Err(FuzzError::Failed { step, reason });
+        }
+    }
+
+    println!(
+        "Fuzzed {} steps with seed {} without any failures",
+        steps, seed
+    );
+    Ok(())
+}
+
+/// Saves the replay of a failed run so it can be played back later
+fn write_replay(path: &str, replay: &Replay) -> Result<(), FuzzError> {
+    serde_json::to_writer_pretty(File::create(path)?, replay)?;
+    Ok(())
+}
+
+/// Plays back the commands in a replay file one at a time, printing out each step. The
+/// replay stops as soon as a step allows different states than it did in the recorded run,
+/// since the game has gone a different way than it did then.
+pub fn replay(path: &str) -> Result<(), FuzzError> {
+    let replay: Replay = serde_json::from_str(&fs::read_to_string(path)?)?;
+    println!(
+        "Replaying seed {}, recorded failure: {}",
+        replay.seed, replay.failure
+    );
+
+    let mut gs = State::new();
+    gs.new_game(replay.seed, replay.players, replay.range, &replay.config)?;
+
+    for (step, fuzz_step) in replay.steps.iter().enumerate() {
+        println!(
+            "step {}: {:?} key {} -> {:?}",
+            step, gs.runstate, fuzz_step.key, fuzz_step.command
+        );
+
+        let expected = expected_state(gs.runstate, fuzz_step.command);
+        if let Some(recorded) = fuzz_step.expected {
+            if recorded != expected {
+                return Err(FuzzError::Failed {
+                    step,
+                    reason: format!(
+                        "replay allows {:?} but the recorded run allowed {:?}",
+                        expected, recorded
+                    ),
+                });
+            }
+        }
+        run_step(&mut gs, fuzz_step.command)
+            .map_err(|reason| FuzzError::Failed { step, reason })?;
+    }
+
+    println!("Replay finished without any failures");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_expect_the_same_states_as_the_recorded_run() {
+        let config = GeneratorConfig {
+            width: 80,
+            height: 60,
+            ..GeneratorConfig::default()
+        };
+        let mut gs = State::new();
+        gs.new_game(4, 2, 8, &config).unwrap();
+        let mut rng = RandomNumberGenerator::seeded(4);
+
+        let mut steps = Vec::new();
+        for _ in 0..300 {
+            let fuzz_step = next_step(&gs, &mut rng);
+            run_step(&mut gs, fuzz_step.command).unwrap();
+            steps.push(fuzz_step);
+        }
+        let mut recorded = Replay {
+            seed: 4,
+            players: 2,
+            range: 8,
+            config,
+            failure: "stopped by the test".to_string(),
+            steps,
+        };
+
+        let path = std::env::temp_dir().join(format!("civlike_replay_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_replay(path, &recorded).unwrap();
+        let played = replay(path);
+
+        // A run that went some other way stops at the first step that doesn't line up
+        let last = recorded.steps.len() - 1;
+        let expected = recorded.steps[last].expected.as_mut().unwrap();
+        expected.first = match expected.first {
+            RunState::MoveCursor => RunState::Overview,
+            _ => RunState::MoveCursor,
+        };
+        write_replay(path, &recorded).unwrap();
+        let diverged = replay(path);
+        fs::remove_file(path).ok();
+
+        assert!(played.is_ok());
+        assert!(matches!(diverged, Err(FuzzError::Failed { step, .. }) if step == last));
+    }
+}
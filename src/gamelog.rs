@@ -4,19 +4,286 @@
 //! this project. This code comes from section 2.7: User Interface
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_8.html#adding-a-message-log
 
+use crate::{PlayerOrder, Position};
+use bracket_lib::prelude::*;
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 use specs_derive::*;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
 
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
 pub enum MessageType {
     Build,
     Claim,
     Move,
+    Combat,
     Error,
     Other,
 }
 
-#[derive(Component)]
+impl MessageType {
+    pub const ALL: [MessageType; 6] = [
+        MessageType::Build,
+        MessageType::Claim,
+        MessageType::Move,
+        MessageType::Combat,
+        MessageType::Error,
+        MessageType::Other,
+    ];
+
+    pub fn name(&self) -> String {
+        match self {
+            MessageType::Build => "Build".to_string(),
+            MessageType::Claim => "Claim".to_string(),
+            MessageType::Move => "Move".to_string(),
+            MessageType::Combat => "Combat".to_string(),
+            MessageType::Error => "Error".to_string(),
+            MessageType::Other => "Other".to_string(),
+        }
+    }
+
+    /// The colour messages of this type are printed in
+    pub fn color(&self) -> RGB {
+        match self {
+            MessageType::Build => RGB::named(YELLOW),
+            MessageType::Claim => RGB::named(SEAGREEN),
+            MessageType::Move => RGB::named(LIGHTBLUE),
+            MessageType::Combat => RGB::named(RED),
+            MessageType::Error => RGB::named(SALMON),
+            MessageType::Other => RGB::named(WHITE),
+        }
+    }
+}
+
+/// How many events the log keeps hold of before the oldest ones start getting dropped,
+/// events written to the log file are kept there no matter how long the game goes on
+pub const MAX_LOG_EVENTS: usize = 1000;
+
+/// Something that happened during the game: what kind of event it was, the turn it happened
+/// on, which player it happened to, and where and to what if it was something on the map
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogEvent {
+    pub kind: MessageType,
+    pub turn: u32,
+    pub player: PlayerOrder,
+    pub position: Option<Position>,
+    /// The id of the unit or fort the event was about, ids can be reused once an entity
+    /// is gone so they only tell entities apart within a stretch of the game
+    pub entity: Option<u32>,
+    pub message: String,
+}
+
+impl LogEvent {
+    /// Makes an event that didn't happen anywhere in particular, the turn gets filled in
+    /// once it's added to the log
+    pub fn new(kind: MessageType, player: PlayerOrder, message: String) -> LogEvent {
+        LogEvent {
+            kind,
+            turn: 0,
+            player,
+            position: None,
+            entity: None,
+            message,
+        }
+    }
+
+    /// Marks where on the map the event happened
+    pub fn at(mut self, position: Position) -> LogEvent {
+        self.position = Some(position);
+        self
+    }
+
+    /// Marks the unit or fort the event was about
+    pub fn about(mut self, entity: Entity) -> LogEvent {
+        self.entity = Some(entity.id());
+        self
+    }
+
+    /// Checks if the event belongs to a player other than the one looking at the log, events
+    /// that don't belong to any player are everyone's
+    pub fn belongs_to_other(&self, viewer: PlayerOrder) -> bool {
+        self.player != PlayerOrder::NoPlayer && self.player != viewer
+    }
+
+    /// Where the event happened as far as the player looking at the log gets told, other
+    /// players' events don't give away where they happened
+    pub fn position_for(&self, viewer: PlayerOrder) -> Option<Position> {
+        if self.belongs_to_other(viewer) {
+            None
+        } else {
+            self.position
+        }
+    }
+}
+
+/// The file events get written out to as they happen, one json object per line
+#[derive(Clone)]
+pub struct LogStream(Arc<Mutex<BufWriter<File>>>);
+
+impl LogStream {
+    fn write(&self, event: &LogEvent) -> std::io::Result<()> {
+        let mut file = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        serde_json::to_writer(&mut *file, event)?;
+        writeln!(file)?;
+        file.flush()
+    }
+}
+
+/// The newest events written during the game, with the events also going out to a log file
+/// if the game was started with one
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct GameLog {
-    pub entries: Vec<String>,
-    pub message_type: Vec<MessageType>,
+    pub events: VecDeque<LogEvent>,
+    /// The turn new events get marked with, kept up to date as the turns go by
+    pub turn: u32,
+    #[serde(skip)]
+    pub stream: Option<LogStream>,
+}
+
+impl GameLog {
+    pub fn new() -> GameLog {
+        GameLog {
+            events: VecDeque::new(),
+            turn: 0,
+            stream: None,
+        }
+    }
+
+    /// Adds an event onto the end of the log, marked with the current turn. Once the log is
+    /// full the oldest event gets dropped to make room for it.
+    pub fn push(&mut self, mut event: LogEvent) {
+        event.turn = self.turn;
+
+        // The game carries on without the file if it can't be written to anymore
+        if let Some(stream) = &self.stream {
+            if let Err(e) = stream.write(&event) {
+                eprintln!("Unable to write to the log file, stopping: {}", e);
+                self.stream = None;
+            }
+        }
+
+        if self.events.len() >= MAX_LOG_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Starts writing events out to the given file, beginning with the events already in the log
+    pub fn stream_to(&mut self, path: &str) -> std::io::Result<()> {
+        let stream = LogStream(Arc::new(Mutex::new(BufWriter::new(File::create(path)?))));
+        for event in self.events.iter() {
+            stream.write(event)?;
+        }
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Gets the indexes of the events that get through the filter and that the player looking
+    /// at the log is allowed to see, newest first. Other players' events that happened on the
+    /// map are left out since their messages say where, apart from combat which can involve
+    /// the viewer and doesn't say where it happened.
+    pub fn filtered(&self, filter: &LogFilter, viewer: PlayerOrder) -> Vec<usize> {
+        (0..self.events.len())
+            .rev()
+            .filter(|idx| {
+                let event = &self.events[*idx];
+                let hidden = event.belongs_to_other(viewer)
+                    && event.position.is_some()
+                    && event.kind != MessageType::Combat;
+                filter.shows(event.kind) && !hidden
+            })
+            .collect()
+    }
+}
+
+impl Default for GameLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Which types of messages are hidden while looking through the message log
+#[derive(Clone, Debug, Default)]
+pub struct LogFilter {
+    pub hidden: Vec<MessageType>,
+}
+
+impl LogFilter {
+    pub fn shows(&self, message_type: MessageType) -> bool {
+        !self.hidden.contains(&message_type)
+    }
+
+    /// Hides the type of message if it's shown, or shows it again if it's hidden
+    pub fn toggle(&mut self, message_type: MessageType) {
+        if self.shows(message_type) {
+            self.hidden.push(message_type);
+        } else {
+            self.hidden.retain(|hidden| *hidden != message_type);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn numbered(number: usize) -> LogEvent {
+        LogEvent::new(
+            MessageType::Other,
+            PlayerOrder::NoPlayer,
+            number.to_string(),
+        )
+    }
+
+    #[test]
+    fn full_log_drops_the_oldest_events_first() {
+        let mut log = GameLog::new();
+        for number in 0..MAX_LOG_EVENTS + 5 {
+            log.push(numbered(number));
+        }
+
+        assert_eq!(log.events.len(), MAX_LOG_EVENTS);
+        assert_eq!(log.events.front().unwrap().message, "5");
+        let newest = (MAX_LOG_EVENTS + 4).to_string();
+        assert_eq!(log.events.back().unwrap().message, newest);
+    }
+
+    #[test]
+    fn stream_writes_one_event_per_line() {
+        let path = std::env::temp_dir().join(format!("civlike_log_{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut log = GameLog::new();
+        log.push(numbered(0));
+        log.stream_to(path).unwrap();
+        log.turn = 3;
+        let claim = LogEvent::new(MessageType::Claim, PlayerOrder::PlayerTwo, "1".to_string());
+        log.push(claim.at(Position { x: 4, y: 7 }));
+        log.stream = None;
+
+        let written = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).ok();
+        let events = written
+            .lines()
+            .map(|line| serde_json::from_str::<LogEvent>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        // Events already in the log are written out first, so the file has the whole game
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].message, "0");
+        assert_eq!(events[0].turn, 0);
+        assert_eq!(events[1].message, "1");
+        assert_eq!(events[1].turn, 3);
+        assert_eq!(events[1].kind, MessageType::Claim);
+        assert_eq!(events[1].player, PlayerOrder::PlayerTwo);
+        let position = events[1].position.unwrap();
+        assert_eq!((position.x, position.y), (4, 7));
+    }
 }
@@ -0,0 +1,432 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Settings for generating the world: how big the map is, how the noise behind
+//! the heightmap is set up, and the heights where the sea ends and the
+//! mountains begin. They can be read from a config file and changed with flags
+//! so the same binary can make small test maps or huge continents. Presets bundle
+//! up settings that work well together for a particular kind of world.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+
+/// The flags that change a single generator setting, each one takes a value after it
+pub const GENERATOR_FLAGS: [&str; 12] = [
+    "-preset",
+    "-target_land",
+    "-width",
+    "-height",
+    "-octaves",
+    "-gain",
+    "-lacunarity",
+    "-frequency",
+    "-x_scale",
+    "-y_scale",
+    "-sea_level",
+    "-mountain_level",
+];
+
+/// Maps smaller than this on either side don't leave enough room to spawn the players
+pub const MIN_MAP_SIZE: i32 = 20;
+/// Maps bigger than this on either side would take too long to generate, and keeping the
+/// sides under it means the number of tiles always fits
+pub const MAX_MAP_SIZE: i32 = 2000;
+
+/// How much of the map the generator tries to make into land can't go past these, since
+/// the players need land to spawn on and water to be split up by
+pub const MIN_TARGET_LAND: f32 = 0.05;
+pub const MAX_TARGET_LAND: f32 = 0.95;
+
+/// A named set of generator settings that can be picked at startup
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum Preset {
+    /// The original recipe, where how much land there is depends on the seed
+    Classic,
+    /// A few large landmasses split up by oceans
+    Continents,
+    /// Lots of small islands spread out over the sea
+    Archipelago,
+    /// One huge landmass in the middle of the map surrounded by ocean
+    Pangaea,
+    /// Mostly land dotted with lakes
+    Lakes,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 5] = [
+        Preset::Classic,
+        Preset::Continents,
+        Preset::Archipelago,
+        Preset::Pangaea,
+        Preset::Lakes,
+    ];
+
+    pub fn name(&self) -> String {
+        match self {
+            Preset::Classic => "classic".to_string(),
+            Preset::Continents => "continents".to_string(),
+            Preset::Archipelago => "archipelago".to_string(),
+            Preset::Pangaea => "pangaea".to_string(),
+            Preset::Lakes => "lakes".to_string(),
+        }
+    }
+
+    /// Finds the preset with the given name, ignoring case
+    pub fn from_name(name: &str) -> Option<Preset> {
+        Preset::ALL
+            .iter()
+            .find(|preset| preset.name().eq_ignore_ascii_case(name))
+            .copied()
+    }
+}
+
+/// The shape of the mask that lowers the land towards the edges of the map, so the edges
+/// end up as ocean instead of cutting landmasses off
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum Falloff {
+    /// The heightmap is left alone
+    None,
+    /// Land drops off towards the left and right edges, leaving the poles to the ice caps
+    Edges,
+    /// Land drops off with the distance from the middle of the map
+    Radial,
+}
+
+/// The settings for the fractal noise the heightmap is made from. The scales are what the
+/// x and y coordinates get divided by before sampling the noise, so larger scales stretch
+/// the land out into bigger shapes.
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+#[serde(default)]
+pub struct NoiseSettings {
+    pub octaves: i32,
+    pub gain: f32,
+    pub lacunarity: f32,
+    pub frequency: f32,
+    pub x_scale: f32,
+    pub y_scale: f32,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        NoiseSettings {
+            octaves: 8,
+            gain: 1.0,
+            lacunarity: 3.0,
+            frequency: 3.0,
+            x_scale: 160.0,
+            y_scale: 100.0,
+        }
+    }
+}
+
+/// Everything that decides what kind of world gets generated from a seed. The levels are
+/// heights in the heightmap, which runs from -1 to +1: anything below the coast level is
+/// open water, land starts at the sea level, and hills and mountains start at their levels.
+/// After the noise is generated the falloff mask gets applied, bodies of water smaller than
+/// the minimum lake size get filled in, and if there's a target amount of land all of the
+/// levels get moved up or down together until that much of the map is land.
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+#[serde(default)]
+pub struct GeneratorConfig {
+    pub width: i32,
+    pub height: i32,
+    pub noise: NoiseSettings,
+    pub coast_level: f32,
+    pub sea_level: f32,
+    pub hills_level: f32,
+    pub mountain_level: f32,
+    pub falloff: Falloff,
+    pub falloff_strength: f32,
+    pub min_lake_size: usize,
+    pub target_land: Option<f32>,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            width: 400,
+            height: 300,
+            noise: NoiseSettings::default(),
+            coast_level: -(1.0 / 8.0),
+            sea_level: -(1.0 / 10.0),
+            hills_level: 1.0 / 10.0,
+            mountain_level: 1.0 / 6.0,
+            falloff: Falloff::None,
+            falloff_strength: 0.0,
+            min_lake_size: 0,
+            target_land: None,
+        }
+    }
+}
+
+/// Used for returning why a generator config couldn't be used
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    MapTooSmall { width: i32, height: i32 },
+    MapTooBig { width: i32, height: i32 },
+    InvalidNoise(String),
+    LevelsOutOfOrder,
+    InvalidTargetLand(f32),
+    InvalidFlag { flag: String, value: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "unable to read generator config: {}", e),
+            ConfigError::Format(e) => write!(f, "generator config is malformed: {}", e),
+            ConfigError::MapTooSmall { width, height } => write!(
+                f,
+                "a {}x{} map is too small, both sides need to be at least {}",
+                width, height, MIN_MAP_SIZE
+            ),
+            ConfigError::MapTooBig { width, height } => write!(
+                f,
+                "a {}x{} map is too big, neither side can be more than {}",
+                width, height, MAX_MAP_SIZE
+            ),
+            ConfigError::InvalidNoise(reason) => write!(f, "invalid noise settings: {}", reason),
+            ConfigError::LevelsOutOfOrder => write!(
+                f,
+                "the coast, sea, hills and mountain levels need to go from lowest to highest"
+            ),
+            ConfigError::InvalidTargetLand(target) => write!(
+                f,
+                "can't aim for {} of the map to be land, it needs to be from {} to {}",
+                target, MIN_TARGET_LAND, MAX_TARGET_LAND
+            ),
+            ConfigError::InvalidFlag { flag, value } => {
+                write!(f, "{} can't be set to {:?}", flag, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> Self {
+        ConfigError::Format(e)
+    }
+}
+
+impl GeneratorConfig {
+    /// Reads a config from a json file, any settings left out of the file keep their
+    /// default values
+    pub fn load(path: &str) -> Result<GeneratorConfig, ConfigError> {
+        let config: GeneratorConfig = serde_json::from_str(&fs::read_to_string(path)?)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Swaps every setting over to the ones from the preset, except for the size of the map
+    pub fn apply_preset(&mut self, preset: Preset) {
+        let mut config = GeneratorConfig {
+            width: self.width,
+            height: self.height,
+            ..GeneratorConfig::default()
+        };
+        let shaped_noise = NoiseSettings {
+            octaves: 6,
+            gain: 0.5,
+            lacunarity: 2.0,
+            ..NoiseSettings::default()
+        };
+
+        match preset {
+            Preset::Classic => {}
+            Preset::Continents => {
+                config.noise = NoiseSettings {
+                    frequency: 2.0,
+                    ..shaped_noise
+                };
+                config.falloff = Falloff::Edges;
+                config.falloff_strength = 0.6;
+                config.min_lake_size = 40;
+                config.target_land = Some(0.4);
+            }
+            Preset::Archipelago => {
+                config.noise = NoiseSettings {
+                    frequency: 6.0,
+                    ..shaped_noise
+                };
+                config.falloff = Falloff::Edges;
+                config.falloff_strength = 0.3;
+                config.min_lake_size = 10;
+                config.target_land = Some(0.25);
+            }
+            Preset::Pangaea => {
+                config.noise = NoiseSettings {
+                    frequency: 1.5,
+                    ..shaped_noise
+                };
+                config.falloff = Falloff::Radial;
+                config.falloff_strength = 1.2;
+                config.min_lake_size = 60;
+                config.target_land = Some(0.45);
+            }
+            Preset::Lakes => {
+                config.noise = NoiseSettings {
+                    frequency: 4.0,
+                    ..shaped_noise
+                };
+                config.min_lake_size = 3;
+                config.target_land = Some(0.75);
+            }
+        }
+
+        *self = config;
+    }
+
+    /// Changes the setting matching one of the generator flags, the config should be
+    /// validated again once all of the flags have been set. Moving the sea level moves the
+    /// coast along with it, and moving the mountain level does the same for the hills, so
+    /// the bands between them stay the same size.
+    pub fn set_flag(&mut self, flag: &str, value: &str) -> Result<(), ConfigError> {
+        let invalid = || ConfigError::InvalidFlag {
+            flag: flag.to_string(),
+            value: value.to_string(),
+        };
+
+        match flag {
+            "-preset" => self.apply_preset(Preset::from_name(value).ok_or_else(invalid)?),
+            "-target_land" => self.target_land = Some(value.parse().map_err(|_| invalid())?),
+            "-width" => self.width = value.parse().map_err(|_| invalid())?,
+            "-height" => self.height = value.parse().map_err(|_| invalid())?,
+            "-octaves" => self.noise.octaves = value.parse().map_err(|_| invalid())?,
+            "-gain" => self.noise.gain = value.parse().map_err(|_| invalid())?,
+            "-lacunarity" => self.noise.lacunarity = value.parse().map_err(|_| invalid())?,
+            "-frequency" => self.noise.frequency = value.parse().map_err(|_| invalid())?,
+            "-x_scale" => self.noise.x_scale = value.parse().map_err(|_| invalid())?,
+            "-y_scale" => self.noise.y_scale = value.parse().map_err(|_| invalid())?,
+            "-sea_level" => {
+                let sea_level: f32 = value.parse().map_err(|_| invalid())?;
+                self.coast_level += sea_level - self.sea_level;
+                self.sea_level = sea_level;
+            }
+            "-mountain_level" => {
+                let mountain_level: f32 = value.parse().map_err(|_| invalid())?;
+                self.hills_level += mountain_level - self.mountain_level;
+                self.mountain_level = mountain_level;
+            }
+            _ => return Err(invalid()),
+        }
+        Ok(())
+    }
+
+    /// How many tiles a map generated with this config has
+    pub fn tile_count(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    /// Checks the settings can actually generate a playable map
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.width < MIN_MAP_SIZE || self.height < MIN_MAP_SIZE {
+            return Err(ConfigError::MapTooSmall {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        if self.width > MAX_MAP_SIZE || self.height > MAX_MAP_SIZE {
+            return Err(ConfigError::MapTooBig {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        if self.noise.octaves < 1 {
+            return Err(ConfigError::InvalidNoise(
+                "there needs to be at least one octave".to_string(),
+            ));
+        }
+        let noise_values = [
+            self.noise.gain,
+            self.noise.lacunarity,
+            self.noise.frequency,
+            self.noise.x_scale,
+            self.noise.y_scale,
+            self.falloff_strength,
+        ];
+        if noise_values.iter().any(|value| !value.is_finite()) {
+            return Err(ConfigError::InvalidNoise(
+                "the noise settings need to be actual numbers".to_string(),
+            ));
+        }
+        if self.noise.x_scale <= 0.0 || self.noise.y_scale <= 0.0 {
+            return Err(ConfigError::InvalidNoise(
+                "the scales need to be above zero".to_string(),
+            ));
+        }
+        if !(self.coast_level <= self.sea_level
+            && self.sea_level <= self.hills_level
+            && self.hills_level <= self.mountain_level)
+        {
+            return Err(ConfigError::LevelsOutOfOrder);
+        }
+        if let Some(target) = self.target_land {
+            if !(MIN_TARGET_LAND..=MAX_TARGET_LAND).contains(&target) {
+                return Err(ConfigError::InvalidTargetLand(target));
+            }
+        }
+        if self.falloff_strength < 0.0 {
+            return Err(ConfigError::InvalidNoise(
+                "the falloff strength can't be below zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_sizes_are_bounded() {
+        let config = GeneratorConfig::default();
+        assert!(config.validate().is_ok());
+
+        let small = GeneratorConfig {
+            width: MIN_MAP_SIZE - 1,
+            ..GeneratorConfig::default()
+        };
+        assert!(matches!(
+            small.validate(),
+            Err(ConfigError::MapTooSmall { .. })
+        ));
+
+        // Big enough that the number of tiles wouldn't fit
+        let big = GeneratorConfig {
+            width: i32::MAX,
+            height: i32::MAX,
+            ..GeneratorConfig::default()
+        };
+        assert!(matches!(big.validate(), Err(ConfigError::MapTooBig { .. })));
+    }
+
+    #[test]
+    fn noise_settings_have_to_be_finite() {
+        let mut config = GeneratorConfig::default();
+        config.set_flag("-x_scale", "NaN").unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidNoise(_))
+        ));
+
+        for value in ["inf", "-inf", "NaN"].iter() {
+            let mut config = GeneratorConfig::default();
+            config.set_flag("-gain", value).unwrap();
+            assert!(matches!(
+                config.validate(),
+                Err(ConfigError::InvalidNoise(_))
+            ));
+        }
+    }
+}
@@ -10,42 +10,88 @@
 //! enums in the units/forts to denote ownership and use marker traits to tell other functions
 //! which forts/units are currently selected by the player
 
-use crate::gui::{MenuResult, SelectionType, draw_selection_box, draw_selection_options};
-use crate::PlayerOrder;
-use crate::{Fort, Name, State, selection};
+use crate::gui::{
+    draw_selection_box, draw_selection_options, SELECTION_LEFT, SELECTION_TOP, SELECTION_WIDTH,
+};
+use crate::{production_per_turn, Fort, Name, Selected, State, FORT_MAX_DEFENSE};
 use bracket_lib::prelude::*;
 use specs::prelude::*;
 
 use super::select_player;
 
-/// Used for printing out a list of the units a player currently has and is able to move
-pub fn fort_list(gs: &mut State, ctx: &mut BTerm) -> MenuResult {
-	let bg = RGB::named(BLACK);
-    let y = 15;
-
+/// Gets the forts owned by the player whose turn it is, in the order they're listed in the menu
+pub fn owned_forts(ecs: &World) -> Vec<(Entity, String)> {
     let mut player_forts: Vec<(Entity, String)> = Vec::new();
-	{
-		let forts = gs.ecs.read_storage::<Fort>();
-		let names = gs.ecs.read_storage::<Name>();
-		let entities = gs.ecs.entities();
-
-		let player_enum: Option<PlayerOrder> = select_player(&gs.ecs);
-
-		let player_enum = player_enum.unwrap();	
-
-        draw_selection_box(ctx, "Fort List".to_string());
+	let forts = ecs.read_storage::<Fort>();
+	let names = ecs.read_storage::<Name>();
+	let entities = ecs.entities();
 
+	if let Some(player_enum) = select_player(ecs) {
 		for (_fort, name, entity) in (&forts, &names, &entities)
 			.join()
 			.filter(|fort| fort.0.owner == player_enum)
 		{
 			player_forts.push((entity, name.name.to_string()));	
 		}
-
 	}
-    ctx.draw_box(14, y + 9, 30, 3, RGB::named(WHITE), bg);
-    ctx.print_color(16, y + 10, RGB::named(YELLOW), bg, format!("Selection: {}", gs.selected));
+	player_forts
+}
+
+/// Used for printing out a list of the forts a player currently has
+pub fn fort_list(gs: &mut State, ctx: &mut BTerm) {
+	let bg = RGB::named(BLACK);
+    let y = SELECTION_TOP;
+
+	let player_forts = owned_forts(&gs.ecs);
+    draw_selection_box(ctx, "Fort List".to_string());
+
+    ctx.draw_box(SELECTION_LEFT, y + 9, SELECTION_WIDTH, 3, RGB::named(WHITE), bg);
+    let selection = format!("Selection: {}", gs.selected);
+    ctx.print_color(SELECTION_LEFT + 2, y + 10, RGB::named(YELLOW), bg, selection);
     draw_selection_options(gs, ctx, &player_forts);	
+}
+
+/// Draws the screen for the selected fort, showing the fort's production queue along with the
+/// keys for changing it
+pub fn fort_screen(gs: &mut State, ctx: &mut BTerm) {
+	let bg = RGB::named(BLACK);
+	let (x, y) = (14, 13);
+	let forts = gs.ecs.read_storage::<Fort>();
+	let names = gs.ecs.read_storage::<Name>();
+	let selects = gs.ecs.read_storage::<Selected>();
+
+	ctx.draw_box(x, y, 30, 17, RGB::named(WHITE), bg);
+
+	let entities = gs.ecs.entities();
+
+	if let Some((entity, fort, name, _selected)) =
+		(&entities, &forts, &names, &selects).join().next()
+	{
+		ctx.print_color(x + 4, y, RGB::named(YELLOW), bg, name.name.to_string());
+		ctx.print_color(x + 2, y + 2, RGB::named(BURLYWOOD3), bg,
+			format!("Defense: {}/{}", fort.defense, FORT_MAX_DEFENSE));
+		ctx.print_color(x + 2, y + 3, RGB::named(BURLYWOOD3), bg,
+			format!("Production: {} per turn", production_per_turn(&gs.ecs, entity)));
+		ctx.print_color(x + 2, y + 4, RGB::named(WHITE), bg, "Queue:".to_string());
+
+		if fort.queue.is_empty() {
+			ctx.print_color(x + 3, y + 5, RGB::named(GRAY), bg, "Nothing queued".to_string());
+		}
+
+		// Only the item at the front of the queue has production points put into it
+		for (i, item) in fort.queue.iter().enumerate() {
+			let progress = if i == 0 { fort.progress } else { 0 };
+			let line = y + 5 + i;
+			if i as u32 == gs.last_option {
+				ctx.set(x + 2, line, RGB::named(WHITE), bg, to_cp437('>'));
+			}
+			ctx.print_color(x + 3, line, RGB::named(YELLOW), bg, format!("{})", i + 1));
+			ctx.print(x + 6, line, format!("{} {}/{}", item.name(), progress, item.cost()));
+		}
+	}
 
-	selection(gs, ctx, player_forts, SelectionType::Fort)
+	ctx.print_color(x + 2, y + 13, RGB::named(GRAY), bg, "[B] Unit  [S] Swordsman".to_string());
+	ctx.print_color(x + 2, y + 14, RGB::named(GRAY), bg, "[Up/Down] Pick an entry".to_string());
+	ctx.print_color(x + 2, y + 15, RGB::named(GRAY), bg, "[[/]] Move  [X] Cancel".to_string());
+	ctx.print_color(x + 2, y + 16, RGB::named(GRAY), bg, "[I] Back to the cursor".to_string());
 }
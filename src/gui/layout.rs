@@ -0,0 +1,110 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Works out where each part of the screen goes from the size of the console,
+//! so the window can be resized or opened at a bigger size. The sidebar and the
+//! message log keep their size, and the map view gets whatever's left over.
+
+use bracket_lib::prelude::*;
+
+/// The size of the console the window opens with when no size is given
+pub const DEFAULT_CONSOLE_WIDTH: u32 = 80;
+pub const DEFAULT_CONSOLE_HEIGHT: u32 = 50;
+
+/// The smallest console everything fits in, smaller windows get the bottom right cut off
+pub const MIN_CONSOLE_WIDTH: u32 = 80;
+pub const MIN_CONSOLE_HEIGHT: u32 = 50;
+
+/// How many columns the sidebar takes up, counting its border
+const SIDEBAR_WIDTH: i32 = 20;
+
+/// How many rows the message log takes up, counting its border
+const LOG_HEIGHT: i32 = 10;
+
+/// Where each part of the screen goes, the sidebar runs down the right side and the message
+/// log runs along the bottom under the map view
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Layout {
+    /// The whole console, used by the screens that cover everything
+    pub screen: Rect,
+    /// The part of the screen the map is drawn in
+    pub viewport: Rect,
+    pub sidebar: Rect,
+    pub log: Rect,
+}
+
+impl Layout {
+    /// Lays the screen out for a console with the given number of columns and rows
+    pub fn new(width: u32, height: u32) -> Layout {
+        let width = width.max(MIN_CONSOLE_WIDTH) as i32;
+        let height = height.max(MIN_CONSOLE_HEIGHT) as i32;
+        let view_width = width - SIDEBAR_WIDTH;
+        let view_height = height - LOG_HEIGHT;
+
+        Layout {
+            screen: Rect::with_size(0, 0, width, height),
+            viewport: Rect::with_size(0, 0, view_width, view_height),
+            sidebar: Rect::with_size(view_width, 0, SIDEBAR_WIDTH, height),
+            log: Rect::with_size(0, view_height, view_width, LOG_HEIGHT),
+        }
+    }
+
+    /// Lays the screen out for the console as it's currently sized
+    pub fn of(ctx: &BTerm) -> Layout {
+        let (width, height) = ctx.get_char_size();
+        Layout::new(width, height)
+    }
+}
+
+/// Draws a box filling the given part of the screen, with its border along the outer edges
+pub fn draw_frame(ctx: &mut BTerm, area: Rect, fg: RGB, bg: RGB) {
+    ctx.draw_box(
+        area.x1,
+        area.y1,
+        area.width() - 1,
+        area.height() - 1,
+        fg,
+        bg,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_consoles_get_the_smallest_layout() {
+        let layout = Layout::new(10, 10);
+        assert_eq!(layout, Layout::new(MIN_CONSOLE_WIDTH, MIN_CONSOLE_HEIGHT));
+        assert_eq!(layout.screen, Rect::with_size(0, 0, 80, 50));
+        assert_eq!(layout.viewport, Rect::with_size(0, 0, 60, 40));
+        assert_eq!(layout.sidebar, Rect::with_size(60, 0, 20, 50));
+        assert_eq!(layout.log, Rect::with_size(0, 40, 60, 10));
+
+        // Only the side that's too small gets clamped
+        let tall = Layout::new(10, 70);
+        assert_eq!(tall.screen, Rect::with_size(0, 0, 80, 70));
+        assert_eq!(tall.viewport, Rect::with_size(0, 0, 60, 60));
+    }
+
+    #[test]
+    fn bigger_consoles_give_the_extra_room_to_the_map() {
+        let layout = Layout::new(120, 70);
+        assert_eq!(layout.screen, Rect::with_size(0, 0, 120, 70));
+        assert_eq!(layout.viewport, Rect::with_size(0, 0, 100, 60));
+        assert_eq!(layout.sidebar, Rect::with_size(100, 0, 20, 70));
+        assert_eq!(layout.log, Rect::with_size(0, 60, 100, 10));
+
+        // Every spot on the screen belongs to exactly one part of it
+        let parts = [layout.viewport, layout.sidebar, layout.log];
+        for y in 0..70 {
+            for x in 0..120 {
+                let point = Point::new(x, y);
+                let covering = parts
+                    .iter()
+                    .filter(|part| part.point_in_rect(point))
+                    .count();
+                assert_eq!(covering, 1, "({}, {})", x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! The full screen message log, which lets the player scroll back through every
+//! message written during the game instead of just the newest few. Messages can
+//! be hidden by their type, and picking a message that happened somewhere on the
+//! map moves the cursor over to it. Other players' messages don't say where they
+//! happened, so they can't be used to find out what's going on under the fog.
+
+use crate::command::{Command, Outcome};
+use crate::gui::layout::{draw_frame, Layout};
+use crate::gui::{revealed_to_player, select_player};
+use crate::{teleport_player, GameLog, MessageType, PlayerOrder, RunState, State};
+use bracket_lib::prelude::*;
+
+/// How many messages page up and page down move the highlight by, which is how many fit on
+/// the smallest screen
+pub const LOG_ROWS: u32 = 42;
+
+const LOG_TOP: u32 = 4;
+
+/// Carries out a command given while the message log is open, letting the player scroll
+/// through the messages, hide or show types of messages, jump the cursor to a message, or
+/// close the log
+pub fn log_command(gs: &mut State, command: Command) -> Outcome {
+    let viewer = select_player(&gs.ecs).unwrap_or(PlayerOrder::NoPlayer);
+    let shown = gs.ecs.fetch::<GameLog>().filtered(&gs.log_filter, viewer);
+    let count = shown.len() as u32;
+
+    match command {
+        Command::Cancel => {
+            gs.runstate = RunState::MoveCursor;
+            Outcome::Applied
+        }
+        Command::ScrollLog(amount) => {
+            let last = count.saturating_sub(1) as i64;
+            gs.last_option = (gs.last_option as i64 + amount as i64).clamp(0, last) as u32;
+            Outcome::Applied
+        }
+        Command::ToggleFilter(message_type) => {
+            gs.log_filter.toggle(message_type);
+            // Keeping the highlight on a message that's still shown
+            let count = gs
+                .ecs
+                .fetch::<GameLog>()
+                .filtered(&gs.log_filter, viewer)
+                .len() as u32;
+            gs.last_option = gs.last_option.min(count.saturating_sub(1));
+            Outcome::Applied
+        }
+        Command::Confirm => {
+            let position = shown
+                .get(gs.last_option as usize)
+                .and_then(|idx| gs.ecs.fetch::<GameLog>().events[*idx].position_for(viewer));
+            // The cursor can't be used to look at tiles the player hasn't explored yet
+            match position {
+                Some(pos) if revealed_to_player(&gs.ecs, pos) => {
+                    teleport_player(pos, &mut gs.ecs);
+                    gs.runstate = RunState::MoveCursor;
+                    Outcome::Applied
+                }
+                _ => Outcome::Failed,
+            }
+        }
+        _ => Outcome::Ignored,
+    }
+}
+
+/// Draws the message log over the whole screen, newest messages first, with the key for
+/// each type of message along the top showing which ones are hidden
+pub fn log_viewer(gs: &mut State, ctx: &mut BTerm) {
+    let bg = RGB::named(BLACK);
+    let viewer = select_player(&gs.ecs).unwrap_or(PlayerOrder::NoPlayer);
+    let log = gs.ecs.fetch::<GameLog>();
+    let shown = log.filtered(&gs.log_filter, viewer);
+    let screen = Layout::of(ctx).screen;
+    // Bigger screens fit more messages, leaving room for the key along the top and the border
+    let rows = (screen.height() - 8) as u32;
+    let width = (screen.width() - 4) as usize;
+
+    draw_frame(ctx, screen, RGB::named(WHITE), bg);
+    ctx.print_color(2, 0, RGB::named(YELLOW), bg, "[Message History]");
+
+    let mut x = 2;
+    for (key, message_type) in MessageType::ALL.iter().enumerate() {
+        let fg = if gs.log_filter.shows(*message_type) {
+            message_type.color()
+        } else {
+            RGB::named(DIMGRAY)
+        };
+        let label = format!("{}:{}", key + 1, message_type.name());
+        ctx.print_color(x, 2, fg, bg, &label);
+        x += label.len() + 2;
+    }
+
+    // The log is shown a page at a time, with the page flipping once the highlight leaves it
+    let first = (gs.last_option / rows) * rows;
+    for (row, idx) in shown
+        .iter()
+        .enumerate()
+        .skip(first as usize)
+        .take(rows as usize)
+    {
+        let y = LOG_TOP + row as u32 - first;
+        let highlighted = row as u32 == gs.last_option;
+        let row_bg = if highlighted {
+            RGB::named(DARKSLATEGRAY)
+        } else {
+            bg
+        };
+
+        let event = &log.events[*idx];
+        let place = match event.position_for(viewer) {
+            Some(pos) => format!("({}, {})", pos.x, pos.y),
+            None => String::new(),
+        };
+        let mut line = format!("T{:<4} {:<11}{}", event.turn, place, event.message);
+        line = line.chars().take(width).collect();
+
+        if highlighted {
+            ctx.set(1, y, RGB::named(WHITE), bg, to_cp437('>'));
+        }
+        ctx.print_color(2, y, event.kind.color(), row_bg, &line);
+    }
+
+    if shown.is_empty() {
+        ctx.print_color(2, LOG_TOP, RGB::named(GRAY), bg, "No messages to show");
+    }
+
+    ctx.print_color(
+        2,
+        screen.y2 - 1,
+        RGB::named(YELLOW),
+        bg,
+        "UP/DOWN/PGUP/PGDN scroll, 1-6 filter, ENTER jump, ESCAPE close",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::apply;
+    use crate::{FogOfWar, LogEvent, Map, Position};
+    use specs::prelude::*;
+
+    /// Finds a tile the active player has or hasn't explored yet
+    fn find_tile(gs: &State, revealed: bool) -> Position {
+        let player_entity = *gs.ecs.fetch::<Entity>();
+        let fogs = gs.ecs.read_storage::<FogOfWar>();
+        let fog = fogs.get(player_entity).unwrap();
+        let map = gs.ecs.fetch::<Map>();
+        let idx = (0..map.tiles.len())
+            .find(|idx| fog.revealed_tiles[*idx] == revealed)
+            .unwrap();
+        Position {
+            x: idx as i32 % map.width,
+            y: idx as i32 / map.width,
+        }
+    }
+
+    /// Opens the log with the newest message highlighted
+    fn open_log_with(gs: &mut State, event: LogEvent) {
+        gs.ecs.fetch_mut::<GameLog>().push(event);
+        gs.runstate = RunState::ShowLog;
+        gs.last_option = 0;
+    }
+
+    fn cursor(gs: &State) -> Point {
+        *gs.ecs.fetch::<Point>()
+    }
+
+    #[test]
+    fn jumping_to_an_explored_tile_moves_the_cursor() {
+        let mut gs = State::test_game(3);
+        let viewer = select_player(&gs.ecs).unwrap();
+        let pos = find_tile(&gs, true);
+        let message = "Explored".to_string();
+        open_log_with(
+            &mut gs,
+            LogEvent::new(MessageType::Move, viewer, message).at(pos),
+        );
+
+        assert_eq!(apply(&mut gs, Command::Confirm), Outcome::Applied);
+        assert_eq!(gs.runstate, RunState::MoveCursor);
+        assert_eq!(cursor(&gs), Point::new(pos.x, pos.y));
+    }
+
+    #[test]
+    fn jumping_to_an_unexplored_tile_is_refused() {
+        let mut gs = State::test_game(3);
+        let viewer = select_player(&gs.ecs).unwrap();
+        let start = cursor(&gs);
+        let pos = find_tile(&gs, false);
+        let message = "Unexplored".to_string();
+        open_log_with(
+            &mut gs,
+            LogEvent::new(MessageType::Move, viewer, message).at(pos),
+        );
+
+        assert_eq!(apply(&mut gs, Command::Confirm), Outcome::Failed);
+        assert_eq!(gs.runstate, RunState::ShowLog);
+        assert_eq!(cursor(&gs), start);
+    }
+
+    #[test]
+    fn other_players_events_dont_give_away_where_they_happened() {
+        let mut gs = State::test_game(3);
+        let viewer = select_player(&gs.ecs).unwrap();
+        let other = PlayerOrder::TURN_ORDER
+            .iter()
+            .copied()
+            .find(|order| *order != viewer)
+            .unwrap();
+        let pos = find_tile(&gs, true);
+        let filter = gs.log_filter.clone();
+
+        // Their builds aren't shown at all since the message says where it happened
+        let built = LogEvent::new(MessageType::Build, other, "Built".to_string()).at(pos);
+        gs.ecs.fetch_mut::<GameLog>().push(built);
+        let newest = gs.ecs.fetch::<GameLog>().events.len() - 1;
+        assert!(!gs
+            .ecs
+            .fetch::<GameLog>()
+            .filtered(&filter, viewer)
+            .contains(&newest));
+
+        // Fights are shown, but can't be jumped to
+        let fight = LogEvent::new(MessageType::Combat, other, "Hit".to_string()).at(pos);
+        open_log_with(&mut gs, fight);
+        let newest = gs.ecs.fetch::<GameLog>().events.len() - 1;
+        assert_eq!(
+            gs.ecs.fetch::<GameLog>().filtered(&filter, viewer)[0],
+            newest
+        );
+        assert!(gs.ecs.fetch::<GameLog>().events[newest]
+            .position_for(viewer)
+            .is_none());
+        assert_eq!(apply(&mut gs, Command::Confirm), Outcome::Failed);
+    }
+
+    #[test]
+    fn turn_changes_dont_say_where_the_next_player_is() {
+        let mut gs = State::test_game(3);
+        assert_eq!(apply(&mut gs, Command::EndTurn), Outcome::Applied);
+
+        let log = gs.ecs.fetch::<GameLog>();
+        let event = log.events.back().unwrap();
+        assert!(event.message.contains("turn"));
+        assert!(event.position.is_none());
+    }
+}
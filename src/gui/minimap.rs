@@ -0,0 +1,429 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Draws the world shrunk down so the whole map fits on the screen at once, each
+//! cell standing in for a block of tiles. The sidebar shows a small one, and the
+//! overview screen shows a bigger one that the player can pick a spot on to move
+//! the cursor there. Only what the player has revealed is drawn, along with the
+//! territory the players have claimed and the part of the map in view.
+
+use crate::camera::{get_tile_glyph, view_area};
+use crate::command::{Command, Outcome};
+use crate::gui::layout::{draw_frame, Layout};
+use crate::{teleport_player, FogOfWar, Map, PlayerOrder, Position, RunState, State, TileType};
+use bracket_lib::prelude::*;
+use specs::prelude::*;
+
+/// How many cells the overview screen can take up, the overview keeps this size on bigger
+/// screens since moving the marker around doesn't know what the screen looks like
+pub const OVERVIEW_COLUMNS: i32 = 78;
+pub const OVERVIEW_ROWS: i32 = 44;
+
+/// How many tiles along each side of a cell get looked at to pick the cell's terrain
+const TERRAIN_SAMPLES: i32 = 6;
+
+/// How a map gets split up into cells to fit in a given space. Cells are square so the map
+/// keeps its shape, which can leave some of the space unused along one side.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Minimap {
+    pub cell_size: i32,
+    pub columns: i32,
+    pub rows: i32,
+}
+
+impl Minimap {
+    /// Picks the smallest cells that let the whole map fit in the given number of columns
+    /// and rows
+    pub fn fit(map: &Map, max_columns: i32, max_rows: i32) -> Minimap {
+        let cell_size = ceil_div(map.width, max_columns).max(ceil_div(map.height, max_rows));
+        Minimap {
+            cell_size,
+            columns: ceil_div(map.width, cell_size),
+            rows: ceil_div(map.height, cell_size),
+        }
+    }
+
+    /// Gets the cell the tile falls in
+    pub fn cell_of(&self, x: i32, y: i32) -> Point {
+        Point::new(x / self.cell_size, y / self.cell_size)
+    }
+
+    /// Gets the tiles a cell covers, cells along the right and bottom edges can hang off of
+    /// the map so they get cut down to fit
+    pub fn tiles_in(&self, map: &Map, cell: Point) -> Rect {
+        let x = cell.x * self.cell_size;
+        let y = cell.y * self.cell_size;
+        Rect::with_exact(
+            x,
+            y,
+            (x + self.cell_size).min(map.width),
+            (y + self.cell_size).min(map.height),
+        )
+    }
+}
+
+fn ceil_div(value: i32, divisor: i32) -> i32 {
+    ((value + divisor - 1) / divisor).max(1)
+}
+
+/// The glyph and colours a cell gets drawn with
+type CellGlyph = (FontCharType, RGB, RGB);
+
+/// The cells each minimap was last drawn with for each player, so the map only gets looked
+/// over again once something on it has changed. Only commands can claim tiles or reveal more
+/// of the map, so the cache gets cleared after every command.
+#[derive(Default)]
+pub struct MinimapCache {
+    drawn: Vec<(Entity, Minimap, Vec<Option<CellGlyph>>)>,
+}
+
+impl MinimapCache {
+    pub fn clear(&mut self) {
+        self.drawn.clear();
+    }
+
+    /// Gets every cell of the minimap as the player knows it in rows from the top left,
+    /// working them out if they haven't been since the cache was last cleared
+    fn cells(
+        &mut self,
+        map: &Map,
+        fog: &FogOfWar,
+        player: Entity,
+        minimap: &Minimap,
+    ) -> &[Option<CellGlyph>] {
+        let found = self
+            .drawn
+            .iter()
+            .position(|(entity, drawn, _)| *entity == player && drawn == minimap);
+        let idx = match found {
+            Some(idx) => idx,
+            None => {
+                let cells = (0..minimap.rows)
+                    .flat_map(|row| (0..minimap.columns).map(move |column| Point::new(column, row)))
+                    .map(|cell| cell_glyph(map, fog, minimap.tiles_in(map, cell)))
+                    .collect();
+                self.drawn.push((player, *minimap, cells));
+                self.drawn.len() - 1
+            }
+        };
+        &self.drawn[idx].2
+    }
+}
+
+/// Works out what a cell gets drawn as: the most common terrain among the revealed tiles it
+/// covers in that terrain's colour, hatched over with the colour of whoever claimed the most
+/// of it. Cells with nothing revealed in them aren't drawn.
+fn cell_glyph(map: &Map, fog: &FogOfWar, tiles: Rect) -> Option<CellGlyph> {
+    // Claims are small enough to vanish between samples, so every tile gets checked for them
+    let mut claims: Vec<(PlayerOrder, usize)> = Vec::new();
+    for y in tiles.y1..tiles.y2 {
+        for x in tiles.x1..tiles.x2 {
+            let idx = map.xy_idx(x, y);
+            let owner = map.claimed_tiles[idx];
+            if owner == PlayerOrder::NoPlayer || !fog.revealed_tiles[idx] {
+                continue;
+            }
+            match claims.iter_mut().find(|(order, _)| *order == owner) {
+                Some((_, count)) => *count += 1,
+                None => claims.push((owner, 1)),
+            }
+        }
+    }
+
+    let step = (tiles.width().max(tiles.height()) / TERRAIN_SAMPLES).max(1);
+    let mut terrain: Vec<(TileType, usize, usize)> = Vec::new();
+    for y in (tiles.y1..tiles.y2).step_by(step as usize) {
+        for x in (tiles.x1..tiles.x2).step_by(step as usize) {
+            let idx = map.xy_idx(x, y);
+            if !fog.revealed_tiles[idx] {
+                continue;
+            }
+            let tile = map.tiles[idx];
+            match terrain
+                .iter_mut()
+                .find(|(tile_type, _, _)| *tile_type == tile)
+            {
+                Some((_, count, _)) => *count += 1,
+                None => terrain.push((tile, 1, idx)),
+            }
+        }
+    }
+
+    let (_, _, idx) = terrain.iter().max_by_key(|(_, count, _)| *count)?;
+    let (_, fg, _) = get_tile_glyph(*idx, map, None);
+
+    match claims.iter().max_by_key(|(_, count)| *count) {
+        Some((owner, _)) => Some((to_cp437('▒'), owner.claim_color(), fg)),
+        None => Some((to_cp437('█'), fg, RGB::named(BLACK))),
+    }
+}
+
+/// Draws the map as the player whose turn it is knows it, shrunk down with its top left
+/// corner at the given spot. The cells the map view covers are lightened to show where on
+/// the map the player is looking.
+pub fn draw_minimap(ecs: &World, ctx: &mut BTerm, x: i32, y: i32, minimap: &Minimap) {
+    let map = ecs.fetch::<Map>();
+    let fogs = ecs.read_storage::<FogOfWar>();
+    let player_entity = ecs.fetch::<Entity>();
+    let fog = match fogs.get(*player_entity) {
+        Some(fog) => fog,
+        None => return,
+    };
+
+    let mut cache = ecs.fetch_mut::<MinimapCache>();
+    let cells = cache.cells(&map, fog, *player_entity, minimap);
+
+    let view = view_area(*ecs.fetch::<Point>(), Layout::of(ctx).viewport);
+    let view_start = minimap.cell_of(view.x1.max(0), view.y1.max(0));
+    let view_end = minimap.cell_of(
+        (view.x2 - 1).clamp(0, map.width - 1),
+        (view.y2 - 1).clamp(0, map.height - 1),
+    );
+
+    for row in 0..minimap.rows {
+        for column in 0..minimap.columns {
+            let in_view = (view_start.x..=view_end.x).contains(&column)
+                && (view_start.y..=view_end.y).contains(&row);
+
+            match cells[(row * minimap.columns + column) as usize] {
+                Some((glyph, fg, bg)) if in_view => ctx.set(
+                    x + column,
+                    y + row,
+                    fg.lerp(RGB::named(WHITE), 0.5),
+                    bg.lerp(RGB::named(WHITE), 0.5),
+                    glyph,
+                ),
+                Some((glyph, fg, bg)) => ctx.set(x + column, y + row, fg, bg, glyph),
+                None if in_view => ctx.set(
+                    x + column,
+                    y + row,
+                    RGB::named(GRAY),
+                    RGB::named(BLACK),
+                    to_cp437('░'),
+                ),
+                None => {}
+            }
+        }
+    }
+}
+
+/// Splits the map up to fit the overview screen
+pub fn overview_minimap(ecs: &World) -> Minimap {
+    Minimap::fit(&ecs.fetch::<Map>(), OVERVIEW_COLUMNS, OVERVIEW_ROWS)
+}
+
+/// Finds the revealed tile in the cell closest to the middle of it that the cursor is
+/// allowed to stand on
+fn landing_tile(ecs: &World, minimap: &Minimap, cell: Point) -> Option<Position> {
+    let map = ecs.fetch::<Map>();
+    let fogs = ecs.read_storage::<FogOfWar>();
+    let player_entity = ecs.fetch::<Entity>();
+    let fog = fogs.get(*player_entity)?;
+
+    let tiles = minimap.tiles_in(&map, cell);
+    let center = tiles.center();
+    let mut best: Option<(f32, Position)> = None;
+    for y in tiles.y1..tiles.y2 {
+        for x in tiles.x1..tiles.x2 {
+            let idx = map.xy_idx(x, y);
+            if !fog.revealed_tiles[idx] || map.tiles[idx] == TileType::Ice {
+                continue;
+            }
+            let distance = DistanceAlg::Pythagoras.distance2d(center, Point::new(x, y));
+            if best.map(|(closest, _)| distance < closest).unwrap_or(true) {
+                best = Some((distance, Position { x, y }));
+            }
+        }
+    }
+    best.map(|(_, pos)| pos)
+}
+
+/// Carries out a command given while the overview is open, letting the player move the
+/// marker around the cells, move the cursor to the marked cell, or close the overview
+pub fn overview_command(gs: &mut State, command: Command) -> Outcome {
+    let minimap = overview_minimap(&gs.ecs);
+
+    match command {
+        Command::MoveCursor(delta_x, delta_y) => {
+            gs.overview_marker.x = (gs.overview_marker.x + delta_x).clamp(0, minimap.columns - 1);
+            gs.overview_marker.y = (gs.overview_marker.y + delta_y).clamp(0, minimap.rows - 1);
+            Outcome::Applied
+        }
+        Command::Confirm => match landing_tile(&gs.ecs, &minimap, gs.overview_marker) {
+            Some(pos) => {
+                teleport_player(pos, &mut gs.ecs);
+                gs.runstate = RunState::MoveCursor;
+                Outcome::Applied
+            }
+            None => Outcome::Failed,
+        },
+        Command::Cancel => {
+            gs.runstate = RunState::MoveCursor;
+            Outcome::Applied
+        }
+        _ => Outcome::Ignored,
+    }
+}
+
+/// Draws the whole map across the screen with a marker on the cell the cursor will be moved
+/// to, the map is centered in the space it has
+pub fn overview_screen(gs: &mut State, ctx: &mut BTerm) {
+    let bg = RGB::named(BLACK);
+    let screen = Layout::of(ctx).screen;
+    let minimap = overview_minimap(&gs.ecs);
+    let x = screen.x1 + 1 + (screen.width() - 2 - minimap.columns) / 2;
+    let y = screen.y1 + 2 + (screen.height() - 6 - minimap.rows) / 2;
+
+    draw_frame(ctx, screen, RGB::named(WHITE), bg);
+    ctx.print_color(2, 0, RGB::named(YELLOW), bg, "[World Overview]");
+    draw_minimap(&gs.ecs, ctx, x, y, &minimap);
+
+    let marker = gs.overview_marker;
+    ctx.set(
+        x + marker.x,
+        y + marker.y,
+        RGB::named(BLACK),
+        RGB::named(MAGENTA),
+        to_cp437('X'),
+    );
+
+    ctx.print_color(
+        2,
+        screen.y2 - 1,
+        RGB::named(YELLOW),
+        bg,
+        "W/A/S/D move the marker, ENTER jump, ESCAPE close",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::apply;
+
+    /// Reveals the whole map to the player whose turn it is
+    fn reveal_all(gs: &mut State) {
+        let player_entity = *gs.ecs.fetch::<Entity>();
+        let mut fogs = gs.ecs.write_storage::<FogOfWar>();
+        let fog = fogs.get_mut(player_entity).unwrap();
+        fog.revealed_tiles
+            .iter_mut()
+            .for_each(|revealed| *revealed = true);
+    }
+
+    #[test]
+    fn maps_are_split_into_square_cells_that_fit() {
+        let gs = State::test_arena(400, 300);
+        let map = gs.ecs.fetch::<Map>();
+        let minimap = Minimap::fit(&map, OVERVIEW_COLUMNS, OVERVIEW_ROWS);
+        assert_eq!(
+            minimap,
+            Minimap {
+                cell_size: 7,
+                columns: 58,
+                rows: 43,
+            }
+        );
+
+        // The last cells hang off the map, so they're cut down to the tiles that are left
+        let corner = minimap.tiles_in(&map, Point::new(57, 42));
+        assert_eq!(
+            (corner.x1, corner.y1, corner.x2, corner.y2),
+            (399, 294, 400, 300)
+        );
+        assert_eq!(minimap.cell_of(399, 299), Point::new(57, 42));
+
+        let gs = State::test_arena(20, 20);
+        let small = Minimap::fit(&gs.ecs.fetch::<Map>(), OVERVIEW_COLUMNS, OVERVIEW_ROWS);
+        assert_eq!(
+            small,
+            Minimap {
+                cell_size: 1,
+                columns: 20,
+                rows: 20,
+            }
+        );
+    }
+
+    #[test]
+    fn cells_show_the_most_common_terrain_and_claims() {
+        let mut gs = State::test_arena(10, 10);
+        reveal_all(&mut gs);
+        {
+            let mut map = gs.ecs.fetch_mut::<Map>();
+            for x in 0..5 {
+                for y in 0..3 {
+                    let idx = map.xy_idx(x, y);
+                    map.tiles[idx] = TileType::Forest;
+                }
+            }
+            for x in 5..10 {
+                let idx = map.xy_idx(x, 0);
+                map.claimed_tiles[idx] = PlayerOrder::PlayerTwo;
+            }
+        }
+        let player_entity = *gs.ecs.fetch::<Entity>();
+        {
+            // Hiding the bottom right, claims included
+            let map = gs.ecs.fetch::<Map>();
+            let mut fogs = gs.ecs.write_storage::<FogOfWar>();
+            let fog = fogs.get_mut(player_entity).unwrap();
+            for x in 5..10 {
+                for y in 5..10 {
+                    fog.revealed_tiles[map.xy_idx(x, y)] = false;
+                }
+            }
+            let idx = map.xy_idx(5, 0);
+            fog.revealed_tiles[idx] = false;
+        }
+
+        let map = gs.ecs.fetch::<Map>();
+        let fogs = gs.ecs.read_storage::<FogOfWar>();
+        let fog = fogs.get(player_entity).unwrap();
+        let minimap = Minimap::fit(&map, 2, 2);
+        let glyph_of =
+            |x: i32, y: i32| cell_glyph(&map, fog, minimap.tiles_in(&map, Point::new(x, y)));
+        let (_, forest, _) = get_tile_glyph(0, &map, None);
+        let (_, grass, _) = get_tile_glyph(map.xy_idx(9, 9), &map, None);
+
+        assert_eq!(minimap.cell_size, 5);
+        assert_eq!(
+            glyph_of(0, 0),
+            Some((to_cp437('█'), forest, RGB::named(BLACK)))
+        );
+        assert_eq!(
+            glyph_of(0, 1),
+            Some((to_cp437('█'), grass, RGB::named(BLACK)))
+        );
+        assert_eq!(
+            glyph_of(1, 0),
+            Some((to_cp437('▒'), PlayerOrder::PlayerTwo.claim_color(), grass))
+        );
+        assert_eq!(glyph_of(1, 1), None);
+    }
+
+    #[test]
+    fn cells_are_only_worked_out_again_after_a_command() {
+        let mut gs = State::test_arena(10, 10);
+        reveal_all(&mut gs);
+        let player_entity = *gs.ecs.fetch::<Entity>();
+        let minimap = Minimap::fit(&gs.ecs.fetch::<Map>(), 1, 1);
+        let cells = |gs: &State| {
+            let map = gs.ecs.fetch::<Map>();
+            let fogs = gs.ecs.read_storage::<FogOfWar>();
+            let fog = fogs.get(player_entity).unwrap();
+            let mut cache = gs.ecs.fetch_mut::<MinimapCache>();
+            cache.cells(&map, fog, player_entity, &minimap)[0]
+        };
+
+        let before = cells(&gs);
+        for tile in gs.ecs.fetch_mut::<Map>().claimed_tiles.iter_mut() {
+            *tile = PlayerOrder::PlayerTwo;
+        }
+        assert_eq!(cells(&gs), before);
+
+        apply(&mut gs, Command::MoveCursor(1, 0));
+        let (glyph, fg, _) = cells(&gs).unwrap();
+        assert_eq!(glyph, to_cp437('▒'));
+        assert_eq!(fg, PlayerOrder::PlayerTwo.claim_color());
+    }
+}
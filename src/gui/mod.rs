@@ -6,24 +6,26 @@
 //! the fort and unit menus
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_0.html
 
+use crate::economy;
 use crate::PlayerOrder;
 use crate::{
-    xy_idx, Fort, GameLog, Map, MessageType, Moving, Name, Player, Position, TileType, Unit,
-    VIEW_HEIGHT, VIEW_WIDTH, State,
+    FogOfWar, Fort, GameLog, LogFilter, Map, Moving, Name, Player, Position, State,
+    Unit, FORT_BASE_PRODUCTION, FORT_MAX_DEFENSE,
 };
 use bracket_lib::prelude::*;
 use specs::prelude::*;
 
 pub mod fort;
+pub mod layout;
+pub mod log;
+pub mod minimap;
 pub mod unit;
 pub mod window;
 
-#[derive(PartialEq, Copy, Clone)]
-pub enum MenuResult {
-    Cancel,
-    NoResponse,
-    Selected,
-}
+pub use fort::owned_forts;
+use layout::{draw_frame, Layout};
+use minimap::{draw_minimap, Minimap};
+pub use unit::owned_units;
 
 // Not that useful atm, will be better when more types of units/forts exist
 #[derive(PartialEq, Copy, Clone)]
@@ -32,41 +34,49 @@ pub enum SelectionType {
     Fort,
 }
 
-pub fn draw_ui(ecs: &World, ctx: &mut BTerm, turns: u32) {
-    draw_sidebar(ecs, ctx, turns);
-    draw_message_box(ecs, ctx);
+/// Draws the sidebar and the message log box, the sidebar describes the tile under the mouse
+/// when there is one and falls back on the cursor or the moving unit otherwise
+pub fn draw_ui(ecs: &World, ctx: &mut BTerm, turns: u32, seed: u64, hovered: Option<Position>) {
+    let layout = Layout::of(ctx);
+    draw_sidebar(ecs, ctx, layout.sidebar, turns, seed, hovered);
+    draw_message_box(ecs, ctx, layout.log);
 }
 
+/// Gets the order of the player whose turn it currently is
 pub fn select_player(ecs: &World) -> Option<PlayerOrder> {
     let players = ecs.read_storage::<Player>();
-    let entities = ecs.entities();
-
-    let mut player_enum: Option<PlayerOrder> = None;
-
-    for (_entity, player) in (&entities, &players).join() {
-        player_enum = Some(player.order);
-    }
+    let player_entity = ecs.fetch::<Entity>();
 
-    player_enum
+    players.get(*player_entity).map(|player| player.order)
 }
 
-fn draw_sidebar(ecs: &World, ctx: &mut BTerm, turns: u32) {
+fn draw_sidebar(
+    ecs: &World,
+    ctx: &mut BTerm,
+    area: Rect,
+    turns: u32,
+    seed: u64,
+    hovered: Option<Position>,
+) {
     let positions = ecs.read_storage::<Position>();
     let players = ecs.read_storage::<Player>();
     let map = ecs.fetch::<Map>();
     let units = ecs.read_storage::<Unit>();
     let moving = ecs.read_storage::<Moving>();
+    let player_entity = ecs.fetch::<Entity>();
 
-    let x = VIEW_WIDTH;
-    let y = 0;
-    let width = 19;
-    let height = VIEW_HEIGHT + 9;
+    let x = area.x1 as usize;
+    let y = area.y1 as usize;
+    // The last row inside the border, the unit stats sit at the bottom of the sidebar
+    let bottom = (area.y2 - 2) as usize;
     let bg = RGB::named(BLACK);
     let mut pos: Position;
 
-    ctx.draw_box(x, y, width, height, RGB::named(WHITE), bg);
+    draw_frame(ctx, area, RGB::named(WHITE), bg);
 
-    for (player, cursor_pos) in (&players, &positions).join() {
+    if let (Some(player), Some(cursor_pos)) =
+        (players.get(*player_entity), positions.get(*player_entity))
+    {
         pos = *cursor_pos;
 
         // Grabbing the position of the unit in the case that theres a moving unit
@@ -75,28 +85,30 @@ fn draw_sidebar(ecs: &World, ctx: &mut BTerm, turns: u32) {
             pos = *unit_pos;
         }
 
+        // The mouse wins out over both, as long as it's over a tile the player knows about
+        if let Some(hovered_pos) = hovered.filter(|hovered| revealed_to_player(ecs, *hovered)) {
+            pos = hovered_pos;
+        }
+
         let location = format!("Pos: ({}, {})", pos.x, pos.y);
-        let tile = &map.tiles[xy_idx(pos.x, pos.y)];
-        let controlled = &map.claimed_tiles[xy_idx(pos.x, pos.y)];
-
-        let tile_str = match tile {
-            TileType::Mountain => "Mountain".to_string(),
-            TileType::Forest => "Forest".to_string(),
-            TileType::Grasslands => "Grasslands".to_string(),
-            TileType::Coast => "Coast".to_string(),
-            TileType::Water => "Water".to_string(),
-            TileType::Ice => "Ice".to_string(),
+        let idx = map.xy_idx(pos.x, pos.y);
+        let tile = &map.tiles[idx];
+        let controlled = &map.claimed_tiles[idx];
+
+        let tile_str = if map.rivers[idx] {
+            format!("{} (River)", tile.name())
+        } else {
+            tile.name()
         };
 
         let claims = match controlled {
             PlayerOrder::NoPlayer => "Unclaimed".to_string(),
-            PlayerOrder::PlayerOne => "Owner: Player1".to_string(),
-            PlayerOrder::PlayerTwo => "Owner: Player2".to_string(),
+            owner => format!("Owner: {}", owner.name()),
         };
 
         // Write out the tile type and the current position to the gui box
         ctx.print_color(x + 1, y + 1, RGB::named(YELLOW), bg, &location);
-        ctx.print_color(x + 1, y + 2, RGB::named(GREEN), bg, &tile_str.to_string());
+        ctx.print_color(x + 1, y + 2, RGB::named(GREEN), bg, &tile_str);
         ctx.print_color(x + 1, y + 3, RGB::named(ORANGE), bg, &claims);
         ctx.print_color(x + 1, y + 4, RGB::named(VIOLET), bg, format!("Current Turn: {}", turns));
 
@@ -104,9 +116,9 @@ fn draw_sidebar(ecs: &World, ctx: &mut BTerm, turns: u32) {
         ctx.print_color(
             x + 1,
             y + 5,
-            RGB::named(WHITE),
+            player.order.claim_color(),
             bg,
-            "You have: ".to_string(),
+            format!("{} has: ", player.order.name()),
         );
         ctx.print_color(
             x + 1,
@@ -123,18 +135,89 @@ fn draw_sidebar(ecs: &World, ctx: &mut BTerm, turns: u32) {
             format!("{} forts", player.fort_count),
         );
 
-        display_unit_info(ecs, ctx, x, y, pos, bg);
+        let move_cost = match map.movement_cost(idx) {
+            Some(cost) => format!("Move cost: {}", cost),
+            None => "Impassable".to_string(),
+        };
+        ctx.print_color(x + 1, y + 8, RGB::named(LIGHTBLUE), bg, move_cost);
+
+        if let Some(deposit) = map.deposits[idx] {
+            let deposit_str = format!("Deposit: {}", deposit.name());
+            ctx.print_color(x + 1, y + 12, RGB::named(LIGHTSTEELBLUE), bg, deposit_str);
+        }
+
+        // What the player has stockpiled, along with what they'll collect next turn. Production
+        // goes straight into the forts' queues, so only how much they'll get is shown for it.
+        let income = economy::income(ecs, player.order);
+        let production = income.production + FORT_BASE_PRODUCTION * player.fort_count as u32;
+        let stockpile = player.stockpile;
+        ctx.print_color(x + 1, y + 15, RGB::named(WHITE), bg, "Resources:".to_string());
+        ctx.print_color(
+            x + 1,
+            y + 16,
+            RGB::named(GREEN),
+            bg,
+            format!("Food: {} (+{})", stockpile.food, income.food),
+        );
+        ctx.print_color(
+            x + 1,
+            y + 17,
+            RGB::named(BURLYWOOD3),
+            bg,
+            format!("Prod: +{} to forts", production),
+        );
+        ctx.print_color(
+            x + 1,
+            y + 18,
+            RGB::named(GOLD),
+            bg,
+            format!("Gold: {} (+{})", stockpile.gold, income.gold),
+        );
+
+        // The seed is printed on its own line since it can be up to 20 digits long
+        ctx.print_color(x + 1, y + 20, RGB::named(GRAY), bg, "World seed:".to_string());
+        ctx.print_color(x + 1, y + 21, RGB::named(GRAY), bg, format!("{}", seed));
+
+        ctx.print_color(x + 1, y + 23, RGB::named(WHITE), bg, "Minimap (M):".to_string());
+        // The minimap gets the space between the resources and the unit stats
+        let columns = area.width() - 3;
+        let rows = (bottom as i32 - 4) - (y as i32 + 24);
+        let cells = Minimap::fit(&map, columns, rows);
+        draw_minimap(ecs, ctx, x as i32 + 1, y as i32 + 24, &cells);
+
+        display_unit_info(ecs, ctx, x, bottom, pos, bg);
         display_fort_info(ecs, ctx, x, y, pos, bg);
     }
 }
 
 
+/// Checks if the player whose turn it is can currently see the given position
+fn visible_to_player(ecs: &World, pos: Position) -> bool {
+    let fogs = ecs.read_storage::<FogOfWar>();
+    let player_entity = ecs.fetch::<Entity>();
+    let map = ecs.fetch::<Map>();
+
+    fogs.get(*player_entity)
+        .map(|fog| fog.visible_tiles[map.xy_idx(pos.x, pos.y)])
+        .unwrap_or(false)
+}
+
+/// Checks if the player whose turn it is has revealed the given position
+fn revealed_to_player(ecs: &World, pos: Position) -> bool {
+    let fogs = ecs.read_storage::<FogOfWar>();
+    let player_entity = ecs.fetch::<Entity>();
+    let map = ecs.fetch::<Map>();
+
+    fogs.get(*player_entity)
+        .map(|fog| fog.revealed_tiles[map.xy_idx(pos.x, pos.y)])
+        .unwrap_or(false)
+}
 
 fn display_unit_info(
     ecs: &World,
     ctx: &mut BTerm,
     x: usize,
-    y: usize,
+    bottom: usize,
     cursor_pos: Position,
     bg: RGB,
 ) {
@@ -142,17 +225,24 @@ fn display_unit_info(
     let positions = ecs.read_storage::<Position>();
     let names = ecs.read_storage::<Name>();
 
+    // Units hidden by the fog of war don't give away their stats
+    if !visible_to_player(ecs, cursor_pos) {
+        return;
+    }
+
     for (unit, unit_pos, unit_name) in (&units, &positions, &names).join() {
         if (unit_pos.x == cursor_pos.x) && (unit_pos.y == cursor_pos.y) {
             ctx.print_color(
                 x + 1,
-                y + 46,
+                bottom - 2,
                 RGB::named(CYAN),
                 bg,
                 format!("{} stats:", unit_name.name),
             );
+            let unit_stamina = format!("Stamina: {}", unit.stamina);
+            ctx.print_color(x + 1, bottom - 1, RGB::named(CYAN), bg, unit_stamina);
             let unit_stats = format!("Hlth: {} Str: {}", unit.health, unit.strength);
-            ctx.print_color(x + 1, y + 48, RGB::named(CYAN), bg, unit_stats);
+            ctx.print_color(x + 1, bottom, RGB::named(CYAN), bg, unit_stats);
         }
     }
 }
@@ -169,94 +259,164 @@ fn display_fort_info(
     let positions = ecs.read_storage::<Position>();
     let names = ecs.read_storage::<Name>();
 
+    let visible = visible_to_player(ecs, cursor_pos);
+    let player_order = select_player(ecs);
+
     for (fort, fort_pos, fort_name) in (&forts, &positions, &names).join() {
-        if (fort_pos.x == cursor_pos.x) && (fort_pos.y == cursor_pos.y) {
+        if (fort_pos.x == cursor_pos.x)
+            && (fort_pos.y == cursor_pos.y)
+            && (visible || Some(fort.owner) == player_order)
+        {
             let fort_info = match fort.owner {
                 PlayerOrder::NoPlayer => "Not Owned".to_string(),
-                PlayerOrder::PlayerOne => "Player1's Fort".to_string(),
-                PlayerOrder::PlayerTwo => "Player2's Fort".to_string(),
+                owner => format!("{}'s Fort", owner.name()),
             };
             ctx.print_color(x + 1, y + 9, RGB::named(BURLYWOOD3), bg, fort_info);
 
             let fort_option_name = format!("Fort name: {}", fort_name.name);
             ctx.print_color(x + 1, y + 10, RGB::named(BURLYWOOD3), bg, fort_option_name);
+
+            let fort_defense = format!("Defense: {}/{}", fort.defense, FORT_MAX_DEFENSE);
+            ctx.print_color(x + 1, y + 11, RGB::named(BURLYWOOD3), bg, fort_defense);
         }
     }
 }
 
+/// Gets the indexes of the events the message box shows the active player, newest first. The
+/// box shows every type of message, but leaves out the same events the full log does.
+fn message_box_events(ecs: &World) -> Vec<usize> {
+    let viewer = select_player(ecs).unwrap_or(PlayerOrder::NoPlayer);
+    ecs.fetch::<GameLog>().filtered(&LogFilter::default(), viewer)
+}
+
 // The code for this came from section 2.7: User Interface
 // Link: https://bfnightly.bracketproductions.com/rustbook/chapter_8.html#adding-a-message-log
-fn draw_message_box(ecs: &World, ctx: &mut BTerm) {
-    ctx.draw_box(
-        0,
-        VIEW_HEIGHT,
-        VIEW_WIDTH - 1,
-        9,
-        RGB::named(WHITE),
-        RGB::named(BLACK),
-    );
+fn draw_message_box(ecs: &World, ctx: &mut BTerm, area: Rect) {
+    draw_frame(ctx, area, RGB::named(WHITE), RGB::named(BLACK));
 
     ctx.print_color(
-        2,
-        VIEW_HEIGHT,
+        area.x1 + 2,
+        area.y1,
         RGB::named(WHITE),
         RGB::named(BLACK),
-        "[Message Log]".to_string(),
+        "[Message Log] (L to open)".to_string(),
     );
 
+    // Messages are cut short so they don't run into the sidebar
+    let width = (area.width() - 3) as usize;
+    let shown = message_box_events(ecs);
     let log = ecs.fetch::<GameLog>();
-    let mut y = VIEW_HEIGHT + 1;
-    for (message, message_type) in log.entries.iter().rev().zip(log.message_type.iter().rev()) {
-        if y < 49 {
-            let fg = match message_type {
-                MessageType::Build => RGB::named(YELLOW),
-                MessageType::Claim => RGB::named(SEAGREEN),
-                MessageType::Move => RGB::named(LIGHTBLUE),
-                MessageType::Error => RGB::named(SALMON),
-                MessageType::Other => RGB::named(WHITE),
-            };
-            ctx.print_color(2, y, fg, RGB::named(BLACK), message);
-        }
-        y += 1;
+    for (y, idx) in (area.y1 + 1..area.y2 - 1).zip(shown) {
+        let event = &log.events[idx];
+        let message: String = event.message.chars().take(width).collect();
+        ctx.print_color(area.x1 + 2, y, event.kind.color(), RGB::named(BLACK), message);
     }
 }
 
 fn draw_selection_box(ctx: &mut BTerm, title: String) {
-    let y = 15;
+    let y = SELECTION_TOP;
     let height = 10;
     let y_cord = y - 2;	
 	let bg = RGB::named(BLACK);
 
-    ctx.draw_box(14, y - 2, 30, height, RGB::named(WHITE), bg);
-    ctx.print_color(18, y_cord, RGB::named(YELLOW), bg, title);
-    ctx.print_color(18, y_cord + height, RGB::named(YELLOW), bg, "ESCAPE to cancel");
+    ctx.draw_box(SELECTION_LEFT, y - 2, SELECTION_WIDTH, height, RGB::named(WHITE), bg);
+    ctx.print_color(SELECTION_LEFT + 4, y_cord, RGB::named(YELLOW), bg, title);
+    ctx.print_color(
+        SELECTION_LEFT + 4,
+        y_cord + height,
+        RGB::named(YELLOW),
+        bg,
+        "ESCAPE to cancel",
+    );
 }
 
-fn draw_selection_options(gs: &mut State, ctx: &mut BTerm, selection_list: &Vec<(Entity, String)>) {
-    let y = 15;
+/// Where the unit/fort list starts on the screen and how many entries it shows at once
+const SELECTION_TOP: u32 = 15;
+const SELECTION_ROWS: u32 = 7;
+/// The column the box around the unit/fort list is drawn from and how wide it is, counting
+/// the border on both sides
+const SELECTION_LEFT: i32 = 14;
+const SELECTION_WIDTH: i32 = 30;
+
+/// Gets the index of the entry shown on the given row of the unit/fort list, the list scrolls
+/// with the highlight until the last entries are in view
+fn option_on_row(current_option: u32, count: u32, row: u32) -> u32 {
+    let mut offset = 0;
+    if count > SELECTION_ROWS {
+        offset = count - SELECTION_ROWS;
+    }
+    if current_option > offset {
+        offset + row
+    } else {
+        current_option + row
+    }
+}
+
+/// Gets the index of the entry in the unit/fort list drawn at the given spot on the screen
+pub fn option_at(gs: &State, count: u32, screen: Point) -> Option<u32> {
+    let row = screen.y - SELECTION_TOP as i32;
+    let inside = SELECTION_LEFT + 1..SELECTION_LEFT + SELECTION_WIDTH;
+    if !inside.contains(&screen.x) || !(0..SELECTION_ROWS as i32).contains(&row) {
+        return None;
+    }
+    let index = option_on_row(gs.last_option, count, row as u32);
+    if index < count {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+fn draw_selection_options(gs: &mut State, ctx: &mut BTerm, selection_list: &[(Entity, String)]) {
+    let (x, y) = (SELECTION_LEFT, SELECTION_TOP);
 	let bg = RGB::named(BLACK);
 
     let count = selection_list.len() as u32;
     let current_option = gs.last_option;
-    let mut offset = 0;
-    if count > 7 {
-        offset = count - 7;
-    }	
-    for i in 0..7 {
-        let mut index = current_option + i;
-        if current_option > offset {
-            index = offset + i;
-        }
+    for i in 0..SELECTION_ROWS {
+        let index = option_on_row(current_option, count, i);
         let width = index.to_string().len();
-        if index < count as u32 {
-            ctx.set(17, y + i, RGB::named(WHITE), bg, to_cp437('('));
-            ctx.print_color(18, y + i, RGB::named(YELLOW), bg, format!("{}", index + 1));
+        if index < count {
+            ctx.set(x + 3, y + i, RGB::named(WHITE), bg, to_cp437('('));
+            ctx.print_color(x + 4, y + i, RGB::named(YELLOW), bg, format!("{}", index + 1));
                 
-            ctx.set(18 + width, y + i, RGB::named(WHITE), bg, to_cp437(')'));
-            ctx.print(20 + width, y + i, selection_list[index as usize].1.clone());
+            ctx.set(x + 4 + width as i32, y + i, RGB::named(WHITE), bg, to_cp437(')'));
+            ctx.print(x + 6 + width as i32, y + i, selection_list[index as usize].1.clone());
         }
         if index == current_option {
-            ctx.set(16, y + i, RGB::named(WHITE), bg, to_cp437('>')); 
+            ctx.set(x + 2, y + i, RGB::named(WHITE), bg, to_cp437('>')); 
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LogEvent, MessageType};
+
+    #[test]
+    fn message_box_leaves_out_where_other_players_were() {
+        let gs = State::test_game(3);
+        let viewer = select_player(&gs.ecs).unwrap();
+        let other = PlayerOrder::TURN_ORDER
+            .iter()
+            .copied()
+            .find(|order| *order != viewer)
+            .unwrap();
+        let pos = Position { x: 1, y: 1 };
+        {
+            let mut log = gs.ecs.fetch_mut::<GameLog>();
+            log.push(LogEvent::new(MessageType::Build, viewer, "Ours".to_string()).at(pos));
+            log.push(LogEvent::new(MessageType::Build, other, "Theirs".to_string()).at(pos));
+            log.push(LogEvent::new(MessageType::Other, other, "Anywhere".to_string()));
         }
+
+        let log = gs.ecs.fetch::<GameLog>();
+        let messages: Vec<&str> = message_box_events(&gs.ecs)
+            .into_iter()
+            .take(2)
+            .map(|idx| log.events[idx].message.as_str())
+            .collect();
+        assert_eq!(messages, vec!["Anywhere", "Ours"]);
     }
 }
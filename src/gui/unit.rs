@@ -10,30 +10,23 @@
 //! enums in the units/forts to denote ownership and use marker traits to tell other functions
 //! which forts/units are currently selected by the player
 
-use crate::gui::{MenuResult, SelectionType, draw_selection_box, draw_selection_options};
-use crate::{Name, State, Unit, selection};
+use crate::gui::{
+    draw_selection_box, draw_selection_options, SELECTION_LEFT, SELECTION_TOP, SELECTION_WIDTH,
+};
+use crate::{Name, State, Unit};
 use bracket_lib::prelude::*;
 use specs::prelude::*;
 
 use super::select_player;
 
-/// Used for printing out a list of the units a player currently has and is able to move
-pub fn unit_list(gs: &mut State, ctx: &mut BTerm) -> MenuResult {
+/// Gets the units owned by the player whose turn it is, in the order they're listed in the menu
+pub fn owned_units(ecs: &World) -> Vec<(Entity, String)> {
 	let mut owned_units: Vec<(Entity, String)> = Vec::new();
-	let bg = RGB::named(BLACK);
-    let y = 15;
-
-	{
-		let units = gs.ecs.read_storage::<Unit>();
-		let names = gs.ecs.read_storage::<Name>();
-		let entities = gs.ecs.entities();
-
-		let player_enum = select_player(&gs.ecs);
-
-		let player_enum = player_enum.unwrap();
-       
-        draw_selection_box(ctx, "Unit List".to_string());
+	let units = ecs.read_storage::<Unit>();
+	let names = ecs.read_storage::<Name>();
+	let entities = ecs.entities();
 
+	if let Some(player_enum) = select_player(ecs) {
 		for (_unit, name, entity) in (&units, &names, &entities)
 			.join()
 			.filter(|unit| unit.0.owner == player_enum)
@@ -41,11 +34,20 @@ pub fn unit_list(gs: &mut State, ctx: &mut BTerm) -> MenuResult {
 			owned_units.push((entity, name.name.to_string()));
 		}
 	}
+	owned_units
+}
 
-    ctx.draw_box(14, y + 9, 30, 3, RGB::named(WHITE), bg);
-    ctx.print_color(16, y + 10, RGB::named(YELLOW), bg, format!("Selection: {}", gs.selected));
+/// Used for printing out a list of the units a player currently has and is able to move
+pub fn unit_list(gs: &mut State, ctx: &mut BTerm) {
+	let bg = RGB::named(BLACK);
+    let y = SELECTION_TOP;
 
-    draw_selection_options(gs, ctx, &owned_units);	
+	let owned_units = owned_units(&gs.ecs);
+	draw_selection_box(ctx, "Unit List".to_string());
 
-	selection(gs, ctx, owned_units, SelectionType::Unit)
+    ctx.draw_box(SELECTION_LEFT, y + 9, SELECTION_WIDTH, 3, RGB::named(WHITE), bg);
+    let selection = format!("Selection: {}", gs.selected);
+    ctx.print_color(SELECTION_LEFT + 2, y + 10, RGB::named(YELLOW), bg, selection);
+
+    draw_selection_options(gs, ctx, &owned_units);	
 }
@@ -1,80 +1,86 @@
 //! Made by: Thomas Gardner, 2022
 
-use crate::gui::{MenuResult, SelectionType};
-use crate::{State, Moving, Selected};
-use bracket_lib::prelude::*;
+use crate::command::{Command, Outcome};
+use crate::gui::{owned_forts, owned_units, SelectionType};
+use crate::{Moving, RunState, Selected, State};
 use specs::prelude::*;
 
-pub fn selection(gs: &mut State, ctx: &mut BTerm, owned: Vec<(Entity, String)>, selected: SelectionType) -> MenuResult 
-{ 
+/// Marks the unit or fort as selected and hands control over to it
+pub fn select_entity(gs: &mut State, entity: Entity, selected: SelectionType) {
+    match selected {
+        SelectionType::Unit => {
+            let mut moving = gs.ecs.write_storage::<Moving>();
+            moving
+                .insert(entity, Moving {})
+                .expect("Unable to mark unit as selected");
+            gs.runstate = RunState::MoveUnit;
+        },
+        SelectionType::Fort => {
+            let mut selected = gs.ecs.write_storage::<Selected>();
+            selected
+                .insert(entity, Selected {})
+                .expect("Unable to mark fort as selected");
+            // The highlight is reused for the fort's production queue
+            gs.last_option = 0;
+            gs.runstate = RunState::SelectedFort;
+        },
+    }
+}
+
+/// Carries out a command given while the unit or fort list is open, letting the player type in
+/// or scroll to an entry and pick it, or close the list
+pub fn selection_command(gs: &mut State, command: Command, selected: SelectionType) -> Outcome
+{
+    let owned: Vec<(Entity, String)> = match selected {
+        SelectionType::Unit => owned_units(&gs.ecs),
+        SelectionType::Fort => owned_forts(&gs.ecs),
+    };
     let count = owned.len() as u32;
-    match ctx.key {
-        None => { MenuResult::NoResponse }, 
-        Some(key) => {
-            match key {
-                VirtualKeyCode::Escape => MenuResult::Cancel,
-                VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
-					if let Ok(result) = gs.selected.parse::<u32>() {
-						gs.selected = "1".to_string();
-                        if result < count && result > 0 {
-                            match selected {
-                                SelectionType::Unit => {
-                                    let mut moving = gs.ecs.write_storage::<Moving>();
-                                    moving	
-                                        .insert(owned[result as usize - 1].0, Moving {})
-                                        .expect("Unable to mark unit as selected");
-                                    gs.selected = String::new();
-                                },
-                                SelectionType::Fort => {
-                                    let mut selected = gs.ecs.write_storage::<Selected>();
-                                    selected
-                                        .insert(owned[result as usize - 1].0, Selected {})
-                                        .expect("Unable to mark fort as selected");
-                                    gs.selected = String::new();
-                                },
-                            }
-                            MenuResult::Selected
-                        } else {	
-                            gs.selected = String::new();
-                            MenuResult::NoResponse
-                        }
-					} else {	
-						gs.selected = String::new();
-						MenuResult::NoResponse
-					}
-                },
-                VirtualKeyCode::Back => {
-                    gs.selected.pop();
-					MenuResult::NoResponse
-                },
-				VirtualKeyCode::Key0 | VirtualKeyCode::Numpad0 => { gs.selected.push('0'); MenuResult::NoResponse },
-				VirtualKeyCode::Key1 | VirtualKeyCode::Numpad1 => { gs.selected.push('1'); MenuResult::NoResponse },
-				VirtualKeyCode::Key2 | VirtualKeyCode::Numpad2 => { gs.selected.push('2'); MenuResult::NoResponse },
-				VirtualKeyCode::Key3 | VirtualKeyCode::Numpad3 => { gs.selected.push('3'); MenuResult::NoResponse },
-				VirtualKeyCode::Key4 | VirtualKeyCode::Numpad4 => { gs.selected.push('4'); MenuResult::NoResponse },
-				VirtualKeyCode::Key5 | VirtualKeyCode::Numpad5 => { gs.selected.push('5'); MenuResult::NoResponse },
-				VirtualKeyCode::Key6 | VirtualKeyCode::Numpad6 => { gs.selected.push('6'); MenuResult::NoResponse },
-				VirtualKeyCode::Key7 | VirtualKeyCode::Numpad7 => { gs.selected.push('7'); MenuResult::NoResponse },
-				VirtualKeyCode::Key8 | VirtualKeyCode::Numpad8 => { gs.selected.push('8'); MenuResult::NoResponse },
-				VirtualKeyCode::Key9 | VirtualKeyCode::Numpad9 => { gs.selected.push('9'); MenuResult::NoResponse },
-				VirtualKeyCode::Up => {
-					if gs.last_option > 0 {
-						gs.last_option -= 1;
-						gs.selected = format!("{}", gs.last_option + 1);
-					}
-					MenuResult::NoResponse
-				},
-				VirtualKeyCode::Down => {
-					if gs.last_option < count - 1 {
-						gs.last_option += 1;
-						gs.selected = format!("{}", gs.last_option + 1);
-					}
-					MenuResult::NoResponse
-				},
-                _ => { 
-					MenuResult::NoResponse
+    match command {
+        Command::Cancel => {
+            gs.runstate = RunState::MoveCursor;
+            Outcome::Applied
+        },
+        Command::Select(index) => {
+            gs.selected = format!("{}", index);
+            selection_command(gs, Command::Confirm, selected)
+        },
+        Command::Confirm => {
+            let choice = gs.selected.parse::<u32>();
+            gs.selected = String::new();
+            match choice {
+                Ok(result) if result <= count && result > 0 => {
+                    select_entity(gs, owned[result as usize - 1].0, selected);
+                    Outcome::Applied
                 },
+                _ => Outcome::Failed,
+            }
+        },
+        Command::DeleteDigit => {
+            gs.selected.pop();
+            Outcome::Applied
+        },
+        Command::TypeDigit(digit) => match std::char::from_digit(digit, 10) {
+            Some(digit) => {
+                gs.selected.push(digit);
+                Outcome::Applied
+            },
+            None => Outcome::Ignored,
+        },
+        Command::MenuUp => {
+            if gs.last_option > 0 {
+                gs.last_option -= 1;
+                gs.selected = format!("{}", gs.last_option + 1);
+            }
+            Outcome::Applied
+        },
+        Command::MenuDown => {
+            if gs.last_option + 1 < count {
+                gs.last_option += 1;
+                gs.selected = format!("{}", gs.last_option + 1);
             }
-		},
+            Outcome::Applied
+        },
+        _ => Outcome::Ignored,
     }
 }
@@ -2,36 +2,343 @@
 //!
 //! Code for this comes from the perlin-fractal example from the bracket-noise crate
 //! Link: https://github.com/amethyst/bracket-lib/tree/master/bracket-noise
+//!
+//! Along with the heightmap, a temperature map and a moisture map are generated the
+//! same way, which the map combines with the height to pick each tile's biome.
+//! The heightmap can then be shaped by the post-processing steps from the
+//! generator config before it gets turned into tiles.
 
-use crate::{xy_idx, MAPCOUNT, MAPHEIGHT, MAPWIDTH};
+use crate::generator_config::{Falloff, GeneratorConfig};
 use bracket_lib::prelude::RandomNumberGenerator;
 use bracket_noise::prelude::*;
 
 /// Generates a 2d heightmap populated with f32 values ranging from -1 to +1
 /// Used for mapping terrain tiles to positions in the map with the terrain tile being
-/// determined by the value generated at the same place in the heightmap
-pub fn generate_heightmap() -> Vec<f32> {
-    let mut rng = RandomNumberGenerator::new();
+/// determined by the value generated at the same place in the heightmap. The noise is
+/// seeded from the given generator so the same seed always gives the same heightmap, and the
+/// size of the heightmap along with the noise settings come from the config
+pub fn generate_heightmap(rng: &mut RandomNumberGenerator, config: &GeneratorConfig) -> Vec<f32> {
+    let settings = config.noise;
+    let mut noise = FastNoise::seeded(rng.next_u64());
+    noise.set_noise_type(NoiseType::PerlinFractal);
+    noise.set_fractal_type(FractalType::FBM);
+    noise.set_fractal_octaves(settings.octaves);
+    noise.set_fractal_gain(settings.gain);
+    noise.set_fractal_lacunarity(settings.lacunarity);
+    noise.set_frequency(settings.frequency);
+
+    let mut generated_heightmap = Vec::with_capacity(config.tile_count());
+
+    for y in 0..config.height {
+        for x in 0..config.width {
+            generated_heightmap.push(
+                noise.get_noise((x as f32) / settings.x_scale, (y as f32) / settings.y_scale),
+            );
+        }
+    }
+
+    generated_heightmap
+}
+
+/// Tiles colder than this turn into ice no matter how high up they are
+pub const ICE_TEMPERATURE: f32 = 0.06;
+
+/// How much the temperature noise can push a tile's temperature away from what its
+/// latitude gives it
+const TEMPERATURE_VARIATION: f32 = 0.15;
+
+/// Generates a 2d temperature map with values ranging from about 0 at the poles up to
+/// 1 at the equator. The latitude decides most of the temperature, with a bit of noise on
+/// top so the bands between biomes aren't straight lines across the map.
+pub fn generate_temperature(rng: &mut RandomNumberGenerator, config: &GeneratorConfig) -> Vec<f32> {
+    let mut noise = FastNoise::seeded(rng.next_u64());
+    noise.set_noise_type(NoiseType::PerlinFractal);
+    noise.set_fractal_type(FractalType::FBM);
+    noise.set_fractal_octaves(4);
+    noise.set_fractal_gain(0.5);
+    noise.set_fractal_lacunarity(2.0);
+    noise.set_frequency(2.0);
+
+    let settings = config.noise;
+    let mut generated_temperature = Vec::with_capacity(config.tile_count());
+
+    let equator = (config.height as f32 - 1.0) / 2.0;
+
+    for y in 0..config.height {
+        let latitude = 1.0 - ((y as f32 - equator).abs() / equator);
+        for x in 0..config.width {
+            let variation =
+                noise.get_noise((x as f32) / settings.x_scale, (y as f32) / settings.y_scale);
+            generated_temperature.push(latitude + variation * TEMPERATURE_VARIATION);
+        }
+    }
 
+    generated_temperature
+}
+
+/// Generates a 2d moisture map with values ranging from 0 for the driest tiles to 1 for
+/// the wettest, used for telling deserts and grasslands apart from forests and jungles
+pub fn generate_moisture(rng: &mut RandomNumberGenerator, config: &GeneratorConfig) -> Vec<f32> {
     let mut noise = FastNoise::seeded(rng.next_u64());
     noise.set_noise_type(NoiseType::PerlinFractal);
     noise.set_fractal_type(FractalType::FBM);
-    noise.set_fractal_octaves(8);
-    noise.set_fractal_gain(1.0);
-    noise.set_fractal_lacunarity(3.0);
+    noise.set_fractal_octaves(6);
+    noise.set_fractal_gain(0.6);
+    noise.set_fractal_lacunarity(2.0);
     noise.set_frequency(3.0);
 
-    let mut generated_heightmap = vec![0.0; MAPCOUNT];
+    let settings = config.noise;
+    let mut generated_moisture = Vec::with_capacity(config.tile_count());
+
+    for y in 0..config.height {
+        for x in 0..config.width {
+            let wetness =
+                noise.get_noise((x as f32) / settings.x_scale, (y as f32) / settings.y_scale);
+            generated_moisture.push(((wetness + 1.0) / 2.0).clamp(0.0, 1.0));
+        }
+    }
+
+    generated_moisture
+}
+
+/// How many times the sea level gets moved while looking for the target amount of land
+const SEA_LEVEL_SEARCH_STEPS: usize = 24;
+/// How much of the land ends up as hills or mountains when aiming for an amount of land, the
+/// hills share includes the mountains
+const HILLS_SHARE: f32 = 0.2;
+const MOUNTAIN_SHARE: f32 = 0.06;
 
-    let map_height: i32 = MAPHEIGHT as i32 - 1;
-    let map_width: i32 = MAPWIDTH as i32 - 1;
+/// Runs the post-processing steps from the config over the heightmap: the falloff mask, filling
+/// in tiny lakes, and moving the sea level until the map has the target amount of land. Returns
+/// the config with its levels moved to wherever the sea level ended up. When aiming for an
+/// amount of land, the hills and mountains are moved to cover the same share of the land no
+/// matter how steep the heightmap turned out. Tiles cold enough to turn into ice aren't counted
+/// as land, so the temperature map has to be generated first.
+pub fn shape_heightmap(
+    heights: &mut [f32],
+    temperature: &[f32],
+    config: &GeneratorConfig,
+) -> GeneratorConfig {
+    let mut shaped = *config;
 
-    for y in 0..=map_height {
-        for x in 0..=map_width {
-            let idx = xy_idx(x, y);
-            generated_heightmap[idx] = noise.get_noise((x as f32) / 160.0, (y as f32) / 100.0);
+    // Presets aiming for an amount of land stretch the noise out to use the whole range
+    // first, so the hills and mountains above the sea level don't depend on the seed
+    if config.target_land.is_some() {
+        normalize(heights);
+    }
+    apply_falloff(heights, config);
+
+    if let Some(target) = config.target_land {
+        let sea_level = find_sea_level(heights, temperature, config, target);
+        shaped.coast_level += sea_level - config.sea_level;
+        shaped.sea_level = sea_level;
+
+        let mut land = heights
+            .iter()
+            .zip(temperature)
+            .filter(|(height, temp)| **height > sea_level && **temp >= ICE_TEMPERATURE)
+            .map(|(height, _)| *height)
+            .collect::<Vec<_>>();
+        if !land.is_empty() {
+            land.sort_by(|a, b| a.total_cmp(b));
+            let level_at = |share: f32| land[((1.0 - share) * (land.len() - 1) as f32) as usize];
+            shaped.hills_level = level_at(HILLS_SHARE);
+            shaped.mountain_level = level_at(MOUNTAIN_SHARE);
         }
     }
 
-    generated_heightmap
+    // Lakes get filled up to just above the sea, so they turn into lowlands
+    for idx in small_lakes(heights, config, shaped.sea_level) {
+        heights[idx] = shaped.sea_level + 0.01;
+    }
+
+    shaped
+}
+
+/// Stretches the heightmap out so its lowest point is at -1 and its highest is at +1
+fn normalize(heights: &mut [f32]) {
+    let lowest = heights.iter().cloned().fold(f32::MAX, f32::min);
+    let highest = heights.iter().cloned().fold(f32::MIN, f32::max);
+    if highest - lowest <= f32::EPSILON {
+        return;
+    }
+
+    for height in heights.iter_mut() {
+        *height = (*height - lowest) / (highest - lowest) * 2.0 - 1.0;
+    }
+}
+
+/// Lowers the land the closer it is to the edges of the map, the mask barely touches the
+/// middle of the map and gets steeper towards the edges
+fn apply_falloff(heights: &mut [f32], config: &GeneratorConfig) {
+    if config.falloff == Falloff::None || config.falloff_strength == 0.0 {
+        return;
+    }
+
+    for y in 0..config.height {
+        // How far the tile is from the middle, from 0 in the middle to 1 at the edges
+        let dy = (y as f32 / (config.height - 1) as f32) * 2.0 - 1.0;
+        for x in 0..config.width {
+            let dx = (x as f32 / (config.width - 1) as f32) * 2.0 - 1.0;
+            let distance = match config.falloff {
+                Falloff::None => 0.0,
+                Falloff::Edges => dx.abs(),
+                Falloff::Radial => (dx * dx + dy * dy).sqrt().min(1.0),
+            };
+
+            let idx = (y * config.width + x) as usize;
+            heights[idx] -= distance.powi(3) * config.falloff_strength;
+        }
+    }
+}
+
+/// Searches for the sea level that leaves the target amount of the map as land once the
+/// tiny lakes are filled in. Each step moves the sea level halfway towards the side the
+/// amount of land was off by, so it quickly settles on the closest level it can find.
+fn find_sea_level(
+    heights: &[f32],
+    temperature: &[f32],
+    config: &GeneratorConfig,
+    target: f32,
+) -> f32 {
+    let mut low = heights.iter().cloned().fold(f32::MAX, f32::min);
+    let mut high = heights.iter().cloned().fold(f32::MIN, f32::max);
+    let mut sea_level = config.sea_level;
+
+    for _ in 0..SEA_LEVEL_SEARCH_STEPS {
+        sea_level = (low + high) / 2.0;
+        let land = land_fraction(heights, temperature, config, sea_level);
+        if land > target {
+            low = sea_level;
+        } else {
+            high = sea_level;
+        }
+    }
+
+    sea_level
+}
+
+/// How much of the map is land with the sea at the given level, counting the tiny lakes
+/// that are going to be filled in as land. Tiles that are going to turn into ice aren't land
+/// units can walk on, so they're left out.
+pub fn land_fraction(
+    heights: &[f32],
+    temperature: &[f32],
+    config: &GeneratorConfig,
+    sea_level: f32,
+) -> f32 {
+    let above_sea = heights
+        .iter()
+        .zip(temperature)
+        .filter(|(height, temp)| **height > sea_level && **temp >= ICE_TEMPERATURE)
+        .count();
+    let filled = small_lakes(heights, config, sea_level)
+        .into_iter()
+        .filter(|idx| temperature[*idx] >= ICE_TEMPERATURE)
+        .count();
+
+    (above_sea + filled) as f32 / heights.len() as f32
+}
+
+/// Flood fills every body of water below the sea level and returns the tiles of the ones
+/// smaller than the config's minimum lake size
+fn small_lakes(heights: &[f32], config: &GeneratorConfig, sea_level: f32) -> Vec<usize> {
+    let mut lakes = Vec::new();
+    if config.min_lake_size == 0 {
+        return lakes;
+    }
+
+    let mut visited = vec![false; heights.len()];
+    let mut body = Vec::new();
+    let mut stack = Vec::new();
+
+    for start in 0..heights.len() {
+        if visited[start] || heights[start] > sea_level {
+            continue;
+        }
+
+        body.clear();
+        stack.push(start);
+        visited[start] = true;
+        while let Some(idx) = stack.pop() {
+            body.push(idx);
+            let x = idx as i32 % config.width;
+            let y = idx as i32 / config.width;
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || nx >= config.width || ny < 0 || ny >= config.height {
+                    continue;
+                }
+                let next = (ny * config.width + nx) as usize;
+                if !visited[next] && heights[next] <= sea_level {
+                    visited[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+
+        if body.len() < config.min_lake_size {
+            lakes.extend_from_slice(&body);
+        }
+    }
+
+    lakes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator_config::Preset;
+    use crate::{Map, TileType};
+
+    #[test]
+    fn ice_is_not_counted_as_land() {
+        let config = GeneratorConfig {
+            min_lake_size: 0,
+            ..GeneratorConfig::default()
+        };
+        let heights = vec![0.5; 10];
+        let mut temperature = vec![0.5; 10];
+        for temp in temperature.iter_mut().take(4) {
+            *temp = ICE_TEMPERATURE / 2.0;
+        }
+
+        assert_eq!(land_fraction(&heights, &temperature, &config, 0.0), 0.6);
+        assert_eq!(land_fraction(&heights, &temperature, &config, 0.9), 0.0);
+    }
+
+    #[test]
+    fn presets_end_up_with_their_target_land() {
+        let presets = [
+            Preset::Continents,
+            Preset::Archipelago,
+            Preset::Pangaea,
+            Preset::Lakes,
+        ];
+        for preset in presets.iter() {
+            let mut config = GeneratorConfig {
+                width: 120,
+                height: 80,
+                ..GeneratorConfig::default()
+            };
+            config.apply_preset(*preset);
+            let target = config.target_land.unwrap();
+
+            let mut rng = RandomNumberGenerator::seeded(5);
+            let map = Map::new_map(&mut rng, &config);
+            let land = map
+                .tiles
+                .iter()
+                .filter(|tile| !matches!(tile, TileType::Water | TileType::Coast | TileType::Ice))
+                .count();
+            let fraction = land as f32 / map.tiles.len() as f32;
+            assert!(
+                (fraction - target).abs() < 0.02,
+                "{:?} has {} land instead of {}",
+                preset,
+                fraction,
+                target
+            );
+        }
+    }
 }
@@ -0,0 +1,381 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Checks that the game world hasn't been left in a state that should be
+//! impossible, used by the fuzzer after every step so bugs that quietly
+//! corrupt the world get caught where they happen.
+
+use crate::{BlocksTile, Fort, Map, Moving, Player, PlayerOrder, Position, Selected, Unit};
+use specs::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A rule about the game world that was found to be broken
+#[derive(PartialEq, Clone, Debug)]
+pub enum InvariantViolation {
+    UnitCountMismatch {
+        player: PlayerOrder,
+        recorded: u16,
+        owned: u16,
+    },
+    FortCountMismatch {
+        player: PlayerOrder,
+        recorded: u16,
+        owned: u16,
+    },
+    TileSharedByBlockers {
+        x: i32,
+        y: i32,
+        count: usize,
+    },
+    TooManyActive(usize),
+    OutOfBounds {
+        x: i32,
+        y: i32,
+    },
+    FortOnUnclaimedTile {
+        x: i32,
+        y: i32,
+        owner: PlayerOrder,
+        claimed_by: PlayerOrder,
+    },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantViolation::UnitCountMismatch {
+                player,
+                recorded,
+                owned,
+            } => write!(
+                f,
+                "{} has a unit count of {} but owns {} units",
+                player.name(),
+                recorded,
+                owned
+            ),
+            InvariantViolation::FortCountMismatch {
+                player,
+                recorded,
+                owned,
+            } => write!(
+                f,
+                "{} has a fort count of {} but owns {} forts",
+                player.name(),
+                recorded,
+                owned
+            ),
+            InvariantViolation::TileSharedByBlockers { x, y, count } => write!(
+                f,
+                "{} entities that block tiles are all on ({}, {})",
+                count, x, y
+            ),
+            InvariantViolation::TooManyActive(count) => write!(
+                f,
+                "{} entities are being moved or are selected at once",
+                count
+            ),
+            InvariantViolation::OutOfBounds { x, y } => {
+                write!(f, "an entity is outside of the map at ({}, {})", x, y)
+            }
+            InvariantViolation::FortOnUnclaimedTile {
+                x,
+                y,
+                owner,
+                claimed_by,
+            } => write!(
+                f,
+                "{}'s fort at ({}, {}) is on a tile claimed by {}",
+                owner.name(),
+                x,
+                y,
+                claimed_by.name()
+            ),
+        }
+    }
+}
+
+/// Goes through the whole world and returns every broken rule that was found
+pub fn check_invariants(ecs: &World) -> Result<(), Vec<InvariantViolation>> {
+    let mut violations = Vec::new();
+
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let players = ecs.read_storage::<Player>();
+    let units = ecs.read_storage::<Unit>();
+    let forts = ecs.read_storage::<Fort>();
+    let blockers = ecs.read_storage::<BlocksTile>();
+    let moving = ecs.read_storage::<Moving>();
+    let selected = ecs.read_storage::<Selected>();
+    let map = ecs.fetch::<Map>();
+
+    // Every player's counts have to match what they actually own
+    for player in players.join() {
+        let owned_units = units
+            .join()
+            .filter(|unit| unit.owner == player.order)
+            .count() as u16;
+        if owned_units != player.unit_count {
+            violations.push(InvariantViolation::UnitCountMismatch {
+                player: player.order,
+                recorded: player.unit_count,
+                owned: owned_units,
+            });
+        }
+
+        let owned_forts = forts
+            .join()
+            .filter(|fort| fort.owner == player.order)
+            .count() as u16;
+        if owned_forts != player.fort_count {
+            violations.push(InvariantViolation::FortCountMismatch {
+                player: player.order,
+                recorded: player.fort_count,
+                owned: owned_forts,
+            });
+        }
+    }
+
+    // Positions are checked before anything gets looked up on the map with them
+    let mut in_bounds = true;
+    for pos in positions.join() {
+        if pos.x < 0 || pos.x >= map.width || pos.y < 0 || pos.y >= map.height {
+            violations.push(InvariantViolation::OutOfBounds { x: pos.x, y: pos.y });
+            in_bounds = false;
+        }
+    }
+
+    if in_bounds {
+        let mut blocked_tiles: HashMap<(i32, i32), usize> = HashMap::new();
+        for (_entity, pos, _blocks) in (&entities, &positions, &blockers).join() {
+            *blocked_tiles.entry((pos.x, pos.y)).or_insert(0) += 1;
+        }
+        let mut shared = blocked_tiles
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .collect::<Vec<_>>();
+        shared.sort();
+        for ((x, y), count) in shared {
+            violations.push(InvariantViolation::TileSharedByBlockers { x, y, count });
+        }
+
+        for (fort, pos) in (&forts, &positions).join() {
+            let claimed_by = map.claimed_tiles[map.xy_idx(pos.x, pos.y)];
+            if claimed_by != fort.owner {
+                violations.push(InvariantViolation::FortOnUnclaimedTile {
+                    x: pos.x,
+                    y: pos.y,
+                    owner: fort.owner,
+                    claimed_by,
+                });
+            }
+        }
+    }
+
+    // Only one thing can be picked at a time, so a unit being moved and a selected fort
+    // can't both be around either
+    let active_count = entities
+        .join()
+        .filter(|entity| moving.contains(*entity) || selected.contains(*entity))
+        .count();
+    if active_count > 1 {
+        violations.push(InvariantViolation::TooManyActive(active_count));
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::economy::Yields;
+    use crate::map::TileType;
+    use crate::State;
+
+    const WIDTH: i32 = 10;
+    const HEIGHT: i32 = 10;
+
+    /// A small map of grasslands with nothing claimed and nobody on it
+    fn test_world() -> World {
+        let mut ecs = State::new().ecs;
+        let tile_count = (WIDTH * HEIGHT) as usize;
+        ecs.insert(Map {
+            tiles: vec![TileType::Grasslands; tile_count],
+            deposits: vec![None; tile_count],
+            elevation: vec![0.0; tile_count],
+            rivers: vec![false; tile_count],
+            width: WIDTH,
+            height: HEIGHT,
+            blocked: vec![false; tile_count],
+            tile_content: vec![Vec::new(); tile_count],
+            claimed_tiles: vec![PlayerOrder::NoPlayer; tile_count],
+        });
+        ecs
+    }
+
+    fn add_player(ecs: &mut World, unit_count: u16, fort_count: u16) {
+        ecs.create_entity()
+            .with(Player {
+                order: PlayerOrder::PlayerOne,
+                unit_count,
+                fort_count,
+                stockpile: Yields::default(),
+            })
+            .build();
+    }
+
+    fn add_unit(ecs: &mut World, x: i32, y: i32) -> Entity {
+        ecs.create_entity()
+            .with(Position { x, y })
+            .with(Unit {
+                owner: PlayerOrder::PlayerOne,
+                health: 10,
+                strength: 2,
+                stamina: 6,
+            })
+            .with(BlocksTile {})
+            .build()
+    }
+
+    fn add_fort(ecs: &mut World, x: i32, y: i32) -> Entity {
+        {
+            let mut map = ecs.fetch_mut::<Map>();
+            let idx = map.xy_idx(x, y);
+            map.claimed_tiles[idx] = PlayerOrder::PlayerOne;
+        }
+        ecs.create_entity()
+            .with(Position { x, y })
+            .with(Fort {
+                owner: PlayerOrder::PlayerOne,
+                defense: 5,
+                queue: Vec::new(),
+                progress: 0,
+            })
+            .with(BlocksTile {})
+            .build()
+    }
+
+    fn violations(ecs: &World) -> Vec<InvariantViolation> {
+        check_invariants(ecs).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn a_consistent_world_passes() {
+        let mut ecs = test_world();
+        add_player(&mut ecs, 1, 1);
+        add_unit(&mut ecs, 2, 2);
+        let fort = add_fort(&mut ecs, 5, 5);
+        ecs.write_storage::<Selected>()
+            .insert(fort, Selected)
+            .unwrap();
+        assert_eq!(check_invariants(&ecs), Ok(()));
+    }
+
+    #[test]
+    fn unit_count_mismatch_is_caught() {
+        let mut ecs = test_world();
+        add_player(&mut ecs, 2, 0);
+        add_unit(&mut ecs, 2, 2);
+        assert_eq!(
+            violations(&ecs),
+            vec![InvariantViolation::UnitCountMismatch {
+                player: PlayerOrder::PlayerOne,
+                recorded: 2,
+                owned: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn fort_count_mismatch_is_caught() {
+        let mut ecs = test_world();
+        add_player(&mut ecs, 0, 0);
+        add_fort(&mut ecs, 5, 5);
+        assert_eq!(
+            violations(&ecs),
+            vec![InvariantViolation::FortCountMismatch {
+                player: PlayerOrder::PlayerOne,
+                recorded: 0,
+                owned: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn blockers_sharing_a_tile_are_caught() {
+        let mut ecs = test_world();
+        add_player(&mut ecs, 2, 0);
+        add_unit(&mut ecs, 3, 4);
+        add_unit(&mut ecs, 3, 4);
+        assert_eq!(
+            violations(&ecs),
+            vec![InvariantViolation::TileSharedByBlockers {
+                x: 3,
+                y: 4,
+                count: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn a_moving_unit_and_a_selected_fort_are_caught() {
+        let mut ecs = test_world();
+        add_player(&mut ecs, 1, 1);
+        let unit = add_unit(&mut ecs, 2, 2);
+        let fort = add_fort(&mut ecs, 5, 5);
+        ecs.write_storage::<Moving>().insert(unit, Moving).unwrap();
+        ecs.write_storage::<Selected>()
+            .insert(fort, Selected)
+            .unwrap();
+        assert_eq!(violations(&ecs), vec![InvariantViolation::TooManyActive(2)]);
+    }
+
+    #[test]
+    fn two_moving_units_are_caught() {
+        let mut ecs = test_world();
+        add_player(&mut ecs, 2, 0);
+        let first = add_unit(&mut ecs, 2, 2);
+        let second = add_unit(&mut ecs, 3, 3);
+        ecs.write_storage::<Moving>().insert(first, Moving).unwrap();
+        ecs.write_storage::<Moving>()
+            .insert(second, Moving)
+            .unwrap();
+        assert_eq!(violations(&ecs), vec![InvariantViolation::TooManyActive(2)]);
+    }
+
+    #[test]
+    fn entities_off_the_map_are_caught() {
+        let mut ecs = test_world();
+        add_player(&mut ecs, 1, 0);
+        add_unit(&mut ecs, WIDTH, -1);
+        assert_eq!(
+            violations(&ecs),
+            vec![InvariantViolation::OutOfBounds { x: WIDTH, y: -1 }]
+        );
+    }
+
+    #[test]
+    fn forts_on_tiles_they_dont_own_are_caught() {
+        let mut ecs = test_world();
+        add_player(&mut ecs, 0, 1);
+        add_fort(&mut ecs, 5, 5);
+        {
+            let mut map = ecs.fetch_mut::<Map>();
+            let idx = map.xy_idx(5, 5);
+            map.claimed_tiles[idx] = PlayerOrder::PlayerTwo;
+        }
+        assert_eq!(
+            violations(&ecs),
+            vec![InvariantViolation::FortOnUnclaimedTile {
+                x: 5,
+                y: 5,
+                owner: PlayerOrder::PlayerOne,
+                claimed_by: PlayerOrder::PlayerTwo,
+            }]
+        );
+    }
+}
@@ -5,7 +5,9 @@
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_0.html
 
 use bracket_lib::prelude::*;
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
+use specs::saveload::{SimpleMarker, SimpleMarkerAllocator};
 
 mod map;
 pub use map::*;
@@ -29,33 +31,53 @@ mod gui;
 pub use gui::fort::*;
 pub use gui::unit::*;
 pub use gui::window::*;
+use gui::minimap::MinimapCache;
 
 mod turns;
 pub use turns::*;
 
+mod combat;
+
+pub mod economy;
+
+pub mod command;
+use command::{apply, click_to_command, key_to_command, Outcome};
+
 mod error_handling;
-use crate::error_handling::generate_key;
+mod export;
+use export::ExportOptions;
+mod fuzz;
+pub mod invariants;
+pub mod generator_config;
+use generator_config::GeneratorConfig;
 mod heightmap;
 mod spawner;
+use spawner::PlacementError;
 
 mod visibility_system;
 use visibility_system::VisibilitySystem;
 
+mod mouse;
+use mouse::MouseState;
+
 mod map_indexing_system;
 use map_indexing_system::MapIndexingSystem;
 
 pub mod camera;
 
+mod saveload_system;
+
 /// Marks what state the games running in to allow the player to open their unit/fort lists
 /// and move their cursor around the map
-#[derive(PartialEq, Copy, Clone, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
 pub enum RunState {
     MoveCursor,
     MoveUnit,
     ShowUnits,
     SelectedFort,
     ShowForts,
-	EndTurn,
+    ShowLog,
+    Overview,
 }
 
 /// Used for returning why a move failed to happen
@@ -63,12 +85,7 @@ pub enum FailedMoveReason {
     TileBlocked,
     UnableToGrabEntity,
 	UnitOutOfMoves,
-}
-
-pub struct ExpectedFuzzState {
-    first: RunState,
-    second: Option<RunState>,
-    third: Option<RunState>,
+    NotEnoughStamina,
 }
 
 /// Contains the game world and all of it's entities within it, and an enum for denoting
@@ -78,13 +95,184 @@ pub struct State {
     pub runstate: RunState,
     pub godmode: bool,
     pub verbose: bool,
-    pub fuzz_test: bool,
     pub selected: String,
 	pub last_option: u32,
 	pub turns: u32,
+    pub seed: u64,
+    pub log_filter: LogFilter,
+    pub overview_marker: Point,
+    pub mouse: MouseState,
 }
 
 impl State {
+    /// Creates an empty world with every component the game uses registered, ready for a
+    /// new game to be generated or a save file to be loaded into it
+    pub fn new() -> State {
+        let mut gs = State {
+            ecs: World::new(),
+            runstate: RunState::MoveCursor,
+            godmode: false,
+            verbose: false,
+            selected: "1".to_string(),
+            last_option: 0,
+            turns: 0,
+            seed: 0,
+            log_filter: LogFilter::default(),
+            overview_marker: Point::zero(),
+            mouse: MouseState::default(),
+        };
+
+        gs.ecs.register::<Position>();
+        gs.ecs.register::<Renderable>();
+        gs.ecs.register::<Viewshed>();
+        gs.ecs.register::<Player>();
+        gs.ecs.register::<Unit>();
+        gs.ecs.register::<Fort>();
+        gs.ecs.register::<Name>();
+        gs.ecs.register::<BlocksTile>();
+        gs.ecs.register::<Moving>();
+        gs.ecs.register::<Selected>();
+        gs.ecs.register::<GameLog>();
+        gs.ecs.register::<FogOfWar>();
+        gs.ecs.register::<GoTo>();
+        gs.ecs.register::<SimpleMarker<SerializeMe>>();
+        gs.ecs.register::<SerializationHelper>();
+
+        gs.ecs.insert(SimpleMarkerAllocator::<SerializeMe>::new());
+        gs.ecs.insert(MinimapCache::default());
+
+        gs
+    }
+
+    /// Generates the world from the given seed and generator config and spawns the given
+    /// number of players into it, with each player's units able to see as far as the given
+    /// range. Fails if the map doesn't have room for every player to get a fair start.
+    pub fn new_game(
+        &mut self,
+        seed: u64,
+        player_count: usize,
+        range: i32,
+        config: &GeneratorConfig,
+    ) -> Result<(), PlacementError> {
+        // Every random roll in the game comes from this generator, so the same seed
+        // always produces the same map and starting positions
+        self.seed = seed;
+        let mut rng = RandomNumberGenerator::seeded(seed);
+
+        let map = Map::new_map(&mut rng, config);
+
+        self.ecs.insert(map);
+        self.ecs.insert(rng);
+
+        let starts = spawner::place_starts(&self.ecs, player_count)?;
+
+        let mut player_entities = Vec::new();
+        for (order, position) in PlayerOrder::TURN_ORDER.iter().zip(starts) {
+            let player_entity =
+                spawner::spawn_player_entities(&mut self.ecs, position, range, *order)?;
+            player_entities.push((player_entity, position));
+        }
+
+        // The first player takes the first turn
+        let (first_player, first_position) = player_entities[0];
+        self.ecs.insert(first_player);
+        self.ecs.insert(Point::new(first_position.0, first_position.1));
+        let mut log = GameLog::new();
+        let welcome =
+            |message: String| LogEvent::new(MessageType::Other, PlayerOrder::NoPlayer, message);
+        log.push(welcome("Welcome to Civlike!".to_string()));
+        log.push(welcome(format!("World seed: {}", seed)));
+        self.ecs.insert(log);
+
+        self.run_systems();
+        Ok(())
+    }
+
+    /// Starts a small two player game from the given seed for the tests to play through
+    #[cfg(test)]
+    pub fn test_game(seed: u64) -> State {
+        let config = GeneratorConfig {
+            width: 80,
+            height: 60,
+            ..GeneratorConfig::default()
+        };
+        let mut gs = State::new();
+        gs.new_game(seed, 2, 8, &config)
+            .expect("Unable to start a test game");
+        gs
+    }
+
+    /// Sets up a map covered in grasslands with two players on it and nothing else, for the
+    /// tests that place everything themselves. Player one takes the first turn.
+    #[cfg(test)]
+    pub fn test_arena(width: i32, height: i32) -> State {
+        let tile_count = (width * height) as usize;
+        let mut gs = State::new();
+        gs.ecs.insert(Map {
+            tiles: vec![TileType::Grasslands; tile_count],
+            deposits: vec![None; tile_count],
+            elevation: vec![0.0; tile_count],
+            rivers: vec![false; tile_count],
+            width,
+            height,
+            blocked: vec![false; tile_count],
+            tile_content: vec![Vec::new(); tile_count],
+            claimed_tiles: vec![PlayerOrder::NoPlayer; tile_count],
+        });
+        gs.ecs.insert(RandomNumberGenerator::seeded(1));
+
+        let first = spawner::player(&mut gs.ecs, (0, 0), PlayerOrder::PlayerOne);
+        spawner::player(&mut gs.ecs, (0, 0), PlayerOrder::PlayerTwo);
+        gs.ecs.insert(first);
+        gs.ecs.insert(Point::new(0, 0));
+        gs.ecs.insert(GameLog::new());
+
+        gs.run_systems();
+        gs
+    }
+
+    /// Places a unit for a test, keeping its owner's unit count up to date
+    #[cfg(test)]
+    pub fn test_unit(&mut self, x: i32, y: i32, owner: PlayerOrder) -> Entity {
+        let unit = spawner::unit(&mut self.ecs, (x, y), "Unit".to_string(), 8, owner);
+        for player in (&mut self.ecs.write_storage::<Player>()).join() {
+            if player.order == owner {
+                player.unit_count += 1;
+            }
+        }
+        self.run_systems();
+        unit
+    }
+
+    /// Places a fort for a test on a tile claimed for its owner, keeping its owner's fort
+    /// count up to date
+    #[cfg(test)]
+    pub fn test_fort(&mut self, x: i32, y: i32, owner: PlayerOrder) -> Entity {
+        {
+            let mut map = self.ecs.fetch_mut::<Map>();
+            let idx = map.xy_idx(x, y);
+            map.claimed_tiles[idx] = owner;
+        }
+        let fort = spawner::fort(&mut self.ecs, (x, y), "Fort".to_string(), owner);
+        for player in (&mut self.ecs.write_storage::<Player>()).join() {
+            if player.order == owner {
+                player.fort_count += 1;
+            }
+        }
+        self.run_systems();
+        fort
+    }
+
+    /// Picks a unit for a test and gets it ready to be moved
+    #[cfg(test)]
+    pub fn test_select(&mut self, unit: Entity) {
+        self.ecs
+            .write_storage::<Moving>()
+            .insert(unit, Moving)
+            .expect("Unable to select the unit");
+        self.runstate = RunState::MoveUnit;
+    }
+
     fn run_systems(&mut self) {
         let mut mapindex = MapIndexingSystem {};
         mapindex.run_now(&self.ecs);
@@ -96,67 +284,39 @@ impl State {
     }
 }
 
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GameState for State {
     fn tick(&mut self, ctx: &mut BTerm) {
-        let mut expected_state: Option<ExpectedFuzzState> = None;
-
         ctx.cls();
         camera::render_camera(&self.ecs, ctx);
-        gui::draw_ui(&self.ecs, ctx, self.turns);
-
-        if self.fuzz_test {
-            expected_state = Some(generate_key(self.runstate, ctx));
-        }
+        let hovered = camera::screen_to_tile(&self.ecs, ctx, ctx.mouse_point());
+        gui::draw_ui(&self.ecs, ctx, self.turns, self.seed, hovered);
 
         self.run_systems();
         match self.runstate {
-            RunState::MoveCursor => {
-                self.runstate = player_input(self, ctx);
-            }
-            RunState::MoveUnit => {
-                self.runstate = unit_input(self, ctx);
-            }
-            RunState::ShowUnits => {
-                let result = unit_list(self, ctx);
-                match result {
-                    gui::MenuResult::Cancel => self.runstate = RunState::MoveCursor,
-                    gui::MenuResult::Selected => {
-                        self.runstate = RunState::MoveUnit;
-                    }
-                    gui::MenuResult::NoResponse => {}
-                }
-            }
-            RunState::SelectedFort => {
-                self.runstate = fort_input(self, ctx);
-            }
-            RunState::ShowForts => {
-                let result = fort_list(self, ctx);
-                match result {
-                    gui::MenuResult::Cancel => self.runstate = RunState::MoveCursor,
-                    gui::MenuResult::Selected => {
-                        self.runstate = RunState::SelectedFort;
-                    }
-                    gui::MenuResult::NoResponse => {}
-                }
-            }
-			RunState::EndTurn => {
-				next_turn(self);
-				self.runstate = RunState::MoveCursor;	
-			}
+            RunState::ShowUnits => unit_list(self, ctx),
+            RunState::ShowForts => fort_list(self, ctx),
+            RunState::SelectedFort => fort_screen(self, ctx),
+            RunState::ShowLog => gui::log::log_viewer(self, ctx),
+            RunState::Overview => gui::minimap::overview_screen(self, ctx),
+            _ => {}
         }
 
-        if let Some(state) = expected_state {
-            if state.second.is_some() && state.third.is_some() {
-                println!("self.runstate: {:?} \n state.first: {:?} \n state.second: {:?}\n", 
-                         self.runstate, state.first, state.second.unwrap());
-                assert!((self.runstate == state.first) 
-                        || (self.runstate == state.second.unwrap())
-                        || (self.runstate == state.third.unwrap()));
-            } else if self.runstate != state.first {
-                panic!(
-                    "Error: runstates don't match! States: {:?} {:?}; Key: {:?}",
-                    self.runstate, state.first, ctx.key
-                );
+        // Keys and clicks are only translated into commands here, the rest of the game never
+        // has to know where its commands came from
+        let click = self.mouse.poll(&self.ecs, ctx);
+        let command = match ctx.key {
+            Some(key) => key_to_command(self.runstate, key),
+            None => click.and_then(|click| click_to_command(self, click)),
+        };
+        if let Some(command) = command {
+            if apply(self, command) == Outcome::Quit {
+                ctx.quit();
             }
         }
     }
@@ -166,66 +326,154 @@ use std::env;
 
 fn main() -> BError {
     let mut range = 8;
-    let mut cmd_args = Vec::new();
-
-    let context = BTermBuilder::simple80x50().with_title("Civlike").build()?;
-
-    let mut gs = State {
-        ecs: World::new(),
-        runstate: RunState::MoveCursor,	
-        godmode: false,
-        verbose: false,
-        fuzz_test: false,
-        selected: "1".to_string(),
-		last_option: 0,
-		turns: 0,
-    };
-
-    for arg in env::args().skip(1) {
-        cmd_args.push(arg.clone());
-    }
-
-    while !cmd_args.is_empty() {
-        let cmd_input = cmd_args.pop();
-
-        if let Some(arg) = cmd_input {
-            match arg.as_str() {
-                "-godmode" => {
-                    range = 400;
-                    gs.godmode = true
+    let mut seed: Option<u64> = None;
+    let mut load_path: Option<String> = None;
+    let mut player_count: usize = 2;
+    let mut fuzz_steps: Option<u32> = None;
+    let mut replay_path: Option<String> = None;
+    let mut config_path: Option<String> = None;
+    let mut generator_flags: Vec<(String, String)> = Vec::new();
+    let mut export_path: Option<String> = None;
+    let mut export_options = ExportOptions::default();
+    let mut log_path: Option<String> = None;
+    let mut console_width = gui::layout::DEFAULT_CONSOLE_WIDTH;
+    let mut console_height = gui::layout::DEFAULT_CONSOLE_HEIGHT;
+
+    let mut gs = State::new();
+
+    let mut cmd_args = env::args().skip(1).peekable();
+
+    while let Some(arg) = cmd_args.next() {
+        match arg.as_str() {
+            "-godmode" => {
+                range = 400;
+                gs.godmode = true
+            }
+            "-verbose" => gs.verbose = true,
+            // The path after export is optional, anything starting with a dash is a flag
+            "export" => {
+                export_path = match cmd_args.next_if(|next| !next.starts_with('-')) {
+                    Some(path) => Some(path),
+                    None => Some(export::DEFAULT_EXPORT_FILE.to_string()),
                 }
-                "-verbose" => gs.verbose = true,
-                "-fuzz_test" => gs.fuzz_test = true,
-                _ => {}
             }
+            "-claims" => export_options.claims = true,
+            "-entities" => export_options.entities = true,
+            "-fuzz_test" => fuzz_steps = Some(fuzz::DEFAULT_FUZZ_STEPS),
+            "-fuzz_steps" => match cmd_args.next().map(|value| value.parse::<u32>()) {
+                Some(Ok(value)) => fuzz_steps = Some(value),
+                _ => return Err("-fuzz_steps expects a number of steps".into()),
+            },
+            "-replay" => match cmd_args.next() {
+                Some(path) => replay_path = Some(path),
+                None => return Err("-replay expects a path to a replay file".into()),
+            },
+            "-seed" => match cmd_args.next().map(|value| value.parse::<u64>()) {
+                Some(Ok(value)) => seed = Some(value),
+                _ => return Err("-seed expects an unsigned 64-bit number".into()),
+            },
+            "-players" => match cmd_args.next().map(|value| value.parse::<usize>()) {
+                Some(Ok(value)) if (1..=PlayerOrder::TURN_ORDER.len()).contains(&value) => {
+                    player_count = value
+                }
+                _ => {
+                    return Err(format!(
+                        "-players expects a number from 1 to {}",
+                        PlayerOrder::TURN_ORDER.len()
+                    )
+                    .into())
+                }
+            },
+            "-load" => match cmd_args.next() {
+                Some(path) => load_path = Some(path),
+                None => return Err("-load expects a path to a save file".into()),
+            },
+            "-log_file" => match cmd_args.next() {
+                Some(path) => log_path = Some(path),
+                None => return Err("-log_file expects a path to write the log to".into()),
+            },
+            "-window_width" => match cmd_args.next().map(|value| value.parse::<u32>()) {
+                Some(Ok(value)) if value >= gui::layout::MIN_CONSOLE_WIDTH => {
+                    console_width = value
+                }
+                _ => {
+                    return Err(format!(
+                        "-window_width expects a number of columns, at least {}",
+                        gui::layout::MIN_CONSOLE_WIDTH
+                    )
+                    .into())
+                }
+            },
+            "-window_height" => match cmd_args.next().map(|value| value.parse::<u32>()) {
+                Some(Ok(value)) if value >= gui::layout::MIN_CONSOLE_HEIGHT => {
+                    console_height = value
+                }
+                _ => {
+                    return Err(format!(
+                        "-window_height expects a number of rows, at least {}",
+                        gui::layout::MIN_CONSOLE_HEIGHT
+                    )
+                    .into())
+                }
+            },
+            "-config" => match cmd_args.next() {
+                Some(path) => config_path = Some(path),
+                None => return Err("-config expects a path to a generator config file".into()),
+            },
+            flag if generator_config::GENERATOR_FLAGS.contains(&flag) => match cmd_args.next() {
+                Some(value) => generator_flags.push((arg.clone(), value)),
+                None => return Err(format!("{} expects a value", flag).into()),
+            },
+            _ => {}
         }
     }
 
-    gs.ecs.register::<Position>();
-    gs.ecs.register::<Renderable>();
-    gs.ecs.register::<Viewshed>();
-    gs.ecs.register::<Player>();
-    gs.ecs.register::<Unit>();
-    gs.ecs.register::<Fort>();
-    gs.ecs.register::<Name>();
-    gs.ecs.register::<BlocksTile>();
-    gs.ecs.register::<Moving>();
-    gs.ecs.register::<Selected>();
-    gs.ecs.register::<GameLog>();
+    // The flags are applied on top of the config file no matter what order they were given in
+    let mut config = match config_path {
+        Some(path) => GeneratorConfig::load(&path)?,
+        None => GeneratorConfig::default(),
+    };
+    // Presets replace most of the settings, so they go first to let the other flags change them
+    generator_flags.sort_by_key(|(flag, _)| flag != "-preset");
+    for (flag, value) in generator_flags.iter() {
+        config.set_flag(flag, value)?;
+    }
+    config.validate()?;
+
+    // Fuzzing, replays and exports run without a window, so they're handled before one gets made
+    if let Some(path) = replay_path {
+        fuzz::replay(&path)?;
+        return Ok(());
+    }
+
+    let seed = seed.unwrap_or_else(|| RandomNumberGenerator::new().next_u64());
+    if let Some(steps) = fuzz_steps {
+        fuzz::run_fuzz(seed, player_count, range, steps, &config)?;
+        return Ok(());
+    }
 
-    let map = Map::new_map();
+    if let Some(path) = export_path {
+        gs.new_game(seed, player_count, range, &config)?;
+        export::export_map(&gs.ecs, &path, export_options)?;
+        println!("Map from seed {} written to {}", seed, path);
+        return Ok(());
+    }
 
-    gs.ecs.insert(map);
+    // Resizing the window changes how many columns and rows the console has instead of
+    // stretching it, and the layout is worked out again from the new size every frame
+    let context = BTermBuilder::simple(console_width, console_height)?
+        .with_title("Civlike")
+        .with_automatic_console_resize(true)
+        .build()?;
 
-    let x_range: (i32, i32) = (0, (MAPWIDTH - 1) as i32);
-    let y_range: (i32, i32) = (0, (MAPHEIGHT - 1) as i32);
+    match load_path {
+        Some(path) => saveload_system::load_game(&mut gs, &path)?,
+        None => gs.new_game(seed, player_count, range, &config)?,
+    }
 
-    let position: (i32, i32) = spawner::generate_coordinates(&gs.ecs, x_range, y_range);
-    spawner::spawn_player_entities(&mut gs.ecs, position, range, PlayerOrder::PlayerOne);
-    gs.ecs.insert(gamelog::GameLog {
-        entries: vec!["Welcome to Civlike!".to_string()],
-        message_type: vec![MessageType::Other],
-    });
+    if let Some(path) = log_path {
+        gs.ecs.fetch_mut::<GameLog>().stream_to(&path)?;
+    }
 
     main_loop(context, gs)
 }
@@ -4,101 +4,396 @@
 //! this project
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_0.html
 
-use crate::heightmap::generate_heightmap;
+use crate::economy::Yields;
+use crate::generator_config::GeneratorConfig;
+use crate::heightmap::{
+    generate_heightmap, generate_moisture, generate_temperature, shape_heightmap, ICE_TEMPERATURE,
+};
 use crate::PlayerOrder;
 use crate::PlayerOrder::*;
 use bracket_lib::prelude::*;
+use serde::{Deserialize, Serialize};
 use specs::Entity;
 
-pub const MAPWIDTH: usize = 400;
-pub const MAPHEIGHT: usize = 300;
-pub const MAPCOUNT: usize = MAPHEIGHT * MAPWIDTH;
+/// One random spot for every this many tiles gets tried as the source of a river, only hills
+/// and mountains can start one so most of the tries don't end up as rivers
+const TILES_PER_RIVER_ATTEMPT: usize = 150;
+/// Rivers that haven't found their way to the water after this many tiles are dropped
+const MAX_RIVER_LENGTH: usize = 200;
+/// Extra stamina it takes for a unit to step onto a tile with a river running through it
+const RIVER_CROSSING_COST: u8 = 1;
+/// What a claimed tile gets on top of its usual yields for being on or next to a river
+const RIVER_YIELDS: Yields = Yields {
+    food: 1,
+    production: 0,
+    gold: 1,
+};
 
-/*
- The work I did for decoupling the screen from the map came from here:
- https://bfnightly.bracketproductions.com/rustbook/chapter_41.html
-*/
-pub const VIEW_WIDTH: usize = 60;
-pub const VIEW_HEIGHT: usize = 40;
-pub const VIEW_COUNT: usize = VIEW_WIDTH * VIEW_HEIGHT;
-
-#[derive(PartialEq, Copy, Clone, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
 pub enum TileType {
     Mountain,
+    Hills,
     Forest,
+    Jungle,
     Grasslands,
+    Desert,
+    Tundra,
+    Snow,
     Coast,
     Water,
     Ice,
 }
 
-/// Uses the x/y coordinates to get the location of a tile in a 1 dimensional array; used for the tile map and the heightmap
-pub fn xy_idx(x: i32, y: i32) -> usize {
-    (y as usize * MAPWIDTH) + x as usize
+/// A resource found on a tile, claiming it either makes the tile worth more or lets the
+/// player build a new type of unit
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum Deposit {
+    Iron,
+    Horses,
+    Fish,
+    Stone,
+}
+
+impl Deposit {
+    pub fn name(&self) -> String {
+        match self {
+            Deposit::Iron => "Iron".to_string(),
+            Deposit::Horses => "Horses".to_string(),
+            Deposit::Fish => "Fish".to_string(),
+            Deposit::Stone => "Stone".to_string(),
+        }
+    }
+
+    /// What the deposit adds on top of the yields of the tile it's on
+    pub fn yields(&self) -> Yields {
+        let (food, production, gold) = match self {
+            Deposit::Iron => (0, 1, 0),
+            Deposit::Horses => (1, 1, 0),
+            Deposit::Fish => (2, 0, 1),
+            Deposit::Stone => (0, 2, 0),
+        };
+        Yields {
+            food,
+            production,
+            gold,
+        }
+    }
+}
+
+impl TileType {
+    /// The name of the tile shown in the sidebar
+    pub fn name(&self) -> String {
+        match self {
+            TileType::Mountain => "Mountain".to_string(),
+            TileType::Hills => "Hills".to_string(),
+            TileType::Forest => "Forest".to_string(),
+            TileType::Jungle => "Jungle".to_string(),
+            TileType::Grasslands => "Grasslands".to_string(),
+            TileType::Desert => "Desert".to_string(),
+            TileType::Tundra => "Tundra".to_string(),
+            TileType::Snow => "Snow".to_string(),
+            TileType::Coast => "Coast".to_string(),
+            TileType::Water => "Water".to_string(),
+            TileType::Ice => "Ice".to_string(),
+        }
+    }
+
+    /// How much stamina it costs a unit to step onto this tile, tiles units can't walk on
+    /// don't have a cost. Used for moving units by hand, for pathfinding, and for the sidebar.
+    pub fn movement_cost(&self) -> Option<u8> {
+        match self {
+            TileType::Grasslands | TileType::Desert | TileType::Tundra => Some(1),
+            TileType::Coast => Some(1),
+            TileType::Forest | TileType::Jungle | TileType::Hills => Some(2),
+            TileType::Snow => Some(3),
+            TileType::Mountain | TileType::Water | TileType::Ice => None,
+        }
+    }
+
+    /// What a claimed tile of this type gives its owner each turn while it's being worked by
+    /// one of their forts
+    pub fn yields(&self) -> Yields {
+        let (food, production, gold) = match self {
+            TileType::Grasslands => (2, 0, 0),
+            TileType::Forest => (1, 2, 0),
+            TileType::Jungle => (2, 1, 0),
+            TileType::Hills => (0, 2, 0),
+            TileType::Desert => (0, 0, 1),
+            TileType::Tundra => (1, 0, 0),
+            TileType::Snow => (0, 0, 0),
+            TileType::Coast => (1, 0, 2),
+            TileType::Water => (1, 0, 1),
+            TileType::Mountain => (0, 1, 0),
+            TileType::Ice => (0, 0, 0),
+        };
+        Yields {
+            food,
+            production,
+            gold,
+        }
+    }
+
+    /// Defense bonus given to a unit standing on this tile as a percentage, a bonus of 50
+    /// means the unit only takes two thirds of the damage it's dealt
+    pub fn defense_bonus(&self) -> i32 {
+        match self {
+            TileType::Forest | TileType::Jungle | TileType::Hills => 50,
+            TileType::Coast => 25,
+            _ => 0,
+        }
+    }
+}
+
+/// Picks the type of tile from the height, temperature and moisture at the same place.
+/// The height decides between water, flat land, hills and mountains, and the flat land
+/// is then split up by how hot and wet it is. The poles are cold enough to freeze over
+/// into ice caps, water included.
+fn biome(height: f32, temperature: f32, moisture: f32, config: &GeneratorConfig) -> TileType {
+    if temperature < ICE_TEMPERATURE {
+        return TileType::Ice;
+    }
+
+    if height > config.mountain_level {
+        TileType::Mountain
+    } else if height > config.hills_level {
+        TileType::Hills
+    } else if height > config.sea_level {
+        if temperature < 0.12 {
+            TileType::Snow
+        } else if temperature < 0.22 {
+            TileType::Tundra
+        } else if temperature > 0.7 && moisture > 0.55 {
+            TileType::Jungle
+        } else if temperature > 0.55 && moisture < 0.45 {
+            TileType::Desert
+        } else if moisture > 0.5 {
+            TileType::Forest
+        } else {
+            TileType::Grasslands
+        }
+    } else if height > config.coast_level {
+        TileType::Coast
+    } else {
+        TileType::Water
+    }
 }
 
-/// Contains all tiles of the map and includes tiles that are revealed, visible, blocked, or claimed by a player
+/// Contains all tiles of the map and includes tiles that are blocked or claimed by a player, what
+/// each player has revealed and can see is kept in their FogOfWar component
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Map {
     pub tiles: Vec<TileType>,
+    pub deposits: Vec<Option<Deposit>>,
+    pub elevation: Vec<f32>,
+    pub rivers: Vec<bool>,
     pub width: i32,
     pub height: i32,
-    pub revealed_tiles: Vec<bool>,
-    pub visible_tiles: Vec<bool>,
     pub blocked: Vec<bool>,
+    #[serde(skip)]
     pub tile_content: Vec<Vec<Entity>>,
     pub claimed_tiles: Vec<PlayerOrder>,
 }
 
 impl Map {
-    pub fn new_map() -> Map {
+    /// Generates a new map with the size and terrain settings from the config, with every
+    /// random roll coming from the given generator so a seeded generator always produces
+    /// the same map
+    pub fn new_map(rng: &mut RandomNumberGenerator, config: &GeneratorConfig) -> Map {
+        let tile_count = config.tile_count();
         let mut map = Map {
-            tiles: vec![TileType::Water; MAPCOUNT],
-            width: MAPWIDTH as i32,
-            height: MAPHEIGHT as i32,
-            revealed_tiles: vec![false; MAPCOUNT],
-            visible_tiles: vec![false; MAPCOUNT],
-            blocked: vec![false; MAPCOUNT],
-            tile_content: vec![Vec::new(); MAPCOUNT],
-            claimed_tiles: vec![NoPlayer; MAPCOUNT],
+            tiles: vec![TileType::Water; tile_count],
+            deposits: vec![None; tile_count],
+            elevation: vec![0.0; tile_count],
+            rivers: vec![false; tile_count],
+            width: config.width,
+            height: config.height,
+            blocked: vec![false; tile_count],
+            tile_content: vec![Vec::new(); tile_count],
+            claimed_tiles: vec![NoPlayer; tile_count],
         };
 
         // Idea for map generation came from: https://gillesleblanc.wordpress.com/2012/10/16/creating-a-random-2d-game-world-map/
-        let perlin = generate_heightmap();
-
-        for y in 0..map.height {
-            for x in 0..map.width {
-                let idx = xy_idx(x, y);
-
-                if perlin[idx] > (1.0 / 6.0) {
-                    map.tiles[idx] = TileType::Mountain;
-                } else if perlin[idx] > 0.0 {
-                    map.tiles[idx] = TileType::Forest;
-                } else if perlin[idx] > -(1.0 / 10.0) {
-                    map.tiles[idx] = TileType::Grasslands;
-                } else if perlin[idx] > -(1.0 / 8.0) {
-                    map.tiles[idx] = TileType::Coast;
-                } else {
-                    map.tiles[idx] = TileType::Water;
-                }
+        let mut perlin = generate_heightmap(rng, config);
+        let temperature = generate_temperature(rng, config);
+        let levels = shape_heightmap(&mut perlin, &temperature, config);
+        let moisture = generate_moisture(rng, config);
+
+        for idx in 0..tile_count {
+            map.tiles[idx] = biome(perlin[idx], temperature[idx], moisture[idx], &levels);
+        }
+        map.elevation = perlin;
+
+        map.place_rivers(rng);
+        map.place_deposits(rng);
+
+        map
+    }
+
+    /// Uses the x/y coordinates to get the location of a tile in the map's 1 dimensional arrays
+    pub fn xy_idx(&self, x: i32, y: i32) -> usize {
+        (y as usize * self.width as usize) + x as usize
+    }
+
+    /// Starts rivers from random spots in the hills and mountains and runs them downhill,
+    /// only keeping the ones that make it all the way to the water
+    fn place_rivers(&mut self, rng: &mut RandomNumberGenerator) {
+        let mut flowed = vec![false; self.tiles.len()];
+        for _ in 0..self.tiles.len() / TILES_PER_RIVER_ATTEMPT {
+            let x = rng.range(0, self.width);
+            let y = rng.range(0, self.height);
+            let source = self.xy_idx(x, y);
+
+            if !matches!(self.tiles[source], TileType::Hills | TileType::Mountain) {
+                continue;
+            }
+
+            self.add_river(source, &mut flowed);
+        }
+    }
+
+    /// Traces a river down from the source and draws it onto the map. Any dips the river
+    /// had to flow out of are filled up to where it spilled over, so it runs downhill the
+    /// whole way to the water. Every tile a river flows through is marked in `flowed`,
+    /// even the ones it isn't drawn on, so later rivers join it instead of filling over it.
+    fn add_river(&mut self, source: usize, flowed: &mut [bool]) -> Option<Vec<usize>> {
+        if flowed[source] {
+            return None;
+        }
+        let path = self.trace_river(source, flowed)?;
+
+        for i in (1..path.len()).rev() {
+            let downstream = self.elevation[path[i]];
+            if self.elevation[path[i - 1]] < downstream {
+                self.elevation[path[i - 1]] = downstream;
             }
         }
-        // Make the boundaries walls
-        for x in 0..map.width {
-            map.tiles[xy_idx(x, 0)] = TileType::Ice;
-            map.tiles[xy_idx(x, map.height - 1)] = TileType::Ice;
+
+        // Rivers are only drawn over tiles units can walk on, so a river starting
+        // up in the mountains seems to come out from the bottom of them
+        for idx in path.iter() {
+            flowed[*idx] = true;
+            if self.tiles[*idx].movement_cost().is_some() {
+                self.rivers[*idx] = true;
+            }
         }
-        for y in 0..map.height {
-            map.tiles[xy_idx(0, y)] = TileType::Ice;
-            map.tiles[xy_idx(map.width - 1, y)] = TileType::Ice;
+
+        Some(path)
+    }
+
+    /// Follows the steepest way down from the source until the river reaches the water or
+    /// runs into another river. When the river ends up in a dip it flows over the lowest
+    /// side it hasn't already been through, which fills up the dip like a lake would.
+    /// Returns None if the river gets stuck or frozen before it finds the water.
+    fn trace_river(&self, source: usize, flowed: &[bool]) -> Option<Vec<usize>> {
+        let mut path = vec![source];
+        let mut current = source;
+
+        while path.len() < MAX_RIVER_LENGTH {
+            match self.tiles[current] {
+                TileType::Water | TileType::Coast => {
+                    path.pop();
+                    return Some(path);
+                }
+                TileType::Ice => return None,
+                _ => {}
+            }
+            if flowed[current] {
+                return Some(path);
+            }
+
+            let next = self
+                .neighbours(current)
+                .into_iter()
+                .filter(|idx| !path.contains(idx))
+                .min_by(|a, b| self.elevation[*a].total_cmp(&self.elevation[*b]))?;
+            path.push(next);
+            current = next;
         }
 
-        map
+        None
+    }
+
+    /// Gets the tiles directly above, below, and to either side of the given tile that are
+    /// still on the map
+    fn neighbours(&self, idx: usize) -> Vec<usize> {
+        let x = idx as i32 % self.width;
+        let y = idx as i32 / self.width;
+
+        [(-1, 0), (1, 0), (0, -1), (0, 1)]
+            .iter()
+            .filter(|(dx, dy)| self.in_bounds(Point::new(x + dx, y + dy)))
+            .map(|(dx, dy)| self.xy_idx(x + dx, y + dy))
+            .collect()
+    }
+
+    /// Checks if a river runs through the tile or past one of its sides
+    pub fn next_to_river(&self, idx: usize) -> bool {
+        self.rivers[idx] || self.neighbours(idx).iter().any(|n| self.rivers[*n])
+    }
+
+    /// How much stamina it costs a unit to step onto the tile, which is the cost of the
+    /// terrain plus a bit more if there's a river to cross
+    pub fn movement_cost(&self, idx: usize) -> Option<u8> {
+        let river_cost = if self.rivers[idx] {
+            RIVER_CROSSING_COST
+        } else {
+            0
+        };
+        self.tiles[idx]
+            .movement_cost()
+            .map(|cost| cost + river_cost)
+    }
+
+    /// Scatters deposits over the terrain, with each deposit only showing up on the type of
+    /// terrain it belongs to
+    fn place_deposits(&mut self, rng: &mut RandomNumberGenerator) {
+        for y in 1..self.height - 1 {
+            for x in 1..self.width - 1 {
+                let idx = self.xy_idx(x, y);
+                let roll = rng.roll_dice(1, 100);
+
+                self.deposits[idx] = match self.tiles[idx] {
+                    TileType::Coast if roll <= 8 => Some(Deposit::Fish),
+                    TileType::Grasslands if roll <= 2 => Some(Deposit::Horses),
+                    TileType::Forest if roll <= 1 => Some(Deposit::Iron),
+                    TileType::Hills if roll <= 3 => Some(Deposit::Iron),
+                    TileType::Tundra if roll <= 2 => Some(Deposit::Horses),
+                    // Stone is only found along the edges of mountain ranges, where it can
+                    // actually be claimed and worked
+                    TileType::Mountain if roll <= 10 && self.next_to_land(x, y) => {
+                        Some(Deposit::Stone)
+                    }
+                    _ => None,
+                };
+            }
+        }
+    }
+
+    /// Checks if any of the tiles around the given tile can be walked on
+    fn next_to_land(&self, x: i32, y: i32) -> bool {
+        [(-1, 0), (1, 0), (0, -1), (0, 1)]
+            .iter()
+            .any(|(dx, dy)| self.tiles[self.xy_idx(x + dx, y + dy)].movement_cost().is_some())
+    }
+
+    /// What a claimed tile gives its owner each turn while it's being worked, counting the
+    /// terrain, any deposit on it, and any river running through or past it
+    pub fn tile_yields(&self, idx: usize) -> Yields {
+        let bonus = self.deposits[idx]
+            .map(|deposit| deposit.yields())
+            .unwrap_or_default();
+        let river = if self.next_to_river(idx) {
+            RIVER_YIELDS
+        } else {
+            Yields::default()
+        };
+        self.tiles[idx].yields() + bonus + river
     }
 
     // Both populate_blocked and clear_content_index came from chapter 7 of the roguelike tutorial
     pub fn populate_blocked(&mut self) {
         for (i, tile) in self.tiles.iter_mut().enumerate() {
-            if *tile == TileType::Ice || *tile == TileType::Mountain || *tile == TileType::Water {
+            if tile.movement_cost().is_none() {
                 self.blocked[i] = true;
             }
         }
@@ -114,6 +409,20 @@ impl Map {
             *tile = false;
         }
     }
+
+    // Came from chapter 9 of the roguelike tutorial, units can only move in the four
+    // cardinal directions so diagonal exits are left out. Returns what it costs to move
+    // onto the tile if it can be moved onto.
+    fn exit_cost(&self, x: i32, y: i32) -> Option<f32> {
+        if x < 0 || x > self.width - 1 || y < 0 || y > self.height - 1 {
+            return None;
+        }
+        let idx = self.xy_idx(x, y);
+        if self.blocked[idx] {
+            return None;
+        }
+        self.movement_cost(idx).map(|cost| cost as f32)
+    }
 }
 
 impl Algorithm2D for Map {
@@ -124,6 +433,197 @@ impl Algorithm2D for Map {
 
 impl BaseMap for Map {
     fn is_opaque(&self, idx: usize) -> bool {
-        self.tiles[idx as usize] == TileType::Ice
+        self.tiles[idx] == TileType::Ice
+    }
+
+    fn get_available_exits(&self, idx: usize) -> SmallVec<[(usize, f32); 10]> {
+        let mut exits = SmallVec::new();
+        let x = idx as i32 % self.width;
+        let y = idx as i32 / self.width;
+
+        if let Some(cost) = self.exit_cost(x - 1, y) {
+            exits.push((idx - 1, cost))
+        };
+        if let Some(cost) = self.exit_cost(x + 1, y) {
+            exits.push((idx + 1, cost))
+        };
+        if let Some(cost) = self.exit_cost(x, y - 1) {
+            exits.push((idx - self.width as usize, cost))
+        };
+        if let Some(cost) = self.exit_cost(x, y + 1) {
+            exits.push((idx + self.width as usize, cost))
+        };
+
+        exits
+    }
+
+    fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
+        let w = self.width as usize;
+        let p1 = Point::new(idx1 % w, idx1 / w);
+        let p2 = Point::new(idx2 % w, idx2 / w);
+        DistanceAlg::Manhattan.distance2d(p1, p2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small map made up of nothing but grasslands
+    fn grass_map(width: i32, height: i32) -> Map {
+        let tile_count = (width * height) as usize;
+        Map {
+            tiles: vec![TileType::Grasslands; tile_count],
+            deposits: vec![None; tile_count],
+            elevation: vec![0.0; tile_count],
+            rivers: vec![false; tile_count],
+            width,
+            height,
+            blocked: vec![false; tile_count],
+            tile_content: vec![Vec::new(); tile_count],
+            claimed_tiles: vec![NoPlayer; tile_count],
+        }
+    }
+
+    #[test]
+    fn biomes_follow_height_temperature_and_moisture() {
+        let config = GeneratorConfig::default();
+        let (water, coast, flat, hills, mountain) = (-0.5, -0.11, 0.0, 0.12, 0.5);
+        // (height, temperature, moisture, tile)
+        let table = [
+            (water, 0.5, 0.5, TileType::Water),
+            (config.coast_level, 0.5, 0.5, TileType::Water),
+            (coast, 0.5, 0.5, TileType::Coast),
+            (config.sea_level, 0.5, 0.5, TileType::Coast),
+            (hills, 0.5, 0.5, TileType::Hills),
+            (config.hills_level, 0.5, 0.2, TileType::Grasslands),
+            (mountain, 0.5, 0.5, TileType::Mountain),
+            (config.mountain_level, 0.5, 0.5, TileType::Hills),
+            // The cold freezes everything over, no matter how high or wet it is
+            (water, 0.05, 0.5, TileType::Ice),
+            (mountain, 0.05, 0.9, TileType::Ice),
+            (flat, ICE_TEMPERATURE, 0.5, TileType::Snow),
+            (flat, 0.11, 0.9, TileType::Snow),
+            (flat, 0.12, 0.9, TileType::Tundra),
+            (flat, 0.21, 0.1, TileType::Tundra),
+            (flat, 0.22, 0.2, TileType::Grasslands),
+            (flat, 0.8, 0.6, TileType::Jungle),
+            (flat, 0.7, 0.6, TileType::Forest),
+            (flat, 0.8, 0.55, TileType::Forest),
+            (flat, 0.6, 0.4, TileType::Desert),
+            (flat, 0.55, 0.4, TileType::Grasslands),
+            (flat, 0.6, 0.45, TileType::Grasslands),
+            (flat, 0.4, 0.51, TileType::Forest),
+            (flat, 0.4, 0.5, TileType::Grasslands),
+        ];
+        for (height, temperature, moisture, tile) in table.iter() {
+            assert_eq!(
+                biome(*height, *temperature, *moisture, &config),
+                *tile,
+                "height {}, temperature {}, moisture {}",
+                height,
+                temperature,
+                moisture
+            );
+        }
+    }
+
+    #[test]
+    fn every_terrain_has_its_own_movement_cost() {
+        let costs = [
+            (TileType::Grasslands, Some(1)),
+            (TileType::Desert, Some(1)),
+            (TileType::Tundra, Some(1)),
+            (TileType::Coast, Some(1)),
+            (TileType::Forest, Some(2)),
+            (TileType::Jungle, Some(2)),
+            (TileType::Hills, Some(2)),
+            (TileType::Snow, Some(3)),
+            (TileType::Mountain, None),
+            (TileType::Water, None),
+            (TileType::Ice, None),
+        ];
+        for (tile, cost) in costs.iter() {
+            assert_eq!(tile.movement_cost(), *cost, "{:?}", tile);
+        }
+    }
+
+    #[test]
+    fn rivers_cost_extra_to_cross() {
+        let mut map = grass_map(3, 3);
+        let idx = map.xy_idx(1, 1);
+        map.tiles[idx] = TileType::Hills;
+        assert_eq!(map.movement_cost(idx), Some(2));
+
+        map.rivers[idx] = true;
+        assert_eq!(map.movement_cost(idx), Some(2 + RIVER_CROSSING_COST));
+
+        map.tiles[idx] = TileType::Water;
+        assert_eq!(map.movement_cost(idx), None);
+    }
+
+    #[test]
+    fn exits_leave_out_edges_blocked_tiles_and_impassable_terrain() {
+        let mut map = grass_map(3, 3);
+        let water = map.xy_idx(1, 0);
+        let blocked = map.xy_idx(0, 1);
+        let forest = map.xy_idx(2, 1);
+        map.tiles[water] = TileType::Water;
+        map.blocked[blocked] = true;
+        map.tiles[forest] = TileType::Forest;
+
+        assert_eq!(map.exit_cost(-1, 0), None);
+        assert_eq!(map.exit_cost(3, 0), None);
+        assert_eq!(map.exit_cost(0, -1), None);
+        assert_eq!(map.exit_cost(0, 3), None);
+        assert_eq!(map.exit_cost(1, 0), None);
+        assert_eq!(map.exit_cost(0, 1), None);
+        assert_eq!(map.exit_cost(2, 1), Some(2.0));
+        assert_eq!(map.exit_cost(1, 2), Some(1.0));
+
+        let mut exits = map.get_available_exits(map.xy_idx(1, 1)).to_vec();
+        exits.sort_by_key(|(idx, _)| *idx);
+        assert_eq!(exits, vec![(forest, 2.0), (map.xy_idx(1, 2), 1.0)]);
+
+        // The corner only has water and a blocked tile next to it
+        assert!(map.get_available_exits(0).is_empty());
+    }
+
+    #[test]
+    fn rivers_run_downhill_to_the_water() {
+        let mut rng = RandomNumberGenerator::seeded(7);
+        let mut map = Map::new_map(&mut rng, &GeneratorConfig::default());
+        map.rivers = vec![false; map.tiles.len()];
+        let mut flowed = vec![false; map.tiles.len()];
+
+        let mut rivers = Vec::new();
+        for source in 0..map.tiles.len() {
+            if !matches!(map.tiles[source], TileType::Hills | TileType::Mountain) {
+                continue;
+            }
+            let joined = flowed.clone();
+            if let Some(path) = map.add_river(source, &mut flowed) {
+                let mouth = *path.last().unwrap();
+                let reaches_water = map
+                    .neighbours(mouth)
+                    .iter()
+                    .any(|idx| matches!(map.tiles[*idx], TileType::Water | TileType::Coast));
+                assert!(
+                    reaches_water || joined[mouth],
+                    "river from {} ends early",
+                    source
+                );
+                rivers.push(path);
+            }
+        }
+        assert!(!rivers.is_empty());
+
+        // Checked after they've all been placed so later rivers can't have undone the
+        // dips filled by earlier ones
+        for path in rivers.iter() {
+            for step in path.windows(2) {
+                assert!(map.elevation[step[1]] <= map.elevation[step[0]]);
+            }
+        }
     }
 }
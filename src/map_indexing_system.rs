@@ -5,7 +5,7 @@
 //! Link: 
 //! https://bfnightly.bracketproductions.com/rustbook/chapter_7.html#blocking-access
 
-use crate::{xy_idx, BlocksTile, Map, Position};
+use crate::{BlocksTile, Map, Position};
 use specs::prelude::*;
 
 pub struct MapIndexingSystem {}
@@ -27,7 +27,7 @@ impl<'a> System<'a> for MapIndexingSystem {
         map.populate_blocked();
         map.clear_content_index();
         for (entity, position) in (&entities, &position).join() {
-            let idx = xy_idx(position.x, position.y);
+            let idx = map.xy_idx(position.x, position.y);
 
             let _p: Option<&BlocksTile> = blockers.get(entity);
             if let Some(_p) = _p {
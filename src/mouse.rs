@@ -0,0 +1,72 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Turns the state of the mouse into clicks. The window only says which buttons
+//! are being held down, so a click gets counted on the frame a button goes from
+//! up to down. Clicks are turned into commands in command.rs the same way key
+//! presses are, so the rest of the game never has to know about the mouse.
+
+use crate::camera::screen_to_tile;
+use crate::Position;
+use bracket_lib::prelude::*;
+use specs::prelude::*;
+
+/// The mouse buttons the game listens to
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum MouseButton {
+    Left,
+    Right,
+}
+
+impl MouseButton {
+    const ALL: [MouseButton; 2] = [MouseButton::Left, MouseButton::Right];
+
+    /// The number the window gives the button
+    fn index(&self) -> usize {
+        match self {
+            MouseButton::Left => 0,
+            MouseButton::Right => 1,
+        }
+    }
+}
+
+/// A button being pressed down over a spot on the screen, along with the tile under the
+/// mouse if it was over the map view
+#[derive(Copy, Clone, Debug)]
+pub struct Click {
+    pub button: MouseButton,
+    pub screen: Point,
+    pub tile: Option<Position>,
+}
+
+/// Remembers which buttons were held down on the last frame
+#[derive(Default)]
+pub struct MouseState {
+    held: [bool; 2],
+}
+
+impl MouseState {
+    /// Checks for a button that's been pressed down since the last frame, if both were
+    /// pressed on the same frame the left button wins
+    pub fn poll(&mut self, ecs: &World, ctx: &BTerm) -> Option<Click> {
+        let mut pressed = None;
+        {
+            let input = INPUT.lock();
+            for button in MouseButton::ALL.iter() {
+                let down = input.is_mouse_button_pressed(button.index());
+                if down && !self.held[button.index()] && pressed.is_none() {
+                    pressed = Some(*button);
+                }
+                self.held[button.index()] = down;
+            }
+        }
+
+        pressed.map(|button| {
+            let screen = ctx.mouse_point();
+            Click {
+                button,
+                screen,
+                tile: screen_to_tile(ecs, ctx, screen),
+            }
+        })
+    }
+}
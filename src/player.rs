@@ -4,9 +4,14 @@
 //! this project.
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_0.html
 
+use crate::command::{Command, Outcome};
+use crate::gui::minimap::overview_minimap;
+use crate::gui::window::select_entity;
+use crate::gui::{select_player, SelectionType};
+use crate::saveload_system::{load_game, save_game, SAVE_FILE};
 use crate::{
-    error_handling, xy_idx, FailedMoveReason, Map, Player, Position, RunState, State, TileType,
-    World,
+    end_player_turn, error_handling, FailedMoveReason, FogOfWar, Fort, GameLog, LogEvent, Map,
+    MessageType, PlayerOrder, Position, RunState, State, TileType, Unit, World,
 };
 use bracket_lib::prelude::*;
 use specs::prelude::*;
@@ -19,12 +24,18 @@ fn try_move_player(
     ecs: &mut World,
 ) -> Result<(i32, i32), FailedMoveReason> {
     let mut positions = ecs.write_storage::<Position>();
-    let mut players = ecs.write_storage::<Player>();
+    let fogs = ecs.read_storage::<FogOfWar>();
+    let player_entity = ecs.fetch::<Entity>();
     let map = ecs.fetch::<Map>();
 
-    if let Some((_player, pos)) = (&mut players, &mut positions).join().next() {
-        let destination_idx = xy_idx(pos.x + delta_x, pos.y + delta_y);
-        if map.tiles[destination_idx] != TileType::Ice && map.revealed_tiles[destination_idx] {
+    if let (Some(pos), Some(fog)) = (positions.get_mut(*player_entity), fogs.get(*player_entity)) {
+        // The cursor stops at the edges of the map now that there's no ice around it
+        if !map.in_bounds(Point::new(pos.x + delta_x, pos.y + delta_y)) {
+            return Err(FailedMoveReason::TileBlocked);
+        }
+
+        let destination_idx = map.xy_idx(pos.x + delta_x, pos.y + delta_y);
+        if map.tiles[destination_idx] != TileType::Ice && fog.revealed_tiles[destination_idx] {
             let mut ppos = ecs.write_resource::<Point>();
             pos.x = min(map.width, max(0, pos.x + delta_x));
             pos.y = min(map.height, max(0, pos.y + delta_y));
@@ -42,10 +53,10 @@ fn try_move_player(
 /// Teleports the player to the location of the stopped unit so the player can look around the area
 pub fn teleport_player(unit_pos: Position, ecs: &mut World) {
     let mut positions = ecs.write_storage::<Position>();
-    let mut players = ecs.write_storage::<Player>();
+    let player_entity = ecs.fetch::<Entity>();
     let map = ecs.fetch::<Map>();
 
-    for (_player, pos) in (&mut players, &mut positions).join() {
+    if let Some(pos) = positions.get_mut(*player_entity) {
         let mut ppos = ecs.write_resource::<Point>();
         pos.x = min(map.width, max(0, unit_pos.x));
         pos.y = min(map.height, max(0, unit_pos.y));
@@ -54,35 +65,104 @@ pub fn teleport_player(unit_pos: Position, ecs: &mut World) {
     }
 }
 
-/// Grabs input for the cursor to let it be moved around or for it to open a menu or close the game
-pub fn player_input(gs: &mut State, ctx: &mut BTerm) -> RunState {
-    match ctx.key {
-        None => return RunState::MoveCursor,
-        Some(key) => match key {
-            VirtualKeyCode::A => {
-                let res = try_move_player(-1, 0, &mut gs.ecs);
-                error_handling::handle_move_result(&mut gs.ecs, res, gs.verbose);
-            }
-            VirtualKeyCode::D => {
-                let res = try_move_player(1, 0, &mut gs.ecs);
-                error_handling::handle_move_result(&mut gs.ecs, res, gs.verbose);
-            }
-            VirtualKeyCode::W => {
-                let res = try_move_player(0, -1, &mut gs.ecs);
-                error_handling::handle_move_result(&mut gs.ecs, res, gs.verbose);
+/// Finds the unit or fort owned by the player whose turn it is on the given tile, units are
+/// picked over a fort they're standing in
+fn owned_entity_at(ecs: &World, x: i32, y: i32) -> Option<(Entity, SelectionType)> {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let units = ecs.read_storage::<Unit>();
+    let forts = ecs.read_storage::<Fort>();
+    let order = select_player(ecs)?;
+    let on_tile = |pos: &Position| pos.x == x && pos.y == y;
+
+    let unit = (&entities, &positions, &units)
+        .join()
+        .find(|(_, pos, unit)| on_tile(pos) && unit.owner == order)
+        .map(|(entity, _, _)| (entity, SelectionType::Unit));
+    unit.or_else(|| {
+        (&entities, &positions, &forts)
+            .join()
+            .find(|(_, pos, fort)| on_tile(pos) && fort.owner == order)
+            .map(|(entity, _, _)| (entity, SelectionType::Fort))
+    })
+}
+
+/// Carries out a command given while the player is moving the cursor around, letting the cursor
+/// move, select whatever the player owns on a tile, open a menu, end the turn, save or load the
+/// game, or close the game
+pub fn cursor_command(gs: &mut State, command: Command) -> Outcome {
+    match command {
+        Command::MoveCursor(delta_x, delta_y) => {
+            let res = try_move_player(delta_x, delta_y, &mut gs.ecs);
+            let moved = res.is_ok();
+            error_handling::handle_move_result(&mut gs.ecs, res, gs.verbose);
+            Outcome::from_success(moved)
+        }
+        Command::SelectAt(x, y) => match owned_entity_at(&gs.ecs, x, y) {
+            Some((entity, selection)) => {
+                select_entity(gs, entity, selection);
+                Outcome::Applied
             }
-            VirtualKeyCode::S => {
-                let res = try_move_player(0, 1, &mut gs.ecs);
+            None => {
+                // Moving the cursor straight there, with the same checks as moving it a tile
+                let cursor = *gs.ecs.fetch::<Point>();
+                let res = try_move_player(x - cursor.x, y - cursor.y, &mut gs.ecs);
+                let moved = res.is_ok();
                 error_handling::handle_move_result(&mut gs.ecs, res, gs.verbose);
+                Outcome::from_success(moved)
             }
-			VirtualKeyCode::N => {	
-				return RunState::EndTurn;
-			}
-            VirtualKeyCode::I => return RunState::ShowUnits,
-            VirtualKeyCode::F => return RunState::ShowForts,
-            VirtualKeyCode::Escape => std::process::exit(0),
-            _ => return RunState::MoveCursor,
         },
+        Command::EndTurn => {
+            end_player_turn(gs);
+            Outcome::Applied
+        }
+        Command::ShowUnits => {
+            gs.runstate = RunState::ShowUnits;
+            Outcome::Applied
+        }
+        Command::ShowForts => {
+            gs.runstate = RunState::ShowForts;
+            Outcome::Applied
+        }
+        Command::ShowOverview => {
+            // The marker starts out on the cell the cursor is in
+            let minimap = overview_minimap(&gs.ecs);
+            let cursor = *gs.ecs.fetch::<Point>();
+            gs.overview_marker = minimap.cell_of(cursor.x, cursor.y);
+            gs.runstate = RunState::Overview;
+            Outcome::Applied
+        }
+        Command::ShowLog => {
+            // The log always opens on the newest message
+            gs.last_option = 0;
+            gs.runstate = RunState::ShowLog;
+            Outcome::Applied
+        }
+        Command::SaveGame => {
+            let res = save_game(gs, SAVE_FILE);
+            let saved = res.is_ok();
+            let (message, message_type) = match res {
+                Ok(()) => (format!("Saved game to {}", SAVE_FILE), MessageType::Other),
+                Err(e) => (format!("Unable to save game, {}", e), MessageType::Error),
+            };
+            let player = select_player(&gs.ecs).unwrap_or(PlayerOrder::NoPlayer);
+            let event = LogEvent::new(message_type, player, message);
+            gs.ecs.fetch_mut::<GameLog>().push(event);
+            Outcome::from_success(saved)
+        }
+        Command::LoadGame => {
+            let res = load_game(gs, SAVE_FILE);
+            let loaded = res.is_ok();
+            let (message, message_type) = match res {
+                Ok(()) => (format!("Loaded game from {}", SAVE_FILE), MessageType::Other),
+                Err(e) => (format!("Unable to load game, {}", e), MessageType::Error),
+            };
+            let player = select_player(&gs.ecs).unwrap_or(PlayerOrder::NoPlayer);
+            let event = LogEvent::new(message_type, player, message);
+            gs.ecs.fetch_mut::<GameLog>().push(event);
+            Outcome::from_success(loaded)
+        }
+        Command::Quit => Outcome::Quit,
+        _ => Outcome::Ignored,
     }
-    RunState::MoveCursor
 }
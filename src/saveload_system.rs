@@ -0,0 +1,347 @@
+//! Made by: Thomas Gardner, 2022
+//!
+//! Big thanks to the Rust roguelike tutorial, which helped quite a bit with
+//! this project. The code for saving and loading the game comes from section
+//! 2.10: Saving and Loading, and was changed to store a versioned header in
+//! front of the components and to report errors instead of panicking
+//! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_11.html
+
+use crate::gui::select_player;
+use crate::{
+    BlocksTile, FogOfWar, Fort, GameLog, GoTo, Map, Name, Player, PlayerOrder, Position,
+    Renderable, RunState, SerializationHelper, SerializeMe, State, Unit, Viewshed,
+};
+use bracket_lib::prelude::{Point, RandomNumberGenerator};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use specs::error::NoError;
+use specs::prelude::*;
+use specs::saveload::{
+    DeserializeComponents, MarkedBuilder, SerializeComponents, SimpleMarker, SimpleMarkerAllocator,
+};
+use std::fmt;
+use std::fs::{self, File};
+
+/// Where the game gets saved to and loaded from when using the in game keys
+pub const SAVE_FILE: &str = "./savegame.json";
+
+/// Bumped whenever the layout of the save file changes, so old saves get rejected
+/// instead of being loaded into the wrong components
+pub const SAVE_VERSION: u32 = 11;
+
+/// Written at the start of every save file, holds the parts of the game state that
+/// live outside of the ECS
+#[derive(Serialize, Deserialize)]
+struct SaveHeader {
+    version: u32,
+    seed: u64,
+    turns: u32,
+    runstate: RunState,
+    active_player: PlayerOrder,
+    rng: RandomNumberGenerator,
+}
+
+/// Used for returning why saving or loading a game failed
+#[derive(Debug)]
+pub enum SaveLoadError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+    MissingData,
+}
+
+impl fmt::Display for SaveLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveLoadError::Io(e) => write!(f, "unable to access save file: {}", e),
+            SaveLoadError::Format(e) => write!(f, "save file is malformed: {}", e),
+            SaveLoadError::UnsupportedVersion(version) => write!(
+                f,
+                "save file version {} is not supported (expected {})",
+                version, SAVE_VERSION
+            ),
+            SaveLoadError::MissingData => write!(f, "save file is missing data"),
+        }
+    }
+}
+
+impl std::error::Error for SaveLoadError {}
+
+impl From<std::io::Error> for SaveLoadError {
+    fn from(e: std::io::Error) -> Self {
+        SaveLoadError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SaveLoadError {
+    fn from(e: serde_json::Error) -> Self {
+        SaveLoadError::Format(e)
+    }
+}
+
+macro_rules! serialize_individually {
+    ($ecs:expr, $ser:expr, $data:expr, $( $type:ty),*) => {
+        $(
+        SerializeComponents::<NoError, SimpleMarker<SerializeMe>>::serialize(
+            &( $ecs.read_storage::<$type>(), ),
+            &$data.0,
+            &$data.1,
+            &mut $ser,
+        )?;
+        )*
+    };
+}
+
+macro_rules! deserialize_individually {
+    ($ecs:expr, $values:expr, $data:expr, $( $type:ty),*) => {
+        $(
+        DeserializeComponents::<NoError, _>::deserialize(
+            &mut ( &mut $ecs.write_storage::<$type>(), ),
+            &$data.0, // entities
+            &mut $data.1, // marker
+            &mut $data.2, // allocater
+            $values.next().ok_or(SaveLoadError::MissingData)?,
+        )?;
+        )*
+    };
+}
+
+/// Writes the map, message log, every marked entity and the turn/runstate out to the given file
+pub fn save_game(gs: &mut State, path: &str) -> Result<(), SaveLoadError> {
+    let header = SaveHeader {
+        version: SAVE_VERSION,
+        seed: gs.seed,
+        turns: gs.turns,
+        runstate: gs.runstate,
+        active_player: select_player(&gs.ecs).unwrap_or(PlayerOrder::PlayerOne),
+        rng: (*gs.ecs.fetch::<RandomNumberGenerator>()).clone(),
+    };
+
+    // Create helper
+    let mapcopy = (*gs.ecs.fetch::<Map>()).clone();
+    let logcopy = (*gs.ecs.fetch::<GameLog>()).clone();
+    let savehelper = gs
+        .ecs
+        .create_entity()
+        .with(SerializationHelper {
+            map: mapcopy,
+            log: logcopy,
+        })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+
+    let result = write_save(&gs.ecs, &header, path);
+
+    // Clean up
+    gs.ecs
+        .delete_entity(savehelper)
+        .expect("Unable to delete save helper");
+    gs.ecs.maintain();
+
+    result
+}
+
+fn write_save(ecs: &World, header: &SaveHeader, path: &str) -> Result<(), SaveLoadError> {
+    let data = (
+        ecs.entities(),
+        ecs.read_storage::<SimpleMarker<SerializeMe>>(),
+    );
+
+    let writer = File::create(path)?;
+    let mut serializer = serde_json::Serializer::new(writer);
+    header.serialize(&mut serializer)?;
+    serialize_individually!(
+        ecs,
+        serializer,
+        data,
+        Position,
+        Renderable,
+        Viewshed,
+        Player,
+        Unit,
+        Fort,
+        Name,
+        BlocksTile,
+        FogOfWar,
+        GoTo,
+        SerializationHelper
+    );
+
+    Ok(())
+}
+
+/// Replaces the current game with the one stored in the given file. The file is loaded into a
+/// world of its own, which only takes the place of the current game once every part of the
+/// file has been read, so a broken save leaves the game being played as it was
+pub fn load_game(gs: &mut State, path: &str) -> Result<(), SaveLoadError> {
+    let data = fs::read_to_string(path)?;
+    let mut values = serde_json::Deserializer::from_str(&data)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<Value>, _>>()?
+        .into_iter();
+
+    let header = SaveHeader::deserialize(values.next().ok_or(SaveLoadError::MissingData)?)?;
+    if header.version != SAVE_VERSION {
+        return Err(SaveLoadError::UnsupportedVersion(header.version));
+    }
+
+    let mut ecs = State::new().ecs;
+    {
+        let mut d = (
+            &mut ecs.entities(),
+            &mut ecs.write_storage::<SimpleMarker<SerializeMe>>(),
+            &mut ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>(),
+        );
+
+        deserialize_individually!(
+            ecs,
+            values,
+            d,
+            Position,
+            Renderable,
+            Viewshed,
+            Player,
+            Unit,
+            Fort,
+            Name,
+            BlocksTile,
+            FogOfWar,
+            GoTo,
+            SerializationHelper
+        );
+    }
+
+    let mut helper_entity: Option<Entity> = None;
+    let mut restored: Option<SerializationHelper> = None;
+    let mut player_entity: Option<(Entity, Position)> = None;
+    {
+        let entities = ecs.entities();
+        let helpers = ecs.read_storage::<SerializationHelper>();
+        let players = ecs.read_storage::<Player>();
+        let positions = ecs.read_storage::<Position>();
+
+        for (entity, helper) in (&entities, &helpers).join() {
+            helper_entity = Some(entity);
+            restored = Some(helper.clone());
+        }
+        for (entity, player, pos) in (&entities, &players, &positions).join() {
+            if player.order == header.active_player {
+                player_entity = Some((entity, *pos));
+            }
+        }
+    }
+
+    let restored = restored.ok_or(SaveLoadError::MissingData)?;
+    let (entity, pos) = player_entity.ok_or(SaveLoadError::MissingData)?;
+
+    let mut map = restored.map;
+    map.tile_content = vec![Vec::new(); map.tiles.len()];
+    ecs.insert(map);
+    // The log file is left open across loads, so it carries on from the loaded game
+    let mut log = restored.log;
+    log.stream = gs.ecs.try_fetch::<GameLog>().and_then(|log| log.stream.clone());
+    ecs.insert(log);
+    ecs.insert(header.rng);
+    ecs.insert(Point::new(pos.x, pos.y));
+    ecs.insert(entity);
+
+    if let Some(entity) = helper_entity {
+        ecs.delete_entity(entity)
+            .expect("Unable to delete save helper");
+    }
+    ecs.maintain();
+
+    // Nothing could have gone wrong past this point, so the loaded game takes over
+    gs.ecs = ecs;
+    gs.seed = header.seed;
+    gs.turns = header.turns;
+    gs.runstate = header.runstate;
+    gs.selected = "1".to_string();
+    gs.last_option = 0;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{apply, Command, Outcome};
+    use crate::invariants::check_invariants;
+    use std::path::PathBuf;
+
+    fn temp_save(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("civlike_{}_{}.json", name, std::process::id()))
+    }
+
+    fn unit_positions(ecs: &World) -> Vec<(i32, i32)> {
+        let units = ecs.read_storage::<Unit>();
+        let positions = ecs.read_storage::<Position>();
+        let mut found: Vec<(i32, i32)> = (&units, &positions)
+            .join()
+            .map(|(_, pos)| (pos.x, pos.y))
+            .collect();
+        found.sort_unstable();
+        found
+    }
+
+    #[test]
+    fn save_then_load_restores_the_game() {
+        let mut gs = State::test_game(1);
+        assert_eq!(apply(&mut gs, Command::EndTurn), Outcome::Applied);
+        let path = temp_save("round_trip");
+        let path = path.to_str().unwrap();
+        save_game(&mut gs, path).expect("Unable to save");
+
+        let mut loaded = State::new();
+        load_game(&mut loaded, path).expect("Unable to load");
+        fs::remove_file(path).ok();
+
+        assert_eq!(loaded.seed, gs.seed);
+        assert_eq!(loaded.turns, gs.turns);
+        assert_eq!(loaded.runstate, gs.runstate);
+        assert_eq!(select_player(&loaded.ecs), select_player(&gs.ecs));
+        assert_eq!(*loaded.ecs.fetch::<Point>(), *gs.ecs.fetch::<Point>());
+        assert_eq!(loaded.ecs.fetch::<Map>().tiles, gs.ecs.fetch::<Map>().tiles);
+        assert_eq!(
+            loaded.ecs.fetch::<Map>().claimed_tiles,
+            gs.ecs.fetch::<Map>().claimed_tiles
+        );
+        assert_eq!(
+            loaded.ecs.fetch::<GameLog>().events.len(),
+            gs.ecs.fetch::<GameLog>().events.len()
+        );
+        assert_eq!(unit_positions(&loaded.ecs), unit_positions(&gs.ecs));
+        loaded.run_systems();
+        assert!(check_invariants(&loaded.ecs).is_ok());
+    }
+
+    #[test]
+    fn broken_save_leaves_the_game_alone() {
+        let mut gs = State::test_game(1);
+        let path = temp_save("broken");
+        let path = path.to_str().unwrap();
+        save_game(&mut gs, path).expect("Unable to save");
+
+        // Dropping the last few components, which leaves a file that's still valid json but
+        // runs out partway through loading
+        let data = fs::read_to_string(path).unwrap();
+        let values = serde_json::Deserializer::from_str(&data)
+            .into_iter::<Value>()
+            .collect::<Result<Vec<Value>, _>>()
+            .unwrap();
+        let kept = values[..values.len() - 3]
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>();
+        fs::write(path, kept.join("")).unwrap();
+
+        let units_before = unit_positions(&gs.ecs);
+        let entities_before = gs.ecs.entities().join().count();
+        assert!(load_game(&mut gs, path).is_err());
+        fs::remove_file(path).ok();
+
+        assert_eq!(unit_positions(&gs.ecs), units_before);
+        assert_eq!(gs.ecs.entities().join().count(), entities_before);
+        assert!(check_invariants(&gs.ecs).is_ok());
+    }
+}
@@ -6,15 +6,60 @@
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_9.html
 
 use bracket_lib::prelude::*;
-use rand::{thread_rng, Rng};
 use specs::prelude::*;
+use specs::saveload::{MarkedBuilder, SimpleMarker};
+use std::fmt;
 
+use crate::economy::Yields;
 use crate::{
-    xy_idx, BlocksTile, Fort, Map, Name, Player, PlayerOrder, Position, Renderable, TileType, Unit,
-    Viewshed,
+    BlocksTile, FogOfWar, Fort, Map, Name, Player, PlayerOrder, Position, Renderable,
+    SerializeMe, TileType, Unit, Viewshed, FORT_MAX_DEFENSE,
 };
 
-fn player(ecs: &mut World, position: (i32, i32), order: PlayerOrder) -> Entity {
+/// How many units each player starts the game with
+const STARTING_UNITS: usize = 3;
+/// How far from their fort a player's starting units can be placed
+const STARTING_UNIT_SPREAD: i32 = 3;
+/// How far around a start the Grasslands and Forest get counted when comparing starts
+const START_RADIUS: i32 = 4;
+/// Starts closer to each other than this are too cramped to be fair
+pub const MIN_START_DISTANCE: f32 = 12.0;
+/// How many different first picks get tried on each landmass before moving on to the next
+const PLACEMENT_ATTEMPTS: usize = 50;
+/// How far apart the good tiles around two starts can be while still being comparable,
+/// as a share of the first start's good tiles, along with the smallest gap that's allowed
+const START_SCORE_TOLERANCE: f32 = 0.15;
+const MIN_START_SCORE_TOLERANCE: i32 = 2;
+
+/// Used for returning why the players couldn't be placed on the map
+#[derive(PartialEq, Clone, Debug)]
+pub enum PlacementError {
+    ZeroPlayers,
+    NoRoomForUnits { x: i32, y: i32 },
+    NoFairStarts { players: usize },
+}
+
+impl fmt::Display for PlacementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlacementError::ZeroPlayers => write!(f, "there are no players to place"),
+            PlacementError::NoRoomForUnits { x, y } => {
+                write!(f, "there's no room to place a unit around ({}, {})", x, y)
+            }
+            PlacementError::NoFairStarts { players } => write!(
+                f,
+                "no landmass has room for {} fair starts at least {} tiles apart",
+                players, MIN_START_DISTANCE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PlacementError {}
+
+pub fn player(ecs: &mut World, position: (i32, i32), order: PlayerOrder) -> Entity {
+    let tile_count = ecs.fetch::<Map>().tiles.len();
+
     ecs.create_entity()
         .with(Position {
             x: position.0,
@@ -30,10 +75,15 @@ fn player(ecs: &mut World, position: (i32, i32), order: PlayerOrder) -> Entity {
             order,
             unit_count: 0,
             fort_count: 0,
+            stockpile: Yields::default(),
         })
-        .with(Name {
-            name: "Player1".to_string(),
+        .with(Name { name: order.name() })
+        .with(FogOfWar {
+            revealed_tiles: vec![false; tile_count],
+            visible_tiles: vec![false; tile_count],
+            seen_forts: Vec::new(),
         })
+        .marked::<SimpleMarker<SerializeMe>>()
         .build()
 }
 
@@ -43,6 +93,40 @@ pub fn unit(
     name: String,
     range: i32,
     player: PlayerOrder,
+) -> Entity {
+    let stats = Unit {
+        owner: player,
+        health: 20,
+        strength: 8,
+        stamina: 6,
+    };
+    unit_with_stats(ecs, position, name, range, to_cp437('i'), stats)
+}
+
+/// A tougher unit that can only be built by players who have claimed iron
+pub fn swordsman(
+    ecs: &mut World,
+    position: (i32, i32),
+    name: String,
+    range: i32,
+    player: PlayerOrder,
+) -> Entity {
+    let stats = Unit {
+        owner: player,
+        health: 25,
+        strength: 12,
+        stamina: 6,
+    };
+    unit_with_stats(ecs, position, name, range, to_cp437('I'), stats)
+}
+
+fn unit_with_stats(
+    ecs: &mut World,
+    position: (i32, i32),
+    name: String,
+    range: i32,
+    glyph: FontCharType,
+    stats: Unit,
 ) -> Entity {
     ecs.create_entity()
         .with(Position {
@@ -50,17 +134,12 @@ pub fn unit(
             y: position.1,
         })
         .with(Renderable {
-            glyph: to_cp437('i'),
-            fg: RGB::named(CYAN),
+            glyph,
+            fg: stats.owner.unit_color(),
             bg: RGB::named(BLACK),
             render_order: 1,
         })
-        .with(Unit {
-            owner: player,
-            health: 20,
-            strength: 8,
-			stamina: 6,
-        })
+        .with(stats)
         .with(BlocksTile {})
         .with(Name { name })
         .with(Viewshed {
@@ -68,6 +147,7 @@ pub fn unit(
             range,
             dirty: true,
         })
+        .marked::<SimpleMarker<SerializeMe>>()
         .build()
 }
 
@@ -83,26 +163,30 @@ pub fn fort(ecs: &mut World, position: (i32, i32), name: String, owner: PlayerOr
             bg: RGB::named(BLACK),
             render_order: 1,
         })
-        .with(Fort { owner, defense: 10 })
+        .with(Fort {
+            owner,
+            defense: FORT_MAX_DEFENSE,
+            queue: Vec::new(),
+            progress: 0,
+        })
         .with(Name { name })
+        .marked::<SimpleMarker<SerializeMe>>()
         .build()
 }
 
-/// Sets up a player and spawns a fort and units at the given location
+/// Sets up a player and spawns a fort and units at the given location, returns the
+/// player's cursor entity
 pub fn spawn_player_entities(
     ecs: &mut World,
     spawn_point: (i32, i32),
     range: i32,
     player_num: PlayerOrder,
-) {
+) -> Result<Entity, PlacementError> {
     let mut unit_counter = 0;
 	let mut fort_counter = 0;
-    {
-        // Adding the player to the game using the spawn_point established outside this scope
-        ecs.insert(Point::new(spawn_point.0, spawn_point.1));
-        let player_entity = player(ecs, spawn_point, player_num);
-        ecs.insert(player_entity);
-    }
+
+    // Adding the player to the game using the spawn_point established outside this scope
+    let player_entity = player(ecs, spawn_point, player_num);
 
     {
         // Claiming the tiles surrounding the fort being placed down
@@ -111,24 +195,40 @@ pub fn spawn_player_entities(
         let (high_x, high_y) = (spawn_point.0 + 1, spawn_point.1 + 1);
         for x in low_x..=high_x {
             for y in low_y..=high_y {
-                let idx = xy_idx(x, y);
+                if !map.in_bounds(Point::new(x, y)) {
+                    continue;
+                }
+                let idx = map.xy_idx(x, y);
                 map.claimed_tiles[idx] = player_num;
             }
         }
     }
 
 	// Building the fort
-	let fort_entity = fort(ecs, spawn_point, format!("Fort{}", 1), player_num);
-	ecs.insert(fort_entity);
+	fort(ecs, spawn_point, format!("Fort{}", 1), player_num);
 	fort_counter += 1;
 
-    for _ in 0..3 {
+    // Units only get placed on the fort's landmass so none of them start off stranded
+    let landmass_ids = {
+        let map = ecs.fetch::<Map>();
+        index_landmasses(&find_landmasses(&map), map.tiles.len())
+    };
+
+    for _ in 0..STARTING_UNITS {
         unit_counter += 1;
-        let x_range = (spawn_point.0 - 3, spawn_point.0 + 3);
-        let y_range = (spawn_point.1 - 3, spawn_point.1 + 3);
-        let pos = generate_coordinates(ecs, x_range, y_range);
-        let unit_entity = unit(ecs, pos, format!("Unit{}", unit_counter), range, player_num);
-        ecs.insert(unit_entity);
+        let pos = generate_coordinates(ecs, spawn_point, &landmass_ids).ok_or(
+            PlacementError::NoRoomForUnits {
+                x: spawn_point.0,
+                y: spawn_point.1,
+            },
+        )?;
+        unit(ecs, pos, format!("Unit{}", unit_counter), range, player_num);
+
+        // Marking the tile as blocked so the next unit can't be placed on top of this one
+        // before the indexing system gets a chance to run
+        let mut map = ecs.fetch_mut::<Map>();
+        let idx = map.xy_idx(pos.0, pos.1);
+        map.blocked[idx] = true;
     }
 
     {
@@ -143,30 +243,259 @@ pub fn spawn_player_entities(
             }
         }
     }
+
+    Ok(player_entity)
+}
+
+/// Picks a random tile around the fort for one of its starting units, using the world's seeded
+/// random number generator. Returns None when there's no room left.
+pub fn generate_coordinates(
+    ecs: &World,
+    fort: (i32, i32),
+    landmass_ids: &[Option<usize>],
+) -> Option<(i32, i32)> {
+    let map = ecs.fetch::<Map>();
+    let mut rng = ecs.fetch_mut::<RandomNumberGenerator>();
+
+    let open_tiles = starting_unit_tiles(&map, landmass_ids, fort);
+    rng.random_slice_entry(&open_tiles).copied()
+}
+
+/// Gets the open tiles within the starting spread of a fort that a starting unit can be placed
+/// on, leaving out the fort's own tile and anything that isn't on the fort's landmass
+fn starting_unit_tiles(
+    map: &Map,
+    landmass_ids: &[Option<usize>],
+    fort: (i32, i32),
+) -> Vec<(i32, i32)> {
+    let landmass = landmass_ids[map.xy_idx(fort.0, fort.1)];
+    if landmass.is_none() {
+        return Vec::new();
+    }
+
+    // Checking the tile's landmass since the functions for populating the blocked map
+    // haven't been ran yet as this occurs before the main game loop runs, units placed
+    // while spawning mark their tiles as blocked themselves
+    let mut open_tiles = Vec::new();
+    for y in fort.1 - STARTING_UNIT_SPREAD..=fort.1 + STARTING_UNIT_SPREAD {
+        for x in fort.0 - STARTING_UNIT_SPREAD..=fort.0 + STARTING_UNIT_SPREAD {
+            if (x, y) == fort || !map.in_bounds(Point::new(x, y)) {
+                continue;
+            }
+            let idx = map.xy_idx(x, y);
+            if landmass_ids[idx] == landmass && !map.blocked[idx] {
+                open_tiles.push((x, y));
+            }
+        }
+    }
+
+    open_tiles
 }
 
-/// Takes in a range of values and generates coordinates that aren't blocked
-pub fn generate_coordinates(ecs: &World, x_range: (i32, i32), y_range: (i32, i32)) -> (i32, i32) {
+/// A tile a player could start on, along with how many Grasslands and Forest tiles are
+/// around it
+#[derive(Copy, Clone)]
+struct StartCandidate {
+    x: i32,
+    y: i32,
+    score: i32,
+}
+
+/// Picks a start tile for each player. The starts are all on the same landmass, at least
+/// the minimum distance apart, and have about the same amount of Grasslands and Forest
+/// around them. The largest landmasses get tried first, and each one gets a number of tries
+/// starting from a different good tile before giving up on it.
+pub fn place_starts(ecs: &World, players: usize) -> Result<Vec<(i32, i32)>, PlacementError> {
+    if players == 0 {
+        return Err(PlacementError::ZeroPlayers);
+    }
+
     let map = ecs.fetch::<Map>();
-    let mut position: Option<(i32, i32)> = None;
-
-    while position.is_none() {
-        let mut rng = thread_rng();
-        let x: i32 = rng.gen_range(x_range.0..x_range.1);
-        let y: i32 = rng.gen_range(y_range.0..y_range.1);
-
-        let idx = xy_idx(x, y);
-
-        // Checking specific tile type since the functions for populating the blocked
-        // map haven't been ran yet as this occurs before the main game loop runs
-        if (map.tiles[idx] != TileType::Ice)
-            && (map.tiles[idx] != TileType::Mountain)
-            && (map.tiles[idx] != TileType::Water)
-        {
-            position = Some((x, y));
-            break;
+    let mut rng = ecs.fetch_mut::<RandomNumberGenerator>();
+
+    let scores = good_tiles_around(&map);
+    let mut landmasses = find_landmasses(&map);
+    let landmass_ids = index_landmasses(&landmasses, map.tiles.len());
+    landmasses.sort_by_key(|landmass| std::cmp::Reverse(landmass.len()));
+
+    for landmass in landmasses.iter() {
+        let mut candidates = landmass
+            .iter()
+            .filter(|idx| has_room_for_units(&map, &landmass_ids, **idx))
+            .map(|idx| StartCandidate {
+                x: *idx as i32 % map.width,
+                y: *idx as i32 / map.width,
+                score: scores[*idx],
+            })
+            .collect::<Vec<_>>();
+        if candidates.len() < players {
+            continue;
+        }
+
+        // The first start is always picked out of the better half of the landmass, so the
+        // other starts have to measure up to a good start instead of a poor one
+        candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.score));
+        let better_half = (candidates.len() / 2).max(1);
+
+        for _ in 0..PLACEMENT_ATTEMPTS {
+            let first = candidates[rng.range(0, better_half as i32) as usize];
+            let tolerance = ((first.score as f32 * START_SCORE_TOLERANCE) as i32)
+                .max(MIN_START_SCORE_TOLERANCE);
+
+            // Going through the rest starting from a random spot, so the other starts
+            // don't always end up on the same side of the landmass
+            let offset = rng.range(0, candidates.len() as i32) as usize;
+            let mut starts = vec![first];
+            for i in 0..candidates.len() {
+                if starts.len() == players {
+                    break;
+                }
+                let candidate = candidates[(offset + i) % candidates.len()];
+                let far_enough = starts.iter().all(|start| {
+                    DistanceAlg::Pythagoras.distance2d(
+                        Point::new(start.x, start.y),
+                        Point::new(candidate.x, candidate.y),
+                    ) >= MIN_START_DISTANCE
+                });
+                if far_enough && (candidate.score - first.score).abs() <= tolerance {
+                    starts.push(candidate);
+                }
+            }
+
+            if starts.len() == players {
+                return Ok(starts.iter().map(|start| (start.x, start.y)).collect());
+            }
+        }
+    }
+
+    Err(PlacementError::NoFairStarts { players })
+}
+
+/// Counts the Grasslands and Forest tiles within the start radius of every tile, using a
+/// table of running totals so each count only takes a few lookups
+fn good_tiles_around(map: &Map) -> Vec<i32> {
+    let width = map.width as usize;
+    let height = map.height as usize;
+
+    // totals[(y + 1) * (width + 1) + (x + 1)] holds how many good tiles are above and to
+    // the left of (x, y), including the tile itself
+    let mut totals = vec![0; (width + 1) * (height + 1)];
+    for y in 0..height {
+        for x in 0..width {
+            let good = matches!(
+                map.tiles[y * width + x],
+                TileType::Grasslands | TileType::Forest
+            ) as i32;
+            totals[(y + 1) * (width + 1) + (x + 1)] = good
+                + totals[y * (width + 1) + (x + 1)]
+                + totals[(y + 1) * (width + 1) + x]
+                - totals[y * (width + 1) + x];
+        }
+    }
+
+    let mut scores = vec![0; width * height];
+    for y in 0..map.height {
+        for x in 0..map.width {
+            let low_x = (x - START_RADIUS).max(0) as usize;
+            let low_y = (y - START_RADIUS).max(0) as usize;
+            let high_x = (x + START_RADIUS).min(map.width - 1) as usize + 1;
+            let high_y = (y + START_RADIUS).min(map.height - 1) as usize + 1;
+            scores[map.xy_idx(x, y)] = totals[high_y * (width + 1) + high_x]
+                - totals[low_y * (width + 1) + high_x]
+                - totals[high_y * (width + 1) + low_x]
+                + totals[low_y * (width + 1) + low_x];
         }
     }
 
-    position.unwrap()
+    scores
+}
+
+/// Splits the tiles units can walk on into landmasses, where every tile in a landmass can be
+/// walked to from every other tile in it
+fn find_landmasses(map: &Map) -> Vec<Vec<usize>> {
+    let mut visited = vec![false; map.tiles.len()];
+    let mut landmasses = Vec::new();
+
+    for start in 0..map.tiles.len() {
+        if visited[start] || map.tiles[start].movement_cost().is_none() {
+            continue;
+        }
+
+        let mut landmass = Vec::new();
+        let mut stack = vec![start];
+        visited[start] = true;
+        while let Some(idx) = stack.pop() {
+            landmass.push(idx);
+            let x = idx as i32 % map.width;
+            let y = idx as i32 / map.width;
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                if !map.in_bounds(Point::new(x + dx, y + dy)) {
+                    continue;
+                }
+                let next = map.xy_idx(x + dx, y + dy);
+                if !visited[next] && map.tiles[next].movement_cost().is_some() {
+                    visited[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        landmasses.push(landmass);
+    }
+
+    landmasses
+}
+
+/// Gets which of the landmasses every tile is on, tiles units can't walk on aren't on any
+fn index_landmasses(landmasses: &[Vec<usize>], tile_count: usize) -> Vec<Option<usize>> {
+    let mut ids = vec![None; tile_count];
+    for (id, landmass) in landmasses.iter().enumerate() {
+        for idx in landmass {
+            ids[*idx] = Some(id);
+        }
+    }
+    ids
+}
+
+/// Checks that a fort on the tile would have enough open tiles around it for the player's
+/// starting units
+fn has_room_for_units(map: &Map, landmass_ids: &[Option<usize>], idx: usize) -> bool {
+    if map.blocked[idx] {
+        return false;
+    }
+
+    let fort = (idx as i32 % map.width, idx as i32 / map.width);
+    starting_unit_tiles(map, landmass_ids, fort).len() >= STARTING_UNITS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    #[test]
+    fn starting_units_are_on_their_forts_landmass() {
+        for seed in 1..6 {
+            let gs = State::test_game(seed);
+            let map = gs.ecs.fetch::<Map>();
+            let landmass_ids = index_landmasses(&find_landmasses(&map), map.tiles.len());
+            let positions = gs.ecs.read_storage::<Position>();
+            let forts = gs.ecs.read_storage::<Fort>();
+            let units = gs.ecs.read_storage::<Unit>();
+
+            for (fort, fort_pos) in (&forts, &positions).join() {
+                let landmass = landmass_ids[map.xy_idx(fort_pos.x, fort_pos.y)];
+                assert!(landmass.is_some());
+
+                let owned = (&units, &positions)
+                    .join()
+                    .filter(|(unit, _)| unit.owner == fort.owner)
+                    .collect::<Vec<_>>();
+                assert_eq!(owned.len(), STARTING_UNITS);
+                for (_, pos) in owned {
+                    assert!((pos.x, pos.y) != (fort_pos.x, fort_pos.y));
+                    assert_eq!(landmass_ids[map.xy_idx(pos.x, pos.y)], landmass);
+                }
+            }
+        }
+    }
 }
@@ -1,15 +1,99 @@
 //! Made by: Thomas Gardner, 2022
 
-use crate::{State, Unit};
+use crate::{
+    advance_production, economy, error_handling, follow_path, Fort, GameLog, GoTo, LogEvent,
+    MessageType, Player, PlayerOrder, Position, State, Unit, FORT_DEFENSE_REGEN, FORT_MAX_DEFENSE,
+};
+use bracket_lib::prelude::Point;
 use specs::prelude::*;
+use std::cmp::min;
 
 pub fn next_turn(gs: &mut State) {
-    let mut units = gs.ecs.write_storage::<Unit>();
-    let entities = gs.ecs.entities();
+    let mut ordered_units = Vec::new();
+    {
+        let mut units = gs.ecs.write_storage::<Unit>();
+        let mut forts = gs.ecs.write_storage::<Fort>();
+        let orders = gs.ecs.read_storage::<GoTo>();
+        let entities = gs.ecs.entities();
 
-    for (_entity, unit) in (&entities, &mut units).join() {
-        unit.stamina = 8;
+        for (_entity, unit) in (&entities, &mut units).join() {
+            unit.stamina = 8;
+        }
+
+        // Forts slowly repair any damage they took from sieges
+        for (_entity, fort) in (&entities, &mut forts).join() {
+            fort.defense = min(fort.defense + FORT_DEFENSE_REGEN, FORT_MAX_DEFENSE);
+        }
+
+        for (entity, _order) in (&entities, &orders).join() {
+            ordered_units.push(entity);
+        }
     }
 
+    // Units with go to orders spend their fresh stamina walking along their paths
+    for entity in ordered_units {
+        let res = follow_path(&mut gs.ecs, entity);
+        error_handling::handle_path_result(&mut gs.ecs, entity, res);
+    }
+
+    // Players collect from the tiles their forts work before the forts build anything
+    economy::collect_yields(&mut gs.ecs);
+
+    // Forts build after units have moved so units walking away free up room around them
+    advance_production(&mut gs.ecs);
+
     gs.turns += 1;
+    gs.ecs.fetch_mut::<GameLog>().turn = gs.turns;
+}
+
+/// Passes control over to the next player in the turn order, once every player has
+/// had their turn the game moves on to the next turn
+pub fn end_player_turn(gs: &mut State) {
+    let mut next_player: Option<(Entity, Position)> = None;
+    let mut first_player: Option<(Entity, Position)> = None;
+    {
+        let entities = gs.ecs.entities();
+        let players = gs.ecs.read_storage::<Player>();
+        let positions = gs.ecs.read_storage::<Position>();
+        let player_entity = gs.ecs.fetch::<Entity>();
+
+        let current = players
+            .get(*player_entity)
+            .map(|player| player.order.index())
+            .unwrap_or(0);
+
+        let mut turn_order = (&entities, &players, &positions)
+            .join()
+            .map(|(entity, player, pos)| (player.order.index(), entity, *pos))
+            .collect::<Vec<_>>();
+        turn_order.sort_by_key(|&(index, _, _)| index);
+
+        if let Some(&(_, entity, pos)) = turn_order.first() {
+            first_player = Some((entity, pos));
+        }
+        if let Some(&(_, entity, pos)) = turn_order.iter().find(|(index, _, _)| *index > current) {
+            next_player = Some((entity, pos));
+        }
+    }
+
+    // Nobody is left in the turn order, so everyone has gone and a new turn starts
+    if next_player.is_none() {
+        next_turn(gs);
+        next_player = first_player;
+    }
+
+    if let Some((entity, pos)) = next_player {
+        gs.ecs.insert(entity);
+        gs.ecs.insert(Point::new(pos.x, pos.y));
+        let order = gs
+            .ecs
+            .read_storage::<Player>()
+            .get(entity)
+            .map(|player| player.order)
+            .unwrap_or(PlayerOrder::NoPlayer);
+
+        let message = format!("It is now {}'s turn", order.name());
+        let mut log = gs.ecs.fetch_mut::<GameLog>();
+        log.push(LogEvent::new(MessageType::Other, order, message));
+    }
 }
@@ -5,52 +5,250 @@
 //! rather than for moving player units.
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_0.html
 
+use crate::combat::{self, Target};
+use crate::command::{Command, Outcome};
+use crate::gui::select_player;
 use crate::spawner::*;
 use crate::{
-    error_handling, teleport_player, xy_idx, FailedMoveReason, Fort, GameLog, Map, MessageType,
-    Moving, Player, PlayerOrder, Position, RunState, State, Unit, Viewshed, World,
+    error_handling, teleport_player, FailedMoveReason, FogOfWar, Fort, GameLog, GoTo, LogEvent,
+    Map, MessageType, Moving, Player, PlayerOrder, Position, RunState, State, Unit, Viewshed,
+    World,
 };
 use bracket_lib::prelude::*;
 use specs::prelude::*;
 use std::cmp::{max, min};
 
-/// Attempts to move a unit, checking if the tile the unit will end up on is blocked or not
-fn try_move_unit(
-    delta_x: i32,
-    delta_y: i32,
+/// Steps a unit onto the given tile, checking if the tile is blocked and if the unit has the
+/// stamina left to pay for moving onto that type of terrain
+fn step_unit(
     ecs: &mut World,
+    entity: Entity,
+    x: i32,
+    y: i32,
 ) -> Result<(i32, i32), FailedMoveReason> {
     let mut positions = ecs.write_storage::<Position>();
     let mut units = ecs.write_storage::<Unit>();
     let mut viewsheds = ecs.write_storage::<Viewshed>();
-    let moving_marker = ecs.read_storage::<Moving>();
-    let map = ecs.fetch::<Map>();
+    let mut map = ecs.fetch_mut::<Map>();
 
-    if let Some((unit, pos, viewshed, _moving)) =
-        (&mut units, &mut positions, &mut viewsheds, &moving_marker)
-            .join()
-            .next()
-    {
-        let destination_idx = xy_idx(pos.x + delta_x, pos.y + delta_y);
-        if !map.blocked[destination_idx] && unit.stamina > 0 {
-            let mut ppos = ecs.write_resource::<Point>();
-            pos.x = min(map.width, max(0, pos.x + delta_x));
-            pos.y = min(map.height, max(0, pos.y + delta_y));
-            ppos.x = pos.x;
-            ppos.y = pos.y;
+    if let (Some(unit), Some(pos), Some(viewshed)) = (
+        units.get_mut(entity),
+        positions.get_mut(entity),
+        viewsheds.get_mut(entity),
+    ) {
+        if !map.in_bounds(Point::new(x, y)) {
+            return Err(FailedMoveReason::TileBlocked);
+        }
+
+        let destination_idx = map.xy_idx(x, y);
+        let cost = match map.movement_cost(destination_idx) {
+            Some(cost) if !map.blocked[destination_idx] => cost,
+            _ => return Err(FailedMoveReason::TileBlocked),
+        };
+
+        if unit.stamina >= cost {
+            // Keeping the blocked tiles up to date so other units moving before the
+            // indexing system runs again can't end up on the same tile
+            let current_idx = map.xy_idx(pos.x, pos.y);
+            map.blocked[current_idx] = false;
+            map.blocked[destination_idx] = true;
+
+            pos.x = min(map.width, max(0, x));
+            pos.y = min(map.height, max(0, y));
             viewshed.dirty = true;
-			unit.stamina -= 1;
+			unit.stamina -= cost;
 
             return Ok((pos.x, pos.y));
         } else if unit.stamina == 0 {
 			return Err(FailedMoveReason::UnitOutOfMoves);
 		}else {
-            return Err(FailedMoveReason::TileBlocked);
+            return Err(FailedMoveReason::NotEnoughStamina);
         }
     }
     Err(FailedMoveReason::UnableToGrabEntity)
 }
 
+/// Attempts to move the unit that's currently being moved, moving it by hand cancels any
+/// go to order the unit has
+fn try_move_unit(
+    delta_x: i32,
+    delta_y: i32,
+    ecs: &mut World,
+) -> Result<(i32, i32), FailedMoveReason> {
+    let mut mover: Option<(Entity, Position)> = None;
+    {
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let moving_marker = ecs.read_storage::<Moving>();
+
+        if let Some((entity, pos, _moving)) = (&entities, &positions, &moving_marker).join().next() {
+            mover = Some((entity, *pos));
+        }
+    }
+
+    let (entity, pos) = mover.ok_or(FailedMoveReason::UnableToGrabEntity)?;
+    let res = step_unit(ecs, entity, pos.x + delta_x, pos.y + delta_y);
+    if let Ok((x, y)) = res {
+        let mut ppos = ecs.write_resource::<Point>();
+        ppos.x = x;
+        ppos.y = y;
+        ecs.write_storage::<GoTo>().remove(entity);
+    }
+    res
+}
+
+/// How far a unit got along the path it was ordered to follow
+pub enum PathProgress {
+    Arrived(i32, i32),
+    Underway,
+    Blocked,
+}
+
+/// Gets the unit the player is currently moving, if there is one
+fn moving_unit(ecs: &World) -> Option<Entity> {
+    let entities = ecs.entities();
+    let moving = ecs.read_storage::<Moving>();
+
+    (&entities, &moving).join().next().map(|(entity, _moving)| entity)
+}
+
+/// Marks the event as being about the moving unit, if there was one
+fn about_unit(event: LogEvent, unit: Option<Entity>) -> LogEvent {
+    match unit {
+        Some(unit) => event.about(unit),
+        None => event,
+    }
+}
+
+/// The map as the player giving an order knows it, paths are only plotted through tiles the
+/// player has revealed so the route a unit takes doesn't give away unexplored terrain
+struct RevealedMap<'a> {
+    map: &'a Map,
+    fog: &'a FogOfWar,
+}
+
+impl BaseMap for RevealedMap<'_> {
+    fn get_available_exits(&self, idx: usize) -> SmallVec<[(usize, f32); 10]> {
+        self.map
+            .get_available_exits(idx)
+            .into_iter()
+            .filter(|(exit, _cost)| self.fog.revealed_tiles[*exit])
+            .collect()
+    }
+
+    fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
+        self.map.get_pathing_distance(idx1, idx2)
+    }
+}
+
+/// Plots a path from the moving unit to the target and orders the unit to follow it, returns
+/// the unit given the order if a path could be found
+fn go_to(ecs: &mut World, target: Position) -> Option<Entity> {
+    let (entity, path) = {
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let moving_marker = ecs.read_storage::<Moving>();
+        let fogs = ecs.read_storage::<FogOfWar>();
+        let player_entity = ecs.fetch::<Entity>();
+        let map = ecs.fetch::<Map>();
+
+        let (entity, pos, _moving) = (&entities, &positions, &moving_marker).join().next()?;
+        let start = map.xy_idx(pos.x, pos.y);
+        let end = map.xy_idx(target.x, target.y);
+        if start == end {
+            return None;
+        }
+        let known = RevealedMap {
+            map: &map,
+            fog: fogs.get(*player_entity)?,
+        };
+        (entity, a_star_search(start, end, &known))
+    };
+    if !path.success {
+        return None;
+    }
+
+    // The first step of the path is the tile the unit is already standing on
+    ecs.write_storage::<GoTo>()
+        .insert(
+            entity,
+            GoTo {
+                path: path.steps[1..].to_vec(),
+            },
+        )
+        .expect("Unable to give unit a go to order");
+    Some(entity)
+}
+
+/// Moves a unit along the path it was ordered to follow until it arrives, runs out of stamina,
+/// or finds its way blocked. The order is dropped once the unit arrives or gets blocked.
+pub fn follow_path(ecs: &mut World, entity: Entity) -> PathProgress {
+    loop {
+        let width = ecs.fetch::<Map>().width;
+        let next_step = ecs
+            .read_storage::<GoTo>()
+            .get(entity)
+            .and_then(|order| order.path.first().copied());
+
+        let idx = match next_step {
+            Some(idx) => idx as i32,
+            None => {
+                ecs.write_storage::<GoTo>().remove(entity);
+                return PathProgress::Blocked;
+            }
+        };
+
+        match step_unit(ecs, entity, idx % width, idx / width) {
+            Ok((x, y)) => {
+                let mut orders = ecs.write_storage::<GoTo>();
+                let arrived = match orders.get_mut(entity) {
+                    Some(order) => {
+                        order.path.remove(0);
+                        order.path.is_empty()
+                    }
+                    None => true,
+                };
+                if arrived {
+                    orders.remove(entity);
+                    return PathProgress::Arrived(x, y);
+                }
+            }
+            Err(FailedMoveReason::UnitOutOfMoves) | Err(FailedMoveReason::NotEnoughStamina) => {
+                return PathProgress::Underway
+            }
+            Err(_) => {
+                ecs.write_storage::<GoTo>().remove(entity);
+                return PathProgress::Blocked;
+            }
+        }
+    }
+}
+
+/// Attacks the enemy unit or fort on the tile the moving unit is heading towards if there is
+/// one, otherwise moves the unit onto that tile. Returns whether the attack or move happened.
+fn move_or_attack(delta_x: i32, delta_y: i32, gs: &mut State) -> bool {
+    match combat::find_target(&gs.ecs, delta_x, delta_y) {
+        Some((attacker, Target::Unit(defender))) => {
+            let res = combat::attack(&mut gs.ecs, attacker, defender);
+            let attacked = res.is_ok();
+            error_handling::handle_combat_result(&mut gs.ecs, res);
+            attacked
+        }
+        Some((attacker, Target::Fort(fort))) => {
+            let res = combat::attack_fort(&mut gs.ecs, attacker, fort);
+            let attacked = res.is_ok();
+            error_handling::handle_siege_result(&mut gs.ecs, res);
+            attacked
+        }
+        None => {
+            let res = try_move_unit(delta_x, delta_y, &mut gs.ecs);
+            let moved = res.is_ok();
+            error_handling::handle_move_result(&mut gs.ecs, res, gs.verbose);
+            moved
+        }
+    }
+}
+
 /// Used for removing the moving marker from a unit struct so it won't move the next time a unit gets moved
 /// Returns a position if it was successful to teleport the player to the location of the recently unmarked unit
 fn unmark_moving_unit(ecs: &mut World) -> Option<Position> {
@@ -72,90 +270,143 @@ fn unmark_moving_unit(ecs: &mut World) -> Option<Position> {
     curr_pos
 }
 
-/// Lets the player move a unit around, claim a tile, build a fort, or exit back to cursor mode
-pub fn unit_input(gs: &mut State, ctx: &mut BTerm) -> RunState {
-    let mut order = String::new();
-    {
-        let entities = gs.ecs.entities();
-        let players = gs.ecs.read_storage::<Player>();
-
-        for (player, _entity) in (&players, &entities).join() {
-            match player.order {
-                PlayerOrder::NoPlayer => order = "No Player".to_string(),
-                PlayerOrder::PlayerOne => order = "Player1".to_string(),
-                PlayerOrder::PlayerTwo => order = "Player2".to_string(),
-            }
+/// Checks if the player whose turn it is has revealed the given tile
+fn revealed_to(ecs: &World, x: i32, y: i32) -> bool {
+    let fogs = ecs.read_storage::<FogOfWar>();
+    let player_entity = ecs.fetch::<Entity>();
+    let map = ecs.fetch::<Map>();
+
+    fogs.get(*player_entity)
+        .map(|fog| fog.revealed_tiles[map.xy_idx(x, y)])
+        .unwrap_or(false)
+}
+
+/// Orders the moving unit to walk to the target and hands control back to the cursor, which
+/// gets left where the unit was sent
+fn send_unit(
+    gs: &mut State,
+    target: Position,
+    order: PlayerOrder,
+    unit: Option<Entity>,
+) -> Outcome {
+    let ordered = match go_to(&mut gs.ecs, target) {
+        Some(entity) => {
+            let res = follow_path(&mut gs.ecs, entity);
+            error_handling::handle_path_result(&mut gs.ecs, entity, res);
+            true
         }
+        None => {
+            let event = LogEvent::new(
+                MessageType::Error,
+                order,
+                format!("Unable to find a path to ({}, {})", target.x, target.y),
+            )
+            .at(target);
+            gs.ecs.fetch_mut::<GameLog>().push(about_unit(event, unit));
+            false
+        }
+    };
+
+    // Control goes back to the cursor even if there was somehow no unit to hand it back from
+    let unmarked = unmark_moving_unit(&mut gs.ecs).is_some();
+    if ordered && unmarked {
+        teleport_player(target, &mut gs.ecs);
     }
+    gs.runstate = RunState::MoveCursor;
+    Outcome::from_success(ordered && unmarked)
+}
 
-    match ctx.key {
-        None => return RunState::MoveUnit,
-        Some(key) => match key {
-            VirtualKeyCode::A => {
-                let res = try_move_unit(-1, 0, &mut gs.ecs);
-                error_handling::handle_move_result(&mut gs.ecs, res, gs.verbose);
-            }
-            VirtualKeyCode::D => {
-                let res = try_move_unit(1, 0, &mut gs.ecs);
-                error_handling::handle_move_result(&mut gs.ecs, res, gs.verbose);
+/// Carries out a command given while a unit is selected, letting the player move the unit around,
+/// claim a tile, build a fort, send the unit towards the cursor or a tile, or go back to cursor
+/// mode
+pub fn unit_command(gs: &mut State, command: Command) -> Outcome {
+    let order = select_player(&gs.ecs).unwrap_or(PlayerOrder::NoPlayer);
+    let unit = moving_unit(&gs.ecs);
+
+    match command {
+        // Units only step onto one of the four tiles next to them, so anything further away
+        // is out of reach
+        Command::MoveUnit(delta_x, delta_y) if delta_x.abs() + delta_y.abs() != 1 => {
+            let message = "Units can only move or attack one tile at a time".to_string();
+            let event = LogEvent::new(MessageType::Error, order, message);
+            gs.ecs.fetch_mut::<GameLog>().push(about_unit(event, unit));
+            Outcome::Failed
+        }
+        Command::MoveUnit(delta_x, delta_y) => {
+            Outcome::from_success(move_or_attack(delta_x, delta_y, gs))
+        }
+        Command::ClaimTile => {
+            let claimed = claim_tile(&mut gs.ecs);
+            let event = match claimed {
+                Some((x, y)) => LogEvent::new(
+                    MessageType::Claim,
+                    order,
+                    format!("{} has claimed a tile at ({}, {})", order.name(), x, y),
+                )
+                .at(Position { x, y }),
+                None => {
+                    LogEvent::new(MessageType::Error, order, "Unable to claim tile".to_string())
+                }
+            };
+            gs.ecs.fetch_mut::<GameLog>().push(about_unit(event, unit));
+            Outcome::from_success(claimed.is_some())
+        }
+        Command::BuildFort => {
+            let new_fort_location = build_fort(&mut gs.ecs);
+            let event = match new_fort_location {
+                Some((x, y)) => LogEvent::new(
+                    MessageType::Build,
+                    order,
+                    format!("{} has built a fort at ({}, {})", order.name(), x, y),
+                )
+                .at(Position { x, y }),
+                None => {
+                    LogEvent::new(MessageType::Error, order, "Unable to build fort".to_string())
+                }
+            };
+            gs.ecs.fetch_mut::<GameLog>().push(about_unit(event, unit));
+            Outcome::from_success(new_fort_location.is_some())
+        }
+        Command::GoToCursor => {
+            let cursor_pos = {
+                let positions = gs.ecs.read_storage::<Position>();
+                let player_entity = gs.ecs.fetch::<Entity>();
+                positions.get(*player_entity).copied()
+            };
+            match cursor_pos {
+                Some(pos) => send_unit(gs, pos, order, unit),
+                None => Outcome::Failed,
             }
-            VirtualKeyCode::W => {
-                let res = try_move_unit(0, -1, &mut gs.ecs);
-                error_handling::handle_move_result(&mut gs.ecs, res, gs.verbose);
+        }
+        Command::GoTo(x, y) => {
+            if !gs.ecs.fetch::<Map>().in_bounds(Point::new(x, y)) {
+                return Outcome::Ignored;
             }
-            VirtualKeyCode::S => {
-                let res = try_move_unit(0, 1, &mut gs.ecs);
-                error_handling::handle_move_result(&mut gs.ecs, res, gs.verbose);
+            // Units can only be sent to places the player has seen, the same as the cursor
+            if !revealed_to(&gs.ecs, x, y) {
+                let event = LogEvent::new(
+                    MessageType::Error,
+                    order,
+                    "Unable to send a unit somewhere unexplored".to_string(),
+                );
+                gs.ecs.fetch_mut::<GameLog>().push(about_unit(event, unit));
+                return Outcome::Failed;
             }
-            VirtualKeyCode::G => {
-                let claimed = claim_tile(&mut gs.ecs);
-                {
-                    let mut log = gs.ecs.fetch_mut::<GameLog>();
-
-                    match claimed {
-                        Some((x, y)) => {
-                            log.entries
-                                .push(format!("{} has claimed a tile at ({}, {})", order, x, y));
-                            log.message_type.push(MessageType::Claim);
-                        }
-                        None => {
-                            log.entries.push("Unable to claim tile".to_string());
-                            log.message_type.push(MessageType::Error);
-                        }
-                    }
-                }
+            send_unit(gs, Position { x, y }, order, unit)
+        }
+        Command::Deselect => match unmark_moving_unit(&mut gs.ecs) {
+            None => {
+                gs.runstate = RunState::MoveCursor;
+                Outcome::Failed
             }
-            VirtualKeyCode::B => {
-                let new_fort_location = build_fort(&mut gs.ecs);
-                {
-                    let mut log = gs.ecs.fetch_mut::<GameLog>();
-
-                    match new_fort_location {
-                        Some((x, y)) => {
-                            log.entries
-                                .push(format!("{} has built a fort at ({}, {})", order, x, y));
-                            log.message_type.push(MessageType::Build);
-                        }
-                        None => {
-                            log.entries.push("Unable to build fort".to_string());
-                            log.message_type.push(MessageType::Error);
-                        }
-                    }
-                }
+            Some(pos) => {
+                teleport_player(pos, &mut gs.ecs);
+                gs.runstate = RunState::MoveCursor;
+                Outcome::Applied
             }
-            VirtualKeyCode::I => match unmark_moving_unit(&mut gs.ecs) {
-                None => {
-                    panic!("Failed to unmark moving unit")
-                }
-                Some(pos) => {
-                    teleport_player(pos, &mut gs.ecs);
-                    return RunState::MoveCursor;
-                }
-            },
-            _ => {}
         },
+        _ => Outcome::Ignored,
     }
-    RunState::MoveUnit
 }
 
 /// Grabs the currently moving unit and claims the tile it's currently on if it isn't claimed already
@@ -163,20 +414,16 @@ fn claim_tile(ecs: &mut World) -> Option<(i32, i32)> {
     let mut units = ecs.write_storage::<Unit>();
     let positions = ecs.read_storage::<Position>();
     let moving = ecs.read_storage::<Moving>();
-    let players = ecs.read_storage::<Player>();
-    let entities = ecs.entities();
     let mut map = ecs.fetch_mut::<Map>();
     let mut claim_pos: Option<(i32, i32)> = None;
 
 		for (unit, pos, _move) in (&mut units, &positions, &moving).join() {
 			if unit.stamina > 0 {
-				for (_player_entity, player) in (&entities, &players).join() {
-					let idx = xy_idx(pos.x, pos.y);
-					if map.claimed_tiles[idx] == PlayerOrder::NoPlayer {
-						map.claimed_tiles[idx] = player.order;
-						claim_pos = Some((pos.x, pos.y));
-					}
-				}	
+				let idx = map.xy_idx(pos.x, pos.y);
+				if map.claimed_tiles[idx] == PlayerOrder::NoPlayer {
+					map.claimed_tiles[idx] = unit.owner;
+					claim_pos = Some((pos.x, pos.y));
+				}
 				unit.stamina -= 1;			
 			}
 		}
@@ -193,11 +440,10 @@ fn claim_tile(ecs: &mut World) -> Option<(i32, i32)> {
 */
 /// Gets the curret location of a unit and if it's claimed by the current player, builds a fort there
 fn build_fort(ecs: &mut World) -> Option<(i32, i32)> {
-    let mut player_order: Option<PlayerOrder> = None;
+    let player_order: Option<PlayerOrder> = select_player(ecs);
     let mut new_fort_pos: Option<(i32, i32)> = None;
 
     {
-        let players = ecs.read_storage::<Player>();
         let entities = ecs.entities();
         let positions = ecs.read_storage::<Position>();
         let mut units = ecs.write_storage::<Unit>();
@@ -205,12 +451,9 @@ fn build_fort(ecs: &mut World) -> Option<(i32, i32)> {
         let moving_units = ecs.read_storage::<Moving>();
         let mut map = ecs.fetch_mut::<Map>();
 
-        for (player, _entity) in (&players, &entities).join() {
-            player_order = Some(player.order);
-        }
         if let Some(ref owner) = player_order {
             for (unit, pos, _moving) in (&mut units, &positions, &moving_units).join() {
-                let idx = xy_idx(pos.x, pos.y);
+                let idx = map.xy_idx(pos.x, pos.y);
                 let mut fort_at_pos = false;
 				
 				if unit.stamina > 4 {
@@ -230,7 +473,10 @@ fn build_fort(ecs: &mut World) -> Option<(i32, i32)> {
 						// Claiming the tiles surrounding this tile if a fort can be built here
 						for x in pos.x - 1..=pos.x + 1 {
 							for y in pos.y - 1..=pos.y + 1 {
-								let idx = xy_idx(x, y);
+								if !map.in_bounds(Point::new(x, y)) {
+									continue;
+								}
+								let idx = map.xy_idx(x, y);
 								map.claimed_tiles[idx] = *owner;
 							}
 						}
@@ -248,18 +494,157 @@ fn build_fort(ecs: &mut World) -> Option<(i32, i32)> {
             let mut fort_counter = 0;
             {
                 let mut players = ecs.write_storage::<Player>();
-                let entities = ecs.entities();
+                let player_entity = ecs.fetch::<Entity>();
 
-                for (player, _entity) in (&mut players, &entities).join() {
+                if let Some(player) = players.get_mut(*player_entity) {
                     player.fort_count += 1;
                     fort_counter = player.fort_count;
                 }
             }
             let name = format!("Fort{}", fort_counter);
-            let fort = fort(ecs, pos, name, player);
-            ecs.insert(fort);
+            fort(ecs, pos, name, player);
         }
     }
 
     new_fort_pos
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::apply;
+    use crate::{LogFilter, TileType};
+
+    /// Selects the first unit in the player's list and picks a walkable tile two steps away
+    /// from it with a walkable tile in between
+    fn select_unit_with_target(gs: &mut State) -> Position {
+        assert_eq!(apply(gs, Command::ShowUnits), Outcome::Applied);
+        assert_eq!(apply(gs, Command::Select(1)), Outcome::Applied);
+        assert_eq!(gs.runstate, RunState::MoveUnit);
+
+        let unit = moving_unit(&gs.ecs).expect("No unit was selected");
+        let pos = *gs.ecs.read_storage::<Position>().get(unit).unwrap();
+        let map = gs.ecs.fetch::<Map>();
+        let walkable = |x: i32, y: i32| {
+            map.in_bounds(Point::new(x, y))
+                && !map.blocked[map.xy_idx(x, y)]
+                && map.movement_cost(map.xy_idx(x, y)).is_some()
+        };
+        [(1, 0), (-1, 0), (0, 1), (0, -1)]
+            .iter()
+            .find(|(dx, dy)| {
+                walkable(pos.x + dx, pos.y + dy) && walkable(pos.x + dx * 2, pos.y + dy * 2)
+            })
+            .map(|(dx, dy)| Position {
+                x: pos.x + dx * 2,
+                y: pos.y + dy * 2,
+            })
+            .expect("No walkable tile near the unit")
+    }
+
+    #[test]
+    fn go_to_follows_a_path_through_revealed_tiles() {
+        let mut gs = State::test_game(3);
+        let target = select_unit_with_target(&mut gs);
+        assert_eq!(
+            apply(&mut gs, Command::GoTo(target.x, target.y)),
+            Outcome::Applied
+        );
+        assert_eq!(gs.runstate, RunState::MoveCursor);
+    }
+
+    #[test]
+    fn go_to_does_not_plan_through_unrevealed_tiles() {
+        let mut gs = State::test_game(3);
+        let target = select_unit_with_target(&mut gs);
+        {
+            let unit = moving_unit(&gs.ecs).unwrap();
+            let pos = *gs.ecs.read_storage::<Position>().get(unit).unwrap();
+            let map = gs.ecs.fetch::<Map>();
+            let player_entity = gs.ecs.fetch::<Entity>();
+            let mut fogs = gs.ecs.write_storage::<FogOfWar>();
+            let fog = fogs.get_mut(*player_entity).unwrap();
+            for (idx, revealed) in fog.revealed_tiles.iter_mut().enumerate() {
+                *revealed =
+                    idx == map.xy_idx(pos.x, pos.y) || idx == map.xy_idx(target.x, target.y);
+            }
+        }
+        assert_eq!(
+            apply(&mut gs, Command::GoTo(target.x, target.y)),
+            Outcome::Failed
+        );
+
+        // The failure says where the unit was sent, so it's kept from the other players
+        let order = select_player(&gs.ecs).unwrap();
+        let other = PlayerOrder::TURN_ORDER
+            .iter()
+            .copied()
+            .find(|player| *player != order)
+            .unwrap();
+        let log = gs.ecs.fetch::<GameLog>();
+        let newest = log.events.len() - 1;
+        let event = &log.events[newest];
+        assert_eq!(event.kind, MessageType::Error);
+        assert!(event.position_for(order).is_some());
+        assert!(!log.filtered(&LogFilter::default(), other).contains(&newest));
+    }
+
+    #[test]
+    fn deselect_without_a_moving_unit_fails_instead_of_panicking() {
+        let mut gs = State::test_game(3);
+        gs.runstate = RunState::MoveUnit;
+        assert_eq!(apply(&mut gs, Command::Deselect), Outcome::Failed);
+        assert_eq!(gs.runstate, RunState::MoveCursor);
+        gs.runstate = RunState::MoveUnit;
+        assert_eq!(apply(&mut gs, Command::GoToCursor), Outcome::Failed);
+    }
+
+    #[test]
+    fn steps_onto_blocked_or_impassable_tiles_fail() {
+        let mut gs = State::test_arena(5, 5);
+        let unit = gs.test_unit(2, 2, PlayerOrder::PlayerOne);
+        gs.test_unit(3, 2, PlayerOrder::PlayerTwo);
+        {
+            let mut map = gs.ecs.fetch_mut::<Map>();
+            let water = map.xy_idx(1, 2);
+            let mountain = map.xy_idx(2, 1);
+            map.tiles[water] = TileType::Water;
+            map.tiles[mountain] = TileType::Mountain;
+        }
+        gs.run_systems();
+
+        for (x, y) in [(1, 2), (2, 1), (3, 2)].iter() {
+            let step = step_unit(&mut gs.ecs, unit, *x, *y);
+            assert!(matches!(step, Err(FailedMoveReason::TileBlocked)));
+        }
+        let pos = *gs.ecs.read_storage::<Position>().get(unit).unwrap();
+        assert_eq!((pos.x, pos.y), (2, 2));
+        assert_eq!(gs.ecs.read_storage::<Unit>().get(unit).unwrap().stamina, 6);
+    }
+
+    #[test]
+    fn steps_need_enough_stamina_for_the_terrain() {
+        let mut gs = State::test_arena(5, 5);
+        let unit = gs.test_unit(2, 2, PlayerOrder::PlayerOne);
+        {
+            let mut map = gs.ecs.fetch_mut::<Map>();
+            let hills = map.xy_idx(3, 2);
+            map.tiles[hills] = TileType::Hills;
+        }
+        gs.ecs
+            .write_storage::<Unit>()
+            .get_mut(unit)
+            .unwrap()
+            .stamina = 1;
+
+        let step = step_unit(&mut gs.ecs, unit, 3, 2);
+        assert!(matches!(step, Err(FailedMoveReason::NotEnoughStamina)));
+
+        // One stamina is still enough for the grasslands on the other side
+        assert!(matches!(step_unit(&mut gs.ecs, unit, 1, 2), Ok((1, 2))));
+        assert_eq!(gs.ecs.read_storage::<Unit>().get(unit).unwrap().stamina, 0);
+
+        let step = step_unit(&mut gs.ecs, unit, 2, 2);
+        assert!(matches!(step, Err(FailedMoveReason::UnitOutOfMoves)));
+    }
+}
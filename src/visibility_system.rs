@@ -4,27 +4,32 @@
 //! this project. This section of code comes from section 2.4: Field of View
 //! Link: https://bfnightly.bracketproductions.com/rustbook/chapter_5.html
 
-use crate::{xy_idx, Map, Position, Viewshed};
+use crate::{
+    FogOfWar, Fort, FortSnapshot, Map, Player, Position, Renderable, Unit, Viewshed,
+};
 use bracket_lib::prelude::{field_of_view, Point};
 use specs::prelude::*;
 
 pub struct VisibilitySystem {}
 
-/// A system for handling how the units see the tiles around them
+/// A system for handling how the units see the tiles around them, with each player only
+/// learning about the tiles their own units can see
 impl<'a> System<'a> for VisibilitySystem {
     type SystemData = (
-        WriteExpect<'a, Map>,
+        ReadExpect<'a, Map>,
         Entities<'a>,
         WriteStorage<'a, Viewshed>,
-        WriteStorage<'a, Position>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Unit>,
+        ReadStorage<'a, Player>,
+        WriteStorage<'a, FogOfWar>,
+        ReadStorage<'a, Fort>,
+        ReadStorage<'a, Renderable>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut map, entities, mut viewshed, pos) = data;
-
-        for t in map.visible_tiles.iter_mut() {
-            *t = false
-        }
+        let (map, entities, mut viewshed, pos, units, players, mut fog, forts, renderables) =
+            data;
 
         for (_ent, viewshed, pos) in (&entities, &mut viewshed, &pos).join() {
             if viewshed.dirty {
@@ -36,11 +41,38 @@ impl<'a> System<'a> for VisibilitySystem {
                     .visible_tiles
                     .retain(|p| p.x >= 0 && p.x < map.width && p.y >= 0 && p.y < map.height);
             }
-            // If this is a unit, reveal what they can see
-            for vis in viewshed.visible_tiles.iter() {
-                let idx = xy_idx(vis.x, vis.y);
-                map.revealed_tiles[idx] = true;
-                map.visible_tiles[idx] = true;
+        }
+
+        for (player, fog) in (&players, &mut fog).join() {
+            for t in fog.visible_tiles.iter_mut() {
+                *t = false
+            }
+
+            // If this is one of the player's units, reveal what they can see
+            for (viewshed, unit) in (&viewshed, &units).join() {
+                if unit.owner != player.order {
+                    continue;
+                }
+                for vis in viewshed.visible_tiles.iter() {
+                    let idx = map.xy_idx(vis.x, vis.y);
+                    fog.revealed_tiles[idx] = true;
+                    fog.visible_tiles[idx] = true;
+                }
+            }
+
+            // Forgetting the forts the player can now see aren't there anymore, then
+            // remembering what enemy forts currently look like
+            let visible_tiles = &fog.visible_tiles;
+            fog.seen_forts
+                .retain(|snapshot| !visible_tiles[map.xy_idx(snapshot.pos.x, snapshot.pos.y)]);
+            for (fort, fort_pos, render) in (&forts, &pos, &renderables).join() {
+                if fort.owner != player.order && fog.visible_tiles[map.xy_idx(fort_pos.x, fort_pos.y)] {
+                    fog.seen_forts.push(FortSnapshot {
+                        pos: *fort_pos,
+                        owner: fort.owner,
+                        render: render.clone(),
+                    });
+                }
             }
         }
     }